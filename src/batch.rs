@@ -0,0 +1,165 @@
+use std::borrow::Borrow;
+use std::sync::Arc;
+use {ChildMap, InnerNode, Leaf, LeafKey, Node, Radix, Sha256Hash, Tree, TreeBuilder, ValueHashing};
+
+/// A tree taking entries without hashing, for loading many entries at once. It can't be
+/// read or proven until `finalize` hashes the nodes changed by all the inserts.
+#[must_use = "the tree is usable only after `finalize`"]
+#[derive(Debug)]
+pub struct DirtyTree<const N: usize = 32> {
+    tree: Tree<N>,
+}
+
+impl<const N: usize> DirtyTree<N> {
+    pub fn insert<K: Borrow<[u8;N]>>(&mut self, key: K, value: Vec<u8>) {
+        self.tree.insert(key.borrow(), value);
+    }
+
+    /// Compute the hashes left stale by the inserts
+    pub fn finalize(mut self) -> Tree<N> {
+        self.tree.update_hashes();
+        self.tree
+    }
+}
+
+impl<const N: usize> TreeBuilder<N> {
+    /// The configured empty tree, taking inserts without hashing
+    pub fn build_dirty(self) -> DirtyTree<N> {
+        DirtyTree {
+            tree: self.build(),
+        }
+    }
+}
+
+/// Subtree `depth` levels down holding `entries`, sorted by digits and sharing the first
+/// `depth` digits, with the inner hashes left stale
+fn build(entries: &[(Vec<u8>, Vec<u8>)], depth: usize, radix: Radix, value_hashing: ValueHashing, leaf_key: LeafKey, hash_len: usize, empty_hash: &Option<Sha256Hash>) -> Node {
+    if entries.len() == 1 {
+        let (digits, value) = &entries[0];
+        let full_key = match leaf_key {
+            LeafKey::Remaining => None,
+            LeafKey::Full => Some(digits.clone()),
+        };
+        return Node::Leaf(Leaf::keyed(digits[depth..].to_vec(), full_key, value.clone(), value_hashing));
+    }
+    let mut map = ChildMap::new();
+    let mut rest = entries;
+    while !rest.is_empty() {
+        let branch = rest[0].0[depth];
+        let split = rest.partition_point(|(digits, _)| digits[depth] == branch);
+        let (group, next) = rest.split_at(split);
+        map.insert(branch, Arc::new(build(group, depth + 1, radix, value_hashing, leaf_key, hash_len, empty_hash)));
+        rest = next;
+    }
+    Node::InnerNode(InnerNode::new(map, radix, hash_len, empty_hash.clone()))
+}
+
+impl<const N: usize> Tree<N> {
+    /// Add entries sorted by key without duplicates, building the subtrees left to right
+    /// instead of walking down from the root for every key. The tree is the same as with
+    /// `extend`, which is used if the tree is not empty. Panics like `add` on a value of
+    /// another length than the fixed one, before adding any entry.
+    pub fn insert_batch_sorted(&mut self, sorted: &[(Sha256Hash<N>, Vec<u8>)]) {
+        debug_assert!(sorted.windows(2).all(|w| w[0].0 < w[1].0), "keys not sorted or not unique");
+        for (_, value) in sorted {
+            self.assert_value_len(value);
+        }
+        if sorted.is_empty() {
+            return;
+        }
+        if self.root.is_some() {
+            self.extend(sorted.iter().cloned());
+            return;
+        }
+        let entries: Vec<_> = sorted.iter().map(|(key, value)| (self.radix.digits(&key.0), self.encode_value(value.clone()))).collect();
+        self.root = Some(build(&entries, 0, self.radix, self.value_hashing, self.leaf_key, self.hash_len, &self.empty_hash));
+        self.update_hashes();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ::*;
+
+    fn sorted_entries(len: u32) -> Vec<(Sha256Hash, Vec<u8>)> {
+        let mut entries: Vec<(Sha256Hash, Vec<u8>)> = (0..len).map(|i| (hash(&i.to_be_bytes()), i.to_be_bytes().to_vec())).collect();
+        entries.sort();
+        entries
+    }
+
+    #[test]
+    fn test_insert_batch_sorted() {
+        let entries = sorted_entries(300);
+        for radix in [Radix::Byte, Radix::Nibble] {
+            for value_hashing in [ValueHashing::Inline, ValueHashing::Digest] {
+                let builder = Tree::builder().radix(radix).value_hashing(value_hashing);
+                let mut expected = builder.build();
+                let mut tree = builder.build();
+                tree.insert_batch_sorted(&[]);
+                assert!(tree.is_empty());
+                for len in [1, 2, 300] {
+                    expected.extend(entries[..len].iter().cloned());
+                    tree = builder.build();
+                    tree.insert_batch_sorted(&entries[..len]);
+                    assert_eq!(tree.hash(), expected.hash());
+                    assert_eq!(tree.verify_hashes(), Ok(()));
+                }
+                assert_eq!(tree.to_entries(), entries);
+            }
+        }
+
+        // keys sharing all the digits but the last
+        let mut a = [0u8;32];
+        a[31] = 1;
+        let pairs = vec![(Sha256Hash([0u8;32]), vec![0]), (Sha256Hash(a), vec![1])];
+        let mut tree = Tree::default();
+        tree.insert_batch_sorted(&pairs);
+        assert_eq!(tree.hash(), Tree::from(&pairs[..]).hash());
+
+        // not empty, falls back to extend
+        let mut tree = Tree::default();
+        tree.add(hash(&[0xff]), vec![0xff]);
+        tree.insert_batch_sorted(&entries);
+        let mut expected = Tree::from(&entries[..]);
+        expected.add(hash(&[0xff]), vec![0xff]);
+        assert_eq!(tree.hash(), expected.hash());
+    }
+
+    #[test]
+    fn test_dirty_tree() {
+        let entries = sorted_entries(300);
+        for radix in [Radix::Byte, Radix::Nibble] {
+            let builder = Tree::builder().radix(radix).hash_len(16);
+            assert_eq!(builder.build_dirty().finalize().hash(), builder.build().hash());
+            let mut dirty = builder.build_dirty();
+            let mut expected = builder.build();
+            for (key, value) in entries.iter().rev() {
+                dirty.insert(key, value.clone());
+                expected.add(key, value.clone());
+            }
+            let tree = dirty.finalize();
+            assert_eq!(tree.hash(), expected.hash());
+            assert_eq!(tree.verify_hashes(), Ok(()));
+            assert_eq!(tree.to_entries(), entries);
+        }
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_insert_batch_sorted_fixed_value_len() {
+        let mut tree: Tree = Tree::with_fixed_value_len(4);
+        let mut entries = sorted_entries(10);
+        entries[5].1 = vec![1];
+        tree.insert_batch_sorted(&entries);
+    }
+
+    #[test]
+    fn fake_bench_insert_batch_sorted() {
+        let entries = sorted_entries(20000);
+        let mut extended = Tree::default();
+        extended.extend(entries.iter().cloned());
+        let mut batch = Tree::default();
+        batch.insert_batch_sorted(&entries);
+        assert_eq!(batch.hash(), extended.hash());
+    }
+}
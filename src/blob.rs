@@ -0,0 +1,98 @@
+use std::borrow::Borrow;
+use std::collections::HashMap;
+use {hash, Sha256Hash, Tree};
+
+/// Keeps values outside of the tree by their hash
+pub trait BlobStore {
+    fn get_blob(&self, hash: &Sha256Hash) -> Option<Vec<u8>>;
+    fn put_blob(&mut self, hash: Sha256Hash, blob: Vec<u8>);
+}
+
+impl BlobStore for HashMap<Sha256Hash, Vec<u8>> {
+    fn get_blob(&self, hash: &Sha256Hash) -> Option<Vec<u8>> {
+        self.get(hash).cloned()
+    }
+
+    fn put_blob(&mut self, hash: Sha256Hash, blob: Vec<u8>) {
+        self.insert(hash, blob);
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BlobError {
+    /// no blob with the hash in the store
+    Missing(Sha256Hash),
+    /// the blob in the store doesn't hash to the value of the key
+    Mismatch(Sha256Hash),
+    /// the value of the key is not the hash of a blob
+    NotAReference,
+}
+
+/// Check a blob fetched from a `BlobStore` against the value proven for its key, the hash
+/// of the blob added with `Tree::add_blob`.
+pub fn verify_blob(value: &[u8], blob: &[u8]) -> bool {
+    hash(blob).0[..] == *value
+}
+
+impl<const N: usize> Tree<N> {
+    /// Put `blob` in `blobs` and add its hash as the value of `key`, returning the hash. The
+    /// proofs of `key` authenticate the hash, the blob is checked with `verify_blob`.
+    pub fn add_blob<K: Borrow<[u8;N]>, B: BlobStore>(&mut self, blobs: &mut B, key: K, blob: Vec<u8>) -> Sha256Hash {
+        let blob_hash = hash(&blob);
+        blobs.put_blob(blob_hash.clone(), blob);
+        self.add(key, blob_hash.0.to_vec());
+        blob_hash
+    }
+
+    /// The blob added for `key` with `add_blob`, fetched from `blobs` and checked against its hash.
+    pub fn get_blob<K: Borrow<[u8;N]>, B: BlobStore>(&self, blobs: &B, key: K) -> Result<Option<Vec<u8>>, BlobError> {
+        let value = match self.get(key) {
+            None => return Ok(None),
+            Some(value) => value,
+        };
+        if value.len() != 32 {
+            return Err(BlobError::NotAReference);
+        }
+        let mut blob_hash = [0u8;32];
+        blob_hash.copy_from_slice(&value);
+        let blob_hash = Sha256Hash(blob_hash);
+        match blobs.get_blob(&blob_hash) {
+            None => Err(BlobError::Missing(blob_hash)),
+            Some(ref blob) if !verify_blob(&value, blob) => Err(BlobError::Mismatch(blob_hash)),
+            Some(blob) => Ok(Some(blob)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ::*;
+    use std::collections::HashMap;
+
+    #[test]
+    fn test_blobs() {
+        let mut blobs = HashMap::new();
+        let mut tree = Tree::default();
+        let key = hash(&[0]);
+        let blob = vec![0xaau8; 100_000];
+        let blob_hash = tree.add_blob(&mut blobs, &key, blob.clone());
+        tree.add_blob(&mut blobs, hash(&[1]), vec![1]);
+        tree.add(hash(&[2]), vec![2]);
+        assert_eq!(tree.get(&key), Some(blob_hash.0.to_vec()));
+        assert_eq!(tree.get_blob(&blobs, &key), Ok(Some(blob.clone())));
+        assert_eq!(tree.get_blob(&blobs, hash(&[3])), Ok(None));
+        assert_eq!(tree.get_blob(&blobs, hash(&[2])), Err(BlobError::NotAReference));
+
+        // the proof carries the hash of the blob, not the blob
+        let proof = tree.prove(&key);
+        assert!(proof.to_bytes().len() < 1000);
+        let value = verify_proof(&tree.options(), &tree.hash(), &key, &proof).unwrap().unwrap();
+        assert!(verify_blob(&value, &blob));
+        assert!(!verify_blob(&value, &blob[1..]));
+
+        blobs.put_blob(blob_hash.clone(), vec![0xbb; 100_000]);
+        assert_eq!(tree.get_blob(&blobs, &key), Err(BlobError::Mismatch(blob_hash.clone())));
+        blobs.remove(&blob_hash);
+        assert_eq!(tree.get_blob(&blobs, &key), Err(BlobError::Missing(blob_hash)));
+    }
+}
@@ -0,0 +1,105 @@
+use std::borrow::Borrow;
+use std::collections::VecDeque;
+use {Sha256Hash, Tree};
+
+/// A tree holding at most `capacity` keys, adding a new key beyond it evicts the key added
+/// first. Overwriting a key keeps its place in the eviction order.
+#[derive(Debug, Clone)]
+pub struct BoundedTree<const N: usize = 32> {
+    tree: Tree<N>,
+    /// keys in insertion order, the oldest first
+    order: VecDeque<Sha256Hash<N>>,
+    capacity: usize,
+}
+
+impl<const N: usize> BoundedTree<N> {
+    pub fn with_capacity(capacity: usize) -> Self {
+        assert!(capacity > 0, "a bounded tree holds at least one key");
+        BoundedTree {
+            tree: Tree::default(),
+            order: VecDeque::with_capacity(capacity),
+            capacity,
+        }
+    }
+
+    /// Add or overwrite `key`, returning the key evicted to make room for it
+    pub fn add<K: Borrow<[u8;N]>>(&mut self, key: K, value: Vec<u8>) -> Option<Sha256Hash<N>> {
+        let key = Sha256Hash(*key.borrow());
+        if self.tree.get(&key).is_some() {
+            self.tree.add(&key, value);
+            return None;
+        }
+        let evicted = if self.order.len() == self.capacity {
+            let oldest = self.order.pop_front().expect("capacity is not zero");
+            // removed as in `Tree::apply_ops`, the `add` below updates the hashes
+            let digits = self.tree.radix.digits(&oldest.0);
+            if self.tree.root.as_mut().is_some_and(|root| root.remove_prefix(&digits)) {
+                self.tree.root = None;
+            }
+            Some(oldest)
+        } else {
+            None
+        };
+        self.tree.add(&key, value);
+        self.order.push_back(key);
+        evicted
+    }
+
+    pub fn get<K: Borrow<[u8;N]>>(&self, key: K) -> Option<Vec<u8>> {
+        self.tree.get(key)
+    }
+
+    pub fn hash(&self) -> Sha256Hash {
+        self.tree.hash()
+    }
+
+    pub fn len(&self) -> usize {
+        self.order.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.order.is_empty()
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// The tree of the keys currently held, to prove them
+    pub fn tree(&self) -> &Tree<N> {
+        &self.tree
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ::*;
+
+    fn key(i: u32) -> Sha256Hash {
+        hash(&i.to_be_bytes())
+    }
+
+    #[test]
+    fn test_bounded_tree() {
+        let n = 10;
+        let mut bounded = BoundedTree::with_capacity(n);
+        for i in 0..n as u32 {
+            assert_eq!(bounded.add(key(i), vec![i as u8]), None);
+        }
+        assert_eq!(bounded.len(), n);
+        assert_eq!(bounded.add(key(0), vec![0xff]), None);
+        assert_eq!(bounded.add(key(n as u32), vec![n as u8]), Some(key(0)));
+        assert_eq!(bounded.len(), n);
+        assert_eq!(bounded.get(key(0)), None);
+        assert_eq!(bounded.get(key(n as u32)), Some(vec![n as u8]));
+
+        let expected: Tree = (1..=n as u32).map(|i| (key(i), vec![i as u8])).collect();
+        assert_eq!(bounded.hash(), expected.hash());
+        for i in n as u32 + 1..100 {
+            assert_eq!(bounded.add(key(i), vec![i as u8]), Some(key(i - n as u32)));
+            assert_eq!(bounded.len(), n);
+        }
+        let expected: Tree = (90..100u32).map(|i| (key(i), vec![i as u8])).collect();
+        assert_eq!(bounded.hash(), expected.hash());
+    }
+}
@@ -0,0 +1,85 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::Mutex;
+use {Proof, Sha256Hash};
+
+/// Proofs generated since the last mutation of the tree.
+///
+/// Every proof commits to the root hash and every path goes through the root, so any
+/// mutation touches the path of every key and the whole cache is dropped.
+#[derive(Default)]
+pub(crate) struct ProofCache<const N: usize> {
+    proofs: Mutex<HashMap<Sha256Hash<N>, Proof>>,
+    #[cfg(test)]
+    pub(crate) generated: Mutex<usize>,
+}
+
+impl<const N: usize> ProofCache<N> {
+    pub(crate) fn get_or_insert_with<F: FnOnce() -> Proof>(&self, key: &Sha256Hash<N>, f: F) -> Proof {
+        if let Some(proof) = self.proofs.lock().unwrap().get(key) {
+            return proof.clone();
+        }
+        #[cfg(test)]
+        {
+            *self.generated.lock().unwrap() += 1;
+        }
+        // built without the lock, threads racing on the same key build the same proof
+        let proof = f();
+        self.proofs.lock().unwrap().insert(key.clone(), proof.clone());
+        proof
+    }
+
+    pub(crate) fn clear(&mut self) {
+        self.proofs.get_mut().unwrap().clear();
+    }
+}
+
+/// The proofs are still valid for the cloned tree
+impl<const N: usize> Clone for ProofCache<N> {
+    fn clone(&self) -> Self {
+        ProofCache {
+            proofs: Mutex::new(self.proofs.lock().unwrap().clone()),
+            #[cfg(test)]
+            generated: Mutex::new(0),
+        }
+    }
+}
+
+impl<const N: usize> fmt::Debug for ProofCache<N> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "ProofCache({} proofs)", self.proofs.lock().unwrap().len())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ::*;
+
+    #[test]
+    fn test_proof_cache() {
+        let mut tree = Tree::default();
+        for i in 0u8..10 {
+            tree.add(Sha256Hash([i;32]), vec![i]);
+        }
+        let generated = |tree: &Tree| *tree.proof_cache.generated.lock().unwrap();
+        let key = Sha256Hash([3u8;32]);
+        let proof = tree.prove(&key);
+        assert_eq!(generated(&tree), 1);
+        assert_eq!(tree.prove(&key), proof);
+        assert_eq!(generated(&tree), 1);
+
+        let mut other = [3u8;32];
+        other[31] = 0;
+        tree.add(Sha256Hash(other), vec![0xff]);
+        let proof = tree.prove(&key);
+        assert_eq!(generated(&tree), 2);
+        assert_eq!(verify_proof(&tree.options(), &tree.hash(), &key, &proof), Ok(Some(vec![3])));
+
+        let token = tree.checkpoint();
+        tree.add(Sha256Hash([20u8;32]), vec![20]);
+        tree.prove(&key);
+        tree.rollback(token);
+        assert_eq!(verify_proof(&tree.options(), &tree.hash(), &key, &tree.prove(&key)), Ok(Some(vec![3])));
+        assert_eq!(generated(&tree), 4);
+    }
+}
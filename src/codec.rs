@@ -0,0 +1,127 @@
+use std::fmt;
+use std::sync::Arc;
+use Tree;
+
+/// Transforms the values stored in a tree, for example compressing them. Leaves hold and
+/// hash the encoded value, so proofs, `for_each_leaf` and `leaf_metadata` deal with the
+/// encoded bytes while `get` and the entries accessors return the decoded ones.
+pub trait ValueCodec: Send + Sync {
+    fn encode(&self, value: &[u8]) -> Vec<u8>;
+
+    /// Inverse of `encode`
+    fn decode(&self, encoded: &[u8]) -> Vec<u8>;
+}
+
+/// The codec shared by a tree and its clones
+#[derive(Clone)]
+pub(crate) struct Codec(Arc<dyn ValueCodec>);
+
+impl fmt::Debug for Codec {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Codec")
+    }
+}
+
+/// Codecs are the same if shared by clones of a tree
+impl PartialEq for Codec {
+    fn eq(&self, other: &Codec) -> bool {
+        Arc::ptr_eq(&self.0, &other.0)
+    }
+}
+
+impl Codec {
+    pub(crate) fn encode(&self, value: &[u8]) -> Vec<u8> {
+        self.0.encode(value)
+    }
+
+    pub(crate) fn decode(&self, encoded: &[u8]) -> Vec<u8> {
+        self.0.decode(encoded)
+    }
+}
+
+impl<const N: usize> Tree<N> {
+    /// An empty tree storing the values encoded by `codec`
+    pub fn with_value_codec<C: ValueCodec + 'static>(codec: C) -> Self {
+        let mut tree = Tree::new();
+        tree.value_codec = Some(Codec(Arc::new(codec)));
+        tree
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ::*;
+
+    /// Run length encoding, pairs of count and byte
+    struct RunLength;
+
+    impl ValueCodec for RunLength {
+        fn encode(&self, value: &[u8]) -> Vec<u8> {
+            let mut encoded = Vec::new();
+            for &b in value {
+                match encoded.len() {
+                    len if len >= 2 && encoded[len - 1] == b && encoded[len - 2] < 0xff => encoded[len - 2] += 1,
+                    _ => encoded.extend(&[1, b]),
+                }
+            }
+            encoded
+        }
+
+        fn decode(&self, encoded: &[u8]) -> Vec<u8> {
+            encoded.chunks(2).flat_map(|pair| vec![pair[1]; usize::from(pair[0])]).collect()
+        }
+    }
+
+    #[test]
+    fn test_value_codec() {
+        let mut tree = Tree::with_value_codec(RunLength);
+        let mut plain = Tree::default();
+        let key = hash(&[0]);
+        let value = vec![0xaau8; 10000];
+        tree.add(&key, value.clone());
+        plain.add(&key, value.clone());
+        tree.add(hash(&[1]), vec![1, 2, 3]);
+        plain.add(hash(&[1]), vec![1, 2, 3]);
+
+        assert_eq!(tree.get(&key), Some(value.clone()));
+        assert_eq!(tree.verified_get(&key), Ok(Some(value.clone())));
+        assert_eq!(tree.to_entries(), plain.to_entries());
+        let stored = |tree: &Tree| {
+            let mut len = 0;
+            tree.for_each_leaf(|_, value| len += value.len());
+            len
+        };
+        assert!(stored(&tree) < stored(&plain) / 10);
+        assert!(tree.leaf_metadata(&key).unwrap().value_len < 100);
+        assert_ne!(tree.hash(), plain.hash());
+
+        // proofs carry the encoded value
+        let proof = tree.prove(&key);
+        assert_eq!(verify_proof(&tree.options(), &tree.hash(), &key, &proof), Ok(Some(RunLength.encode(&value))));
+
+        // the codec goes with clones and split trees
+        let mut clone = tree.clone();
+        let split = clone.split_off(&key.0[..1]);
+        assert_eq!(split.get(&key), Some(value));
+        clone.map_values(|value| value.iter().map(|b| b + 1).collect());
+        assert_eq!(clone.get(hash(&[1])), Some(vec![2, 3, 4]));
+    }
+
+    #[test]
+    fn test_graft_value_codec() {
+        let key = Sha256Hash([0xaau8;32]);
+        let mut tree = Tree::with_value_codec(RunLength);
+        let mut subtree = tree.empty_like();
+        subtree.add(&key, vec![0xaa; 100]);
+        let mut other = Tree::with_value_codec(RunLength);
+        other.add(&key, vec![0xaa; 100]);
+        let mut plain = Tree::default();
+        plain.add(&key, vec![0xaa; 100]);
+
+        assert_eq!(tree.clone().graft(0xaa, other), Err(GraftError::ValueCodecMismatch));
+        assert_eq!(Tree::default().graft(0xaa, subtree.clone()), Err(GraftError::ValueCodecMismatch));
+        assert_eq!(tree.clone().graft(0xaa, plain), Err(GraftError::ValueCodecMismatch));
+        tree.graft(0xaa, subtree).unwrap();
+        assert_eq!(tree.get(&key), Some(vec![0xaa; 100]));
+    }
+}
@@ -0,0 +1,175 @@
+use integer_encoding::VarInt;
+use deserialize::{read_var, take};
+use proof::write_radix;
+use {DeserializeError, LeafKey, Proof, Radix, Sha256Hash, ValueHashing, HASH_LEN};
+
+/// Compact encoding of a proof, in the layout of the sparse Merkle proofs listing the
+/// sibling hashes bottom up:
+///
+/// - the radix byte, the length of truncated hashes and the hash of the empty slots, as in
+///   `Proof::to_bytes`
+/// - the varint number of levels
+/// - the directions bitstring, for every level from the deepest to the root one bit per
+///   branch digit set if the branch holds a sibling, most significant bit first
+/// - the sibling hashes in post-order, the same order as the bits, truncated if the tree
+///   truncates them
+/// - 0x00 if the path ends in an empty slot, otherwise the leaf type, 0x02 or 0x03 for a
+///   leaf with the value hash, 0x04 or 0x05 with `LeafKey::Full`, then the length prefixed
+///   remaining key and value
+impl Proof {
+    pub fn to_compact(&self) -> Vec<u8> {
+        let mut result = Vec::new();
+        write_radix(&mut result, self.radix, self.hash_len, self.empty_hash.as_ref()).expect("writing to a Vec doesn't fail");
+        result.extend(self.levels.len().encode_var_vec());
+        for siblings in self.levels.iter().rev() {
+            let mut bits = vec![0u8; self.radix.width() / 8];
+            for (branch, _) in siblings {
+                bits[usize::from(*branch / 8)] |= 0x80 >> (branch % 8);
+            }
+            result.extend(bits);
+        }
+        for siblings in self.levels.iter().rev() {
+            for (_, hash) in siblings {
+                result.extend(&hash.0[..self.hash_len]);
+            }
+        }
+        match self.leaf {
+            None => result.push(0x00),
+            Some((ref remaining_key, ref value, value_hashing, leaf_key)) => {
+                let leaf_type = match value_hashing {
+                    ValueHashing::Inline => 0x02,
+                    ValueHashing::Digest => 0x03,
+                };
+                result.push(match leaf_key {
+                    LeafKey::Remaining => leaf_type,
+                    LeafKey::Full => leaf_type + 2,
+                });
+                result.extend(remaining_key.len().encode_var_vec());
+                result.extend(remaining_key);
+                result.extend(value.len().encode_var_vec());
+                result.extend(value);
+            },
+        }
+        result
+    }
+
+    /// Inverse of `to_compact`, the proof and its options are checked only by `verify_proof`
+    pub fn from_compact(bytes: &[u8]) -> Result<Proof, DeserializeError> {
+        let (flags, rest) = take(bytes, 1)?;
+        let radix = match flags[0] & 0x3f {
+            0x00 => Radix::Byte,
+            0x01 => Radix::Nibble,
+            _ => return Err(DeserializeError::UnknownNodeType(flags[0])),
+        };
+        let (hash_len, rest) = match flags[0] & 0x80 {
+            0x00 => (HASH_LEN, rest),
+            _ => {
+                let (len, rest) = take(rest, 1)?;
+                let hash_len = usize::from(len[0]);
+                if hash_len == 0 || hash_len >= HASH_LEN {
+                    return Err(DeserializeError::InvalidChild(len[0]));
+                }
+                (hash_len, rest)
+            },
+        };
+        let (empty_hash, rest) = match flags[0] & 0x40 {
+            0x00 => (None, rest),
+            _ => {
+                let (hash, rest) = take(rest, hash_len)?;
+                let mut bytes = [0u8;32];
+                bytes[..hash_len].copy_from_slice(hash);
+                (Some(Sha256Hash(bytes)), rest)
+            },
+        };
+        let (depth, rest) = read_var(rest)?;
+        let level_len = radix.width() / 8;
+        let (bits, mut rest) = take(rest, depth.checked_mul(level_len).ok_or(DeserializeError::UnexpectedEnd)?)?;
+        let mut levels = Vec::with_capacity(depth);
+        for level_bits in bits.chunks(level_len) {
+            let mut siblings = Vec::new();
+            for branch in radix.all_digits() {
+                if level_bits[usize::from(branch / 8)] & (0x80 >> (branch % 8)) != 0 {
+                    let (hash, next) = take(rest, hash_len)?;
+                    let mut bytes = [0u8;32];
+                    bytes[..hash_len].copy_from_slice(hash);
+                    siblings.push((branch, Sha256Hash(bytes)));
+                    rest = next;
+                }
+            }
+            levels.push(siblings);
+        }
+        levels.reverse();
+        let (leaf_type, rest) = take(rest, 1)?;
+        let (value_hashing, leaf_key) = match leaf_type[0] {
+            0x00 if rest.is_empty() => return Ok(Proof { radix, hash_len, empty_hash, levels, leaf: None }),
+            0x00 => return Err(DeserializeError::TrailingBytes),
+            0x02 => (ValueHashing::Inline, LeafKey::Remaining),
+            0x03 => (ValueHashing::Digest, LeafKey::Remaining),
+            0x04 => (ValueHashing::Inline, LeafKey::Full),
+            0x05 => (ValueHashing::Digest, LeafKey::Full),
+            other => return Err(DeserializeError::UnknownNodeType(other)),
+        };
+        let (len, rest) = read_var(rest)?;
+        let (remaining_key, rest) = take(rest, len)?;
+        let (len, rest) = read_var(rest)?;
+        let (value, rest) = take(rest, len)?;
+        if !rest.is_empty() {
+            return Err(DeserializeError::TrailingBytes);
+        }
+        Ok(Proof { radix, hash_len, empty_hash, levels, leaf: Some((remaining_key.to_vec(), value.to_vec(), value_hashing, leaf_key)) })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ::*;
+
+    fn trees() -> Vec<Tree> {
+        let mut trees = Vec::new();
+        for radix in [Radix::Byte, Radix::Nibble] {
+            for value_hashing in [ValueHashing::Inline, ValueHashing::Digest] {
+                for hash_len in [32, 16] {
+                    let builder = Tree::builder().radix(radix).value_hashing(value_hashing).hash_len(hash_len);
+                    for mut tree in [builder.build(), builder.empty_hash(hash(&[0xee])).build()] {
+                        trees.push(tree.clone());
+                        for i in 0u32..100 {
+                            tree.add(hash(&i.to_be_bytes()), i.to_be_bytes().to_vec());
+                        }
+                        trees.push(tree);
+                    }
+                }
+            }
+        }
+        trees
+    }
+
+    #[test]
+    fn test_compact_roundtrip() {
+        for tree in trees() {
+            for i in 0u32..150 {
+                let proof = tree.prove(hash(&i.to_be_bytes()));
+                let compact = proof.to_compact();
+                assert_eq!(Proof::from_compact(&compact), Ok(proof.clone()));
+                assert!(Proof::from_compact(&compact[..compact.len() - 1]).is_err());
+                let mut trailing = compact.clone();
+                trailing.push(0x00);
+                assert_eq!(Proof::from_compact(&trailing), Err(DeserializeError::TrailingBytes));
+            }
+        }
+        assert_eq!(Proof::from_compact(&[0x02]), Err(DeserializeError::UnknownNodeType(0x02)));
+    }
+
+    #[test]
+    fn test_compact_verifies() {
+        for tree in trees() {
+            let root = tree.hash();
+            for i in 0u32..150 {
+                let key = hash(&i.to_be_bytes());
+                let proof = tree.prove(&key);
+                let decoded = Proof::from_compact(&proof.to_compact()).unwrap();
+                assert_eq!(verify_proof(&tree.options(), &root, &key, &decoded), verify_proof(&tree.options(), &root, &key, &proof));
+                assert_eq!(verify_proof(&tree.options(), &root, &hash(&[1]), &decoded), verify_proof(&tree.options(), &root, &hash(&[1]), &proof));
+            }
+        }
+    }
+}
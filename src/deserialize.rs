@@ -0,0 +1,271 @@
+use std::sync::Arc;
+use integer_encoding::VarInt;
+use {hash, ChildMap, InnerNode, Leaf, LeafKey, Node, PartialTree, Radix, Sha256Hash, ValueHashing, HASH_LEN};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DeserializeError {
+    UnexpectedEnd,
+    InvalidVarInt,
+    UnknownNodeType(u8),
+    InvalidChild(u8),
+    TrailingBytes,
+}
+
+/// Inverse of `Serializable`, accepting only the canonical encoding so that
+/// the deserialized value hashes like the bytes it was read from.
+pub trait Deserializable: Sized {
+    fn deserialize(bytes: &[u8]) -> Result<Self, DeserializeError>;
+}
+
+pub(crate) fn read_var(bytes: &[u8]) -> Result<(usize, &[u8]), DeserializeError> {
+    let mut value = 0u64;
+    for (i, b) in bytes.iter().enumerate() {
+        if i == 9 {
+            return Err(DeserializeError::InvalidVarInt);
+        }
+        value |= u64::from(b & 0x7f) << (7 * i);
+        if b & 0x80 == 0 {
+            // non minimal encodings would serialize differently
+            if value.required_space() != i + 1 {
+                return Err(DeserializeError::InvalidVarInt);
+            }
+            return Ok((value as usize, &bytes[i + 1..]));
+        }
+    }
+    Err(DeserializeError::UnexpectedEnd)
+}
+
+pub(crate) fn take(bytes: &[u8], len: usize) -> Result<(&[u8], &[u8]), DeserializeError> {
+    if len > bytes.len() {
+        return Err(DeserializeError::UnexpectedEnd);
+    }
+    Ok(bytes.split_at(len))
+}
+
+/// Read the type byte and the length prefixed content of a node, which must span all of `bytes`
+fn read_node(bytes: &[u8]) -> Result<(u8, &[u8]), DeserializeError> {
+    let (node_type, rest) = take(bytes, 1)?;
+    let (len, rest) = read_var(rest)?;
+    let (inside, rest) = take(rest, len)?;
+    if !rest.is_empty() {
+        return Err(DeserializeError::TrailingBytes);
+    }
+    Ok((node_type[0], inside))
+}
+
+/// A leaf with `LeafKey::Full` is read as the root of a tree, see `Node::at_depth`
+impl Deserializable for Leaf {
+    fn deserialize(bytes: &[u8]) -> Result<Self, DeserializeError> {
+        let (node_type, inside) = read_node(bytes)?;
+        let leaf_key = match node_type {
+            0x02 => LeafKey::Remaining,
+            0x04 => LeafKey::Full,
+            _ => return Err(DeserializeError::UnknownNodeType(node_type)),
+        };
+        let (len, rest) = read_var(inside)?;
+        let (key, rest) = take(rest, len)?;
+        let (len, rest) = read_var(rest)?;
+        let (value, rest) = take(rest, len)?;
+        if !rest.is_empty() {
+            return Err(DeserializeError::TrailingBytes);
+        }
+        Ok(match leaf_key {
+            LeafKey::Remaining => Leaf::new(key.to_vec(), value.to_vec()),
+            LeafKey::Full => Leaf::keyed(key.to_vec(), Some(key.to_vec()), value.to_vec(), ValueHashing::Inline),
+        })
+    }
+}
+
+/// Read a leaf with type 0x03 or 0x05, returning its key, the hash of its value and whether
+/// the key is the full key
+pub(crate) fn read_hashed_leaf(bytes: &[u8]) -> Result<(Vec<u8>, Sha256Hash, LeafKey), DeserializeError> {
+    let (node_type, inside) = read_node(bytes)?;
+    let leaf_key = match node_type {
+        0x03 => LeafKey::Remaining,
+        0x05 => LeafKey::Full,
+        _ => return Err(DeserializeError::UnknownNodeType(node_type)),
+    };
+    let (len, rest) = read_var(inside)?;
+    let (key, rest) = take(rest, len)?;
+    let (value_hash, rest) = take(rest, 32)?;
+    if !rest.is_empty() {
+        return Err(DeserializeError::TrailingBytes);
+    }
+    let mut bytes = [0u8;32];
+    bytes.copy_from_slice(value_hash);
+    Ok((key.to_vec(), Sha256Hash(bytes), leaf_key))
+}
+
+/// The leaf read with `read_hashed_leaf`, once its value is known
+pub(crate) fn hashed_leaf(key: Vec<u8>, value: Vec<u8>, leaf_key: LeafKey) -> Leaf {
+    let full_key = match leaf_key {
+        LeafKey::Remaining => None,
+        LeafKey::Full => Some(key.clone()),
+    };
+    Leaf::keyed(key, full_key, value, ValueHashing::Digest)
+}
+
+impl Node {
+    /// The deserialized node as a child `depth` levels down. A leaf with `LeafKey::Full`
+    /// keeps as remaining key the digits of its key below the inner nodes, `None` if its
+    /// key is shorter than the depth.
+    pub(crate) fn at_depth(self, depth: usize) -> Option<Node> {
+        match self {
+            Node::Leaf(leaf) => match leaf.full_key {
+                Some(ref full_key) if full_key.len() < depth => None,
+                Some(ref full_key) => {
+                    let remaining_key = full_key[depth..].to_vec();
+                    Some(Node::Leaf(leaf.moved(remaining_key)))
+                },
+                None => Some(Node::Leaf(leaf)),
+            },
+            node => Some(node),
+        }
+    }
+}
+
+/// Children are known only by their hash, so they are returned as pruned nodes
+impl Deserializable for InnerNode {
+    fn deserialize(bytes: &[u8]) -> Result<Self, DeserializeError> {
+        let (node_type, mut inside) = read_node(bytes)?;
+        if node_type != 0x01 {
+            return Err(DeserializeError::UnknownNodeType(node_type));
+        }
+        let mut map = ChildMap::new();
+        let mut width = 0;
+        let mut hash_len = None;
+        for i in 0u8..=255 {
+            if inside.is_empty() {
+                break;
+            }
+            width += 1;
+            let (len, rest) = take(inside, 1)?;
+            let len = usize::from(len[0]);
+            inside = match len {
+                0 => rest,
+                // every child hash is truncated to the same length
+                1..=HASH_LEN if *hash_len.get_or_insert(len) == len => {
+                    let (hash, rest) = take(rest, len)?;
+                    let mut bytes = [0u8;32];
+                    bytes[..len].copy_from_slice(hash);
+                    map.insert(i, Arc::new(Node::Pruned(Sha256Hash(bytes))));
+                    rest
+                },
+                _ => return Err(DeserializeError::InvalidChild(i)),
+            };
+        }
+        if !inside.is_empty() {
+            return Err(DeserializeError::TrailingBytes);
+        }
+        let radix = match width {
+            16 => Radix::Nibble,
+            256 => Radix::Byte,
+            _ => return Err(DeserializeError::UnexpectedEnd),
+        };
+        let mut inner = InnerNode::new(map, radix, hash_len.unwrap_or(HASH_LEN), None);
+        inner.update();
+        Ok(inner)
+    }
+}
+
+/// A leaf with type 0x03 or 0x05 doesn't contain its value, it's returned pruned
+impl Deserializable for Node {
+    fn deserialize(bytes: &[u8]) -> Result<Self, DeserializeError> {
+        match bytes.first() {
+            None => Err(DeserializeError::UnexpectedEnd),
+            Some(0x01) => Ok(Node::InnerNode(InnerNode::deserialize(bytes)?)),
+            Some(0x02) | Some(0x04) => Ok(Node::Leaf(Leaf::deserialize(bytes)?)),
+            Some(0x03) | Some(0x05) => {
+                read_hashed_leaf(bytes)?;
+                Ok(Node::Pruned(hash(bytes)))
+            },
+            Some(node_type) => Err(DeserializeError::UnknownNodeType(*node_type)),
+        }
+    }
+}
+
+/// Reads the output of `Tree::serialize`, the root children are pruned since
+/// the serialization commits to them only by hash.
+impl<const N: usize> Deserializable for PartialTree<N> {
+    fn deserialize(bytes: &[u8]) -> Result<Self, DeserializeError> {
+        if bytes == [0x00] {
+            return Ok(PartialTree::default());
+        }
+        Ok(PartialTree::from_root(Node::deserialize(bytes)?))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ::*;
+    use rand::{Rng, RngCore, SeedableRng};
+    use rand::prng::XorShiftRng;
+
+    fn corpus() -> Vec<Vec<u8>> {
+        let mut result = Vec::new();
+        let mut tree = Tree::default();
+        result.push(tree.serialize());
+        for i in 0u8..4 {
+            tree.add(Sha256Hash([i;32]), vec![i; i as usize]);
+            result.push(tree.serialize());
+        }
+        result.push(Leaf::new(vec![], vec![]).serialize());
+        result.push(Leaf::with_value_hashing(vec![1], vec![2], ValueHashing::Digest).serialize());
+        result
+    }
+
+    #[test]
+    fn test_deserialize_roundtrip() {
+        let mut tree = Tree::default();
+        for i in 0u8..4 {
+            let partial: PartialTree = PartialTree::deserialize(&tree.serialize()).unwrap();
+            assert_eq!(partial.hash(), tree.hash());
+            tree.add(Sha256Hash([i;32]), vec![i]);
+        }
+        let partial = PartialTree::deserialize(&tree.serialize()).unwrap();
+        assert_eq!(partial.get(&Sha256Hash([0u8;32])), Err(Pruned(Leaf::new(vec![0u8;31], vec![0]).my_hash())));
+    }
+
+    #[test]
+    fn test_deserialize_errors() {
+        assert_eq!(PartialTree::<32>::deserialize(&[]).unwrap_err(), DeserializeError::UnexpectedEnd);
+        assert_eq!(PartialTree::<32>::deserialize(&[0x06]).unwrap_err(), DeserializeError::UnknownNodeType(0x06));
+        assert_eq!(PartialTree::<32>::deserialize(&[0x03]).unwrap_err(), DeserializeError::UnexpectedEnd);
+        assert_eq!(Leaf::deserialize(&[0x02, 0x80, 0x00]).unwrap_err(), DeserializeError::InvalidVarInt);
+        assert_eq!(Leaf::deserialize(&[0x02, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0x01]).unwrap_err(), DeserializeError::InvalidVarInt);
+        assert_eq!(Leaf::deserialize(&[0x02,0x04,0x01,0x01,0x01,0x02,0x00]).unwrap_err(), DeserializeError::TrailingBytes);
+        assert_eq!(Leaf::deserialize(&[0x02,0x04,0x01,0x01,0x02,0x02]).unwrap_err(), DeserializeError::UnexpectedEnd);
+    }
+
+    #[test]
+    fn fuzz_deserialize() {
+        let mut rng = XorShiftRng::from_seed([42u8;16]);
+        let corpus = corpus();
+        for _ in 0..20000 {
+            let mut bytes = corpus[rng.gen_range(0, corpus.len())].clone();
+            match rng.gen_range(0, 4) {
+                0 => {
+                    let len = rng.gen_range(0, bytes.len() + 1);
+                    bytes.truncate(len);
+                },
+                1 => {
+                    let i = rng.gen_range(0, bytes.len());
+                    bytes[i] = rng.gen();
+                },
+                2 => {
+                    let i = rng.gen_range(0, bytes.len() + 1);
+                    bytes.insert(i, rng.gen());
+                },
+                _ => {
+                    bytes = vec![0u8; rng.gen_range(0, 64)];
+                    rng.fill_bytes(&mut bytes);
+                },
+            }
+            if let Ok(partial) = PartialTree::<32>::deserialize(&bytes) {
+                if bytes != [0x00] {
+                    assert_eq!(partial.hash(), hash(&bytes));
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,210 @@
+use {Node, PartialTree, Sha256Hash, Tree, TreeBuilder};
+
+/// Change of the value of a key between two versions of a tree
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DiffOp {
+    /// the key is added or its value replaced
+    Insert(Vec<u8>),
+    Remove,
+}
+
+/// Proves the changes taking a tree from an old root to a new one, holding the old tree
+/// pruned to the paths of the changed keys, see `Tree::prove_diff`.
+#[derive(Debug, Clone)]
+pub struct DiffProof<const N: usize = 32> {
+    old: PartialTree<N>,
+    changes: Vec<(Sha256Hash<N>, DiffOp)>,
+}
+
+impl<const N: usize> DiffProof<N> {
+    /// The changed keys in key order, with their change
+    pub fn changes(&self) -> &[(Sha256Hash<N>, DiffOp)] {
+        &self.changes
+    }
+}
+
+impl Node {
+    /// Key digits of the first leaf under the node, reached through the `path` digits
+    fn first_leaf_digits(&self, path: &[u8]) -> Vec<u8> {
+        let mut digits = path.to_vec();
+        let mut node = self;
+        loop {
+            match node {
+                Node::InnerNode(inner) => {
+                    let branch = inner.branches()[0];
+                    digits.push(branch);
+                    node = &inner.map[&branch];
+                },
+                Node::Leaf(leaf) => {
+                    digits.extend(&leaf.remaining_key);
+                    return digits;
+                },
+                Node::Pruned(_) => unreachable!("the tree is not pruned"),
+            }
+        }
+    }
+
+    /// Digits of a key under every child of the inner nodes that removing `removed` may leave
+    /// with a single child, which the verifier needs to tell whether it collapses
+    fn siblings_of_removed(&self, removed: &[&[u8]], path: &mut Vec<u8>, keys: &mut Vec<Vec<u8>>) {
+        if let Node::InnerNode(inner) = self {
+            let keep = inner.map.len() <= removed.len() + 1;
+            for branch in inner.branches() {
+                let rest: Vec<&[u8]> = removed.iter().filter(|key| key[0] == branch).map(|key| &key[1..]).collect();
+                path.push(branch);
+                let child = &inner.map[&branch];
+                if !rest.is_empty() {
+                    child.siblings_of_removed(&rest, path, keys);
+                } else if keep {
+                    keys.push(child.first_leaf_digits(path));
+                }
+                path.pop();
+            }
+        }
+    }
+}
+
+impl<const N: usize> Tree<N> {
+    /// Prove the changes taking `old` to this tree, see `verify_diff`. The trees must have the
+    /// same options.
+    pub fn prove_diff(&self, old: &Tree<N>) -> DiffProof<N> {
+        let (mut old_entries, mut new_entries) = (old.to_entries().into_iter().peekable(), self.to_entries().into_iter().peekable());
+        let mut changes = Vec::new();
+        loop {
+            let change = match (old_entries.peek(), new_entries.peek()) {
+                (None, None) => break,
+                (Some(before), Some(after)) if before.0 == after.0 => {
+                    let (before, after) = (old_entries.next().unwrap(), new_entries.next().unwrap());
+                    if before.1 == after.1 {
+                        continue;
+                    }
+                    (after.0, DiffOp::Insert(after.1))
+                },
+                (Some(before), Some(after)) if before.0 > after.0 => {
+                    let (key, value) = new_entries.next().unwrap();
+                    (key, DiffOp::Insert(value))
+                },
+                (Some(_), _) => (old_entries.next().unwrap().0, DiffOp::Remove),
+                (None, Some(_)) => {
+                    let (key, value) = new_entries.next().unwrap();
+                    (key, DiffOp::Insert(value))
+                },
+            };
+            changes.push(change);
+        }
+
+        let mut keys: Vec<Sha256Hash<N>> = changes.iter().map(|(key, _)| key.clone()).collect();
+        if let Some(ref root) = old.root {
+            let removed: Vec<Vec<u8>> = changes.iter().filter(|(_, op)| *op == DiffOp::Remove).map(|(key, _)| old.radix.digits(&key.0)).collect();
+            let removed: Vec<&[u8]> = removed.iter().map(|key| &key[..]).collect();
+            let mut siblings = Vec::new();
+            root.siblings_of_removed(&removed, &mut Vec::new(), &mut siblings);
+            keys.extend(siblings.iter().map(|digits| old.radix.key(digits)));
+        }
+        DiffProof {
+            old: old.prune_to_keys(&keys),
+            changes,
+        }
+    }
+}
+
+/// Check that applying the changes of `proof` to the tree built with `options` with
+/// `old_root` gives `new_root`, returning them.
+pub fn verify_diff<const N: usize>(options: &TreeBuilder<N>, proof: &DiffProof<N>, old_root: &Sha256Hash, new_root: &Sha256Hash) -> Option<Vec<(Sha256Hash<N>, DiffOp)>> {
+    let mut tree = proof.old.clone();
+    if !tree.take_options(options) || tree.verify_hashes().is_err() || tree.hash() != *old_root {
+        return None;
+    }
+    for (key, op) in &proof.changes {
+        match op {
+            DiffOp::Insert(value) => tree.add(key, value.clone()).ok()?,
+            DiffOp::Remove if tree.remove(key) == Ok(true) => (),
+            DiffOp::Remove => return None,
+        }
+    }
+    if tree.hash() == *new_root {
+        Some(proof.changes.clone())
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ::*;
+
+    #[test]
+    fn test_prove_diff() {
+        let key = |i: u32| hash(&i.to_be_bytes());
+        for radix in [Radix::Byte, Radix::Nibble] {
+            let mut old = Tree::with_radix(radix);
+            for i in 0..300u32 {
+                old.add(key(i), vec![i as u8]);
+            }
+            let mut new = old.clone();
+            for i in 0..300u32 {
+                match i % 7 {
+                    0 => drop(new.split_off(&key(i).0)),
+                    1 => new.add(key(i), vec![0xff]),
+                    2 => new.add(key(i), vec![i as u8]),
+                    _ => (),
+                }
+            }
+            for i in 300..320u32 {
+                new.add(key(i), vec![i as u8]);
+            }
+            let proof = new.prove_diff(&old);
+            assert_eq!(proof.changes().len(), 43 + 43 + 20);
+            let changes = verify_diff(&old.options(), &proof, &old.hash(), &new.hash()).unwrap();
+            let mut applied = old.clone();
+            for (key, op) in &changes {
+                match op {
+                    DiffOp::Insert(value) => applied.add(key, value.clone()),
+                    DiffOp::Remove => drop(applied.split_off(&key.0)),
+                }
+            }
+            assert_eq!(applied.hash(), new.hash());
+            assert_eq!(verify_diff(&old.options(), &proof, &new.hash(), &new.hash()), None);
+            assert_eq!(verify_diff(&old.options(), &proof, &old.hash(), &old.hash()), None);
+
+            // removing all but one key collapses the tree into a leaf
+            let mut last = Tree::with_radix(radix);
+            last.add(key(5), vec![5]);
+            let proof = last.prove_diff(&old);
+            assert_eq!(verify_diff(&old.options(), &proof, &old.hash(), &last.hash()).map(|changes| changes.len()), Some(299));
+            let proof = Tree::with_radix(radix).prove_diff(&old);
+            assert!(verify_diff(&old.options(), &proof, &old.hash(), &EMPTY_ROOT).is_some());
+            let proof = old.prove_diff(&Tree::with_radix(radix));
+            assert!(verify_diff(&old.options(), &proof, &EMPTY_ROOT, &old.hash()).is_some());
+        }
+    }
+
+    #[test]
+    fn test_forged_empty_hash() {
+        let key = |i: u32| hash(&i.to_be_bytes());
+        let mut old = Tree::with_radix(Radix::Nibble);
+        for i in 0..200u32 {
+            old.add(key(i), vec![i as u8]);
+        }
+        let digits = Radix::Nibble.digits(&key(0).0);
+        let inner = match old.root_node() {
+            Some(Node::InnerNode(inner)) => inner,
+            other => panic!("{:?}", other),
+        };
+        let child = inner.child(digits[0]).unwrap().my_hash();
+        // the keys under the slot hashing as the empty slots in the full root are dropped
+        // without being listed, the insert takes their place
+        let map = inner.children().filter(|(branch, _)| *branch != digits[0]).map(|(branch, node)| (branch, Arc::new(Node::Pruned(node.my_hash())))).collect();
+        let mut root = InnerNode::new(map, Radix::Nibble, HASH_LEN, Some(child.clone()));
+        root.update();
+        let changes = vec![(key(0), DiffOp::Insert(vec![0xff]))];
+        let forged = DiffProof { old: PartialTree::from_root(Node::InnerNode(root)), changes: changes.clone() };
+        let mut children = inner.siblings(digits[0]);
+        children.push((digits[0], Leaf::new(digits[1..].to_vec(), vec![0xff]).my_hash()));
+        let new_root = combine(Radix::Nibble, &children);
+
+        let prover_options = Tree::<32>::builder().radix(Radix::Nibble).empty_hash(child);
+        assert_eq!(verify_diff(&prover_options, &forged, &old.hash(), &new_root), Some(changes));
+        assert_eq!(verify_diff(&old.options(), &forged, &old.hash(), &new_root), None);
+    }
+}
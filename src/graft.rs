@@ -0,0 +1,159 @@
+use std::sync::Arc;
+use {ChildMap, InnerNode, Node, Tree};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum GraftError {
+    /// the tree already has keys starting with the branch digit
+    Occupied(u8),
+    /// the subtree has keys not starting with the branch digit
+    OutsideBranch(u8),
+    /// the subtree branches on a different radix
+    RadixMismatch,
+    /// the subtree truncates the hashes to a different length
+    HashLenMismatch,
+    /// the subtree serializes the empty slots differently
+    EmptyHashMismatch,
+    /// the leaves of the subtree commit to another part of the key
+    LeafKeyMismatch,
+    /// the leaves of the subtree hold the values differently
+    ValueHashingMismatch,
+    /// the subtree accepts values of another length
+    FixedValueLenMismatch,
+    /// the subtree encodes the values with another codec
+    #[cfg(feature = "value-codec")]
+    ValueCodecMismatch,
+}
+
+impl Node {
+    /// The node as a child at `branch`, dropping the first digit of its keys, `None` if
+    /// some key doesn't start with `branch`
+    fn below(&self, branch: u8) -> Option<Node> {
+        match self {
+            Node::Leaf(leaf) if leaf.remaining_key[0] == branch => {
+                Some(Node::Leaf(leaf.clone().moved(leaf.remaining_key[1..].to_vec())))
+            },
+            Node::InnerNode(inner) if inner.map.len() == 1 => {
+                inner.map.get(&branch).map(|child| (**child).clone())
+            },
+            _ => None,
+        }
+    }
+}
+
+impl<const N: usize> Tree<N> {
+    /// Attach the entries of `subtree`, whose keys must all start with the digit `branch`,
+    /// under the `branch` child of the root. The tree is the same as if the entries were
+    /// added one by one, but the nodes of `subtree` are moved without rehashing them.
+    pub fn graft(&mut self, branch: u8, subtree: Tree<N>) -> Result<(), GraftError> {
+        if subtree.radix != self.radix {
+            return Err(GraftError::RadixMismatch);
+        }
+        if subtree.hash_len != self.hash_len {
+            return Err(GraftError::HashLenMismatch);
+        }
+        if subtree.empty_hash != self.empty_hash {
+            return Err(GraftError::EmptyHashMismatch);
+        }
+        if subtree.leaf_key != self.leaf_key {
+            return Err(GraftError::LeafKeyMismatch);
+        }
+        if subtree.value_hashing != self.value_hashing {
+            return Err(GraftError::ValueHashingMismatch);
+        }
+        if subtree.fixed_value_len != self.fixed_value_len {
+            return Err(GraftError::FixedValueLenMismatch);
+        }
+        #[cfg(feature = "value-codec")]
+        if subtree.value_codec != self.value_codec {
+            return Err(GraftError::ValueCodecMismatch);
+        }
+        let child = match subtree.root {
+            None => return Ok(()),
+            Some(ref root) => root.below(branch).ok_or(GraftError::OutsideBranch(branch))?,
+        };
+        match self.root.take() {
+            None => self.root = subtree.root,
+            Some(Node::Leaf(leaf)) => {
+                let other = leaf.remaining_key[0];
+                if other == branch {
+                    self.root = Some(Node::Leaf(leaf));
+                    return Err(GraftError::Occupied(branch));
+                }
+                let mut map = ChildMap::new();
+                let remaining_key = leaf.remaining_key[1..].to_vec();
+                map.insert(other, Arc::new(Node::Leaf(leaf.moved(remaining_key))));
+                map.insert(branch, Arc::new(child));
+                self.root = Some(Node::InnerNode(InnerNode::new(map, self.radix, self.hash_len, self.empty_hash.clone())));
+            },
+            Some(Node::InnerNode(mut inner)) => {
+                let occupied = inner.map.contains_key(&branch);
+                if !occupied {
+                    inner.map.insert(branch, Arc::new(child));
+                    inner.hash = None;
+                }
+                self.root = Some(Node::InnerNode(inner));
+                if occupied {
+                    return Err(GraftError::Occupied(branch));
+                }
+            },
+            Some(Node::Pruned(_)) => unreachable!(),
+        }
+        self.update_hashes();
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ::*;
+
+    #[test]
+    fn test_graft() {
+        for radix in [Radix::Byte, Radix::Nibble] {
+            let a = Sha256Hash([0x11u8;32]);
+            let b = Sha256Hash([0x22u8;32]);
+            let single = |key: &Sha256Hash| {
+                let mut tree = Tree::with_radix(radix);
+                tree.add(key, key.0.to_vec());
+                tree
+            };
+            let branch = |key: &Sha256Hash| radix.digits(&key.0)[0];
+
+            let mut tree = Tree::with_radix(radix);
+            tree.graft(branch(&a), single(&a)).unwrap();
+            tree.graft(branch(&b), single(&b)).unwrap();
+            assert_eq!(tree.get(&a), Some(a.0.to_vec()));
+            assert_eq!(tree.get(&b), Some(b.0.to_vec()));
+            let mut added = single(&a);
+            added.add(&b, b.0.to_vec());
+            assert_eq!(tree.hash(), added.hash());
+
+            let root = tree.hash();
+            assert_eq!(tree.graft(branch(&a), single(&a)), Err(GraftError::Occupied(branch(&a))));
+            assert_eq!(tree.graft(3, single(&a)), Err(GraftError::OutsideBranch(3)));
+            assert_eq!(tree.hash(), root);
+        }
+
+        let mut tree = Tree::default();
+        for i in 0u8..10 {
+            tree.add(Sha256Hash([i;32]), vec![i]);
+        }
+        let mut subtree = Tree::default();
+        for i in 0u8..10 {
+            let mut key = [i;32];
+            key[0] = 0xaa;
+            subtree.add(Sha256Hash(key), vec![i]);
+        }
+        let mut expected = tree.to_entries();
+        expected.extend(subtree.to_entries());
+        tree.graft(0xaa, subtree).unwrap();
+        assert_eq!(tree.to_entries(), expected);
+        assert_eq!(tree.verify_hashes(), Ok(()));
+        assert_eq!(tree.graft(0xbb, Tree::with_radix(Radix::Nibble)), Err(GraftError::RadixMismatch));
+        assert_eq!(tree.graft(0xbb, Tree::builder().hash_len(16).build()), Err(GraftError::HashLenMismatch));
+        assert_eq!(tree.graft(0xbb, Tree::with_empty_hash(hash(&[0]))), Err(GraftError::EmptyHashMismatch));
+        assert_eq!(tree.graft(0xbb, Tree::builder().value_hashing(ValueHashing::Digest).build()), Err(GraftError::ValueHashingMismatch));
+        assert_eq!(tree.graft(0xbb, Tree::with_fixed_value_len(1)), Err(GraftError::FixedValueLenMismatch));
+        assert_eq!(tree.verify_hashes(), Ok(()));
+    }
+}
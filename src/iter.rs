@@ -0,0 +1,864 @@
+use std::borrow::Borrow;
+use std::ops::ControlFlow;
+use std::sync::Arc;
+use {Hashable, Leaf, Node, Serializable, Sha256Hash, Tree, NO_PRUNED};
+
+/// Where and how an entry is stored, see `Tree::leaf_metadata`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LeafMeta {
+    /// inner nodes above the leaf
+    pub depth: usize,
+    /// key digits stored in the leaf, with `depth` they make all the digits of the key
+    pub remaining_key_len: usize,
+    pub value_len: usize,
+    pub leaf_hash: Sha256Hash,
+}
+
+/// Nodes still to serialize, an inner node is pushed back above its children and
+/// serialized once they are
+struct SerializedNodes<'a> {
+    stack: Vec<(&'a Node, bool)>,
+}
+
+impl<'a> Iterator for SerializedNodes<'a> {
+    type Item = Vec<u8>;
+
+    fn next(&mut self) -> Option<Vec<u8>> {
+        loop {
+            let (node, expanded) = self.stack.pop()?;
+            match node {
+                Node::InnerNode(inner) if !expanded => {
+                    self.stack.push((node, true));
+                    for branch in inner.branches().into_iter().rev() {
+                        self.stack.push((&inner.map[&branch], false));
+                    }
+                },
+                _ => return Some(node.serialize()),
+            }
+        }
+    }
+}
+
+impl Node {
+    /// Count in `histogram` the inner nodes under the node by their number of children
+    fn count_children(&self, histogram: &mut [usize]) {
+        if let Node::InnerNode(inner) = self {
+            histogram[inner.map.len()] += 1;
+            for child in inner.map.values() {
+                child.count_children(histogram);
+            }
+        }
+    }
+
+    /// Call `f` with the digits consumed to reach every leaf, in key order
+    fn for_each_leaf<'a>(&'a self, path: &mut Vec<u8>, f: &mut dyn FnMut(&[u8], &'a Leaf)) {
+        match self {
+            Node::Leaf(leaf) => f(path, leaf),
+            Node::InnerNode(inner) => {
+                for branch in inner.branches() {
+                    path.push(branch);
+                    inner.map[&branch].for_each_leaf(path, f);
+                    path.pop();
+                }
+            },
+            Node::Pruned(_) => (),
+        }
+    }
+
+    /// Like `for_each_leaf`, not visiting the leaves after the one `f` breaks at
+    fn try_for_each_leaf<'a>(&'a self, path: &mut Vec<u8>, f: &mut dyn FnMut(&[u8], &'a Leaf) -> ControlFlow<()>) -> ControlFlow<()> {
+        match self {
+            Node::Leaf(leaf) => f(path, leaf),
+            Node::InnerNode(inner) => {
+                for branch in inner.branches() {
+                    path.push(branch);
+                    let flow = inner.map[&branch].try_for_each_leaf(path, f);
+                    path.pop();
+                    flow?;
+                }
+                ControlFlow::Continue(())
+            },
+            Node::Pruned(_) => ControlFlow::Continue(()),
+        }
+    }
+
+    /// Move the digits and the value of every leaf to `entries` in key order, cloning only
+    /// the nodes shared with other trees
+    fn into_entries(self, path: &mut Vec<u8>, entries: &mut Vec<(Vec<u8>, Vec<u8>)>) {
+        match self {
+            Node::Leaf(leaf) => {
+                let mut digits = path.clone();
+                digits.extend(leaf.remaining_key);
+                entries.push((digits, leaf.value));
+            },
+            Node::InnerNode(mut inner) => {
+                for branch in inner.branches() {
+                    let child = inner.map.remove(&branch).expect("branches are in the map");
+                    path.push(branch);
+                    Arc::try_unwrap(child).unwrap_or_else(|shared| (*shared).clone()).into_entries(path, entries);
+                    path.pop();
+                }
+            },
+            Node::Pruned(_) => (),
+        }
+    }
+
+    /// Follow `prefix` down the inner nodes pushing the consumed digits to `path`, stopping
+    /// at the node covering the keys starting with `prefix` or at a leaf reached before.
+    fn descend(&self, prefix: &[u8], path: &mut Vec<u8>) -> Option<&Node> {
+        match self {
+            Node::InnerNode(inner) if !prefix.is_empty() => {
+                path.push(prefix[0]);
+                inner.map.get(&prefix[0])?.descend(&prefix[1..], path)
+            },
+            _ => Some(self),
+        }
+    }
+
+    /// See `Tree::walk`, a pruned node ends the walk without a leaf
+    pub(crate) fn walk(&self, digits: &[u8]) -> (usize, Option<&Leaf>) {
+        let mut node = self;
+        let mut depth = 0;
+        loop {
+            match node {
+                Node::Leaf(leaf) => return (depth, Some(leaf)),
+                Node::InnerNode(inner) => match inner.map.get(&digits[depth]) {
+                    None => return (depth + 1, None),
+                    Some(child) => node = child,
+                },
+                Node::Pruned(_) => return (depth, None),
+            }
+            depth += 1;
+        }
+    }
+}
+
+impl<const N: usize> Tree<N> {
+    /// Follow `digits` down to the leaf or the empty slot where they end, returning the
+    /// number of inner nodes on the way, the levels of a proof for the same key
+    pub(crate) fn walk(&self, digits: &[u8]) -> (usize, Option<&Leaf>) {
+        match self.root {
+            None => (0, None),
+            Some(ref root) => root.walk(digits),
+        }
+    }
+
+    /// The entry with the key closest to `target` by xor distance, `None` if the tree is empty.
+    /// No other key shares more leading bits with `target`, among the keys sharing as many
+    /// the one with the lowest distance in the first differing digit wins.
+    pub fn nearest(&self, target: &Sha256Hash<N>) -> Option<(Sha256Hash<N>, Vec<u8>)> {
+        let digits = self.radix.digits(&target.0);
+        let mut path = Vec::new();
+        let mut node = self.root.as_ref()?;
+        loop {
+            match node {
+                Node::Leaf(leaf) => {
+                    path.extend(&leaf.remaining_key);
+                    return Some((self.radix.key(&path), self.decode_value(&leaf.value)));
+                },
+                Node::InnerNode(inner) => {
+                    // keys under different branches first differ in this digit
+                    let digit = digits[path.len()];
+                    let branch = inner.map.keys().cloned().min_by_key(|branch| branch ^ digit)?;
+                    path.push(branch);
+                    node = &inner.map[&branch];
+                },
+                Node::Pruned(_) => return None,
+            }
+        }
+    }
+
+    /// Where and how the entry of `key` is stored, `None` if the key is not in the tree.
+    pub fn leaf_metadata(&self, key: &Sha256Hash<N>) -> Option<LeafMeta> {
+        let digits = self.radix.digits(&key.0);
+        match self.walk(&digits) {
+            (depth, Some(leaf)) if leaf.remaining_key[..] == digits[depth..] => Some(LeafMeta {
+                depth,
+                remaining_key_len: leaf.remaining_key.len(),
+                value_len: leaf.value.len(),
+                leaf_hash: leaf.my_hash(),
+            }),
+            _ => None,
+        }
+    }
+
+    /// Hashes of the nodes on the path of `key`, from the root to the leaf the path ends in,
+    /// which may hold another key, or to the inner node with the empty slot of `key`.
+    /// Empty for the empty tree.
+    pub fn path_hashes(&self, key: &Sha256Hash<N>) -> Vec<Sha256Hash> {
+        let digits = self.radix.digits(&key.0);
+        let mut hashes = Vec::new();
+        let mut node = self.root.as_ref();
+        for digit in &digits {
+            match node {
+                Some(Node::InnerNode(inner)) => {
+                    hashes.push(inner.my_hash());
+                    node = inner.map.get(digit).map(|child| &**child);
+                },
+                _ => break,
+            }
+        }
+        match node {
+            Some(Node::Leaf(leaf)) => hashes.push(leaf.my_hash()),
+            Some(Node::Pruned(_)) => panic!("{}", NO_PRUNED),
+            _ => (),
+        }
+        hashes
+    }
+
+    /// Key digits stored in the leaf the path of `key` ends in, the ones not consumed by the
+    /// inner nodes above it. The leaf may hold another key, `None` if the path ends in an
+    /// empty slot.
+    pub fn remaining_key_at(&self, key: &Sha256Hash<N>) -> Option<Vec<u8>> {
+        let digits = self.radix.digits(&key.0);
+        self.walk(&digits).1.map(|leaf| leaf.remaining_key.clone())
+    }
+
+    /// The value of `key` with the hash of its leaf in a single lookup, `None` if the key is
+    /// not in the tree.
+    pub fn get_with_metadata<K: Borrow<[u8;N]>>(&self, key: K) -> Option<(Vec<u8>, Sha256Hash)> {
+        let digits = self.radix.digits(key.borrow());
+        match self.walk(&digits) {
+            (depth, Some(leaf)) if leaf.remaining_key[..] == digits[depth..] => Some((self.decode_value(&leaf.value), leaf.my_hash())),
+            _ => None,
+        }
+    }
+
+    /// Hash of the deepest inner node on the paths of both `a` and `b`, the node where they
+    /// branch apart, `None` if either key is not in the tree or no inner node is above them.
+    pub fn common_ancestor_hash(&self, a: &Sha256Hash<N>, b: &Sha256Hash<N>) -> Option<Sha256Hash> {
+        self.leaf_metadata(a)?;
+        self.leaf_metadata(b)?;
+        let (a, b) = (self.radix.digits(&a.0), self.radix.digits(&b.0));
+        let mut node = self.root.as_ref()?;
+        let mut ancestor = None;
+        for (digit_a, digit_b) in a.iter().zip(&b) {
+            match node {
+                Node::InnerNode(inner) => {
+                    ancestor = Some(inner.my_hash());
+                    if digit_a != digit_b {
+                        break;
+                    }
+                    node = &inner.map[digit_a];
+                },
+                _ => break,
+            }
+        }
+        ancestor
+    }
+
+    fn for_each_leaf_under<'a>(&'a self, prefix: &[u8], f: &mut dyn FnMut(Sha256Hash<N>, &'a Leaf)) {
+        let prefix = self.radix.digits(prefix);
+        let mut path = Vec::new();
+        let node = match self.root {
+            Some(ref root) => root.descend(&prefix, &mut path),
+            None => None,
+        };
+        if let Some(node) = node {
+            node.for_each_leaf(&mut path, &mut |path, leaf| {
+                let mut digits = path.to_vec();
+                digits.extend(&leaf.remaining_key);
+                if digits.starts_with(&prefix) {
+                    f(self.radix.key(&digits), leaf);
+                }
+            });
+        }
+    }
+
+    /// Call `f` with the remaining key and the value of every leaf in key order, borrowing
+    /// them from the tree instead of building the owned entries of `to_entries`. The
+    /// remaining key holds the key digits below the inner nodes, nibbles with `Radix::Nibble`.
+    pub fn for_each_leaf<F: FnMut(&[u8], &[u8])>(&self, mut f: F) {
+        if let Some(ref root) = self.root {
+            root.for_each_leaf(&mut Vec::new(), &mut |_, leaf| f(&leaf.remaining_key, &leaf.value));
+        }
+    }
+
+    /// Call `f` with every entry in key order until it returns `ControlFlow::Break`, the
+    /// subtrees after the breaking entry are not visited.
+    pub fn walk_ordered<F: FnMut(&Sha256Hash<N>, &[u8]) -> ControlFlow<()>>(&self, mut f: F) {
+        if let Some(ref root) = self.root {
+            let _ = root.try_for_each_leaf(&mut Vec::new(), &mut |path, leaf| {
+                let mut digits = path.to_vec();
+                digits.extend(&leaf.remaining_key);
+                f(&self.radix.key(&digits), &self.decode_value(&leaf.value))
+            });
+        }
+    }
+
+    /// The serialization of every node in post-order, the children in branch order before
+    /// their parent, so the last item is `Tree::serialize`. One node is serialized at a time,
+    /// `[0x00]` alone for the empty tree.
+    pub fn iter_serialized(&self) -> impl Iterator<Item = Vec<u8>> + '_ {
+        let empty = match self.root {
+            None => Some(self.serialize()),
+            Some(_) => None,
+        };
+        let stack = self.root.iter().map(|root| (root, false)).collect();
+        empty.into_iter().chain(SerializedNodes { stack })
+    }
+
+    /// Number of leaves by depth, the number of inner nodes above them. Random keys keep the
+    /// leaves within a few levels, a long tail hints at keys chosen to collide.
+    pub fn depth_histogram(&self) -> Vec<usize> {
+        let mut histogram = Vec::new();
+        if let Some(ref root) = self.root {
+            root.for_each_leaf(&mut Vec::new(), &mut |path, _| {
+                if histogram.len() <= path.len() {
+                    histogram.resize(path.len() + 1, 0);
+                }
+                histogram[path.len()] += 1;
+            });
+        }
+        histogram
+    }
+
+    /// Number of inner nodes by number of children, from 0 to the radix width. With random
+    /// keys the nodes near the root fill up and the deeper ones split a few leaves, mostly
+    /// 2, many nodes deep down hint at clustered keys.
+    pub fn children_histogram(&self) -> Vec<usize> {
+        let mut histogram = vec![0; self.radix.width() + 1];
+        if let Some(ref root) = self.root {
+            root.count_children(&mut histogram);
+        }
+        histogram
+    }
+
+    /// Copy of the subtree under `prefix` as a tree with keys of `M` bytes, the keys with
+    /// `prefix` stripped, `None` if no key starts with `prefix` or if `prefix` isn't `N - M`
+    /// bytes long. The nodes are shared with this tree until modified, the hashes below the
+    /// prefix stay the same, with `LeafKey::Full` the leaves keep committing to the keys
+    /// with `prefix`.
+    pub fn clone_subtree<const M: usize>(&self, prefix: &[u8]) -> Option<Tree<M>> {
+        if prefix.len() + M != N {
+            return None;
+        }
+        let prefix = self.radix.digits(prefix);
+        let mut path = Vec::new();
+        let root = match self.root.as_ref()?.descend(&prefix, &mut path)? {
+            // a leaf reached before the end of the prefix holds the rest of it
+            Node::Leaf(leaf) => {
+                let rest = &prefix[path.len()..];
+                if !leaf.remaining_key.starts_with(rest) {
+                    return None;
+                }
+                Node::Leaf(leaf.clone().moved(leaf.remaining_key[rest.len()..].to_vec()))
+            },
+            node => node.clone(),
+        };
+        let mut tree = self.empty_like();
+        tree.root = Some(root);
+        Some(tree)
+    }
+
+    /// All the entries whose key starts with `prefix`, sorted by key.
+    pub fn entries_under_prefix(&self, prefix: &[u8]) -> Vec<(Sha256Hash<N>, Vec<u8>)> {
+        let mut result = Vec::new();
+        self.for_each_leaf_under(prefix, &mut |key, leaf| result.push((key, self.decode_value(&leaf.value))));
+        result
+    }
+
+    /// Number of keys starting with `prefix`, counting the leaves under the node covering them.
+    pub fn count_under_prefix(&self, prefix: &[u8]) -> usize {
+        let mut count = 0;
+        self.for_each_leaf_under(prefix, &mut |_, _| count += 1);
+        count
+    }
+
+    /// All the entries sorted by key, `Tree::from_entries` rebuilds the same tree.
+    /// Every walk of the public API visits the children in branch order, so the order
+    /// depends only on the entries, not on the insertion order or on the map backend.
+    pub fn to_entries(&self) -> Vec<(Sha256Hash<N>, Vec<u8>)> {
+        self.entries_under_prefix(&[])
+    }
+
+    /// All the entries sorted by value, ties broken by key, for debugging and analytics.
+    pub fn leaves_sorted_by_value(&self) -> Vec<(Sha256Hash<N>, Vec<u8>)> {
+        let mut entries = self.to_entries();
+        entries.sort_by(|a, b| a.1.cmp(&b.1).then_with(|| a.0.cmp(&b.0)));
+        entries
+    }
+
+    /// All the entries sorted by key like `to_entries`, moving the values out of the nodes not
+    /// shared with other trees instead of cloning them.
+    pub fn into_sorted_vec(mut self) -> Vec<(Sha256Hash<N>, Vec<u8>)> {
+        let mut entries = Vec::new();
+        if let Some(root) = self.root.take() {
+            root.into_entries(&mut Vec::new(), &mut entries);
+        }
+        #[cfg(feature = "value-codec")]
+        if self.value_codec.is_some() {
+            return entries.into_iter().map(|(digits, value)| (self.radix.key(&digits), self.decode_value(&value))).collect();
+        }
+        entries.into_iter().map(|(digits, value)| (self.radix.key(&digits), value)).collect()
+    }
+
+    pub fn from_entries<I: IntoIterator<Item = (Sha256Hash<N>, Vec<u8>)>>(entries: I) -> Self {
+        entries.into_iter().collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ::*;
+    use rand::{Rng, SeedableRng};
+    use rand::prng::XorShiftRng;
+
+    #[test]
+    fn test_entries_under_prefix() {
+        for radix in [Radix::Byte, Radix::Nibble] {
+            let mut tree = Tree::with_radix(radix);
+            let mut expected = Vec::new();
+            for i in 0u8..5 {
+                let mut key = [i;32];
+                key[0] = 0xab;
+                key[1] = 0xcd;
+                expected.push((Sha256Hash(key), vec![i]));
+                tree.add(Sha256Hash(key), vec![i]);
+                key[1] = 0xce;
+                tree.add(Sha256Hash(key), vec![i]);
+                tree.add(Sha256Hash([i;32]), vec![i]);
+            }
+            expected.reverse();
+            tree.add(&expected[0].0, vec![0xff]);
+            expected[0].1 = vec![0xff];
+            expected.sort();
+
+            assert_eq!(tree.entries_under_prefix(&[0xab, 0xcd]), expected);
+            assert_eq!(tree.entries_under_prefix(&[0xab]).len(), 10);
+            assert_eq!(tree.entries_under_prefix(&[]).len(), 15);
+            assert!(tree.entries_under_prefix(&[0xab, 0xcf]).is_empty());
+            assert_eq!(tree.entries_under_prefix(&expected[1].0 .0), vec![expected[1].clone()]);
+        }
+
+        let mut tree = Tree::default();
+        assert!(tree.entries_under_prefix(&[]).is_empty());
+        tree.add(Sha256Hash([1u8;32]), vec![1]);
+        assert_eq!(tree.entries_under_prefix(&[1, 1]), vec![(Sha256Hash([1u8;32]), vec![1])]);
+        assert!(tree.entries_under_prefix(&[1, 2]).is_empty());
+    }
+
+    #[test]
+    fn test_count_under_prefix() {
+        for radix in [Radix::Byte, Radix::Nibble] {
+            let mut tree = Tree::with_radix(radix);
+            assert_eq!(tree.count_under_prefix(&[]), 0);
+            for bucket in 0u8..5 {
+                for i in 0..usize::from(bucket) * 3 {
+                    let mut key = hash(&[bucket, i as u8]).0;
+                    key[0] = bucket;
+                    tree.add(key, vec![i as u8]);
+                }
+            }
+            for bucket in 0u8..5 {
+                assert_eq!(tree.count_under_prefix(&[bucket]), usize::from(bucket) * 3);
+            }
+            assert_eq!(tree.count_under_prefix(&[]), 30);
+            assert_eq!(tree.count_under_prefix(&[0xff]), 0);
+            let key = tree.to_entries()[0].0.clone();
+            assert_eq!(tree.count_under_prefix(&key.0), 1);
+            assert_eq!(tree.count_under_prefix(&key.0[..31]), 1);
+        }
+    }
+
+    #[test]
+    fn test_clone_subtree() {
+        for radix in [Radix::Byte, Radix::Nibble] {
+            let mut tree = Tree::with_radix(radix);
+            assert!(tree.clone_subtree::<31>(&[0xab]).is_none());
+            let mut key = [0u8;32];
+            key[0] = 0xab;
+            tree.add(Sha256Hash(key), vec![0]);
+            let single: Tree<31> = tree.clone_subtree(&[0xab]).unwrap();
+            assert_eq!(single.to_entries(), vec![(Sha256Hash([0u8;31]), vec![0])]);
+            assert!(tree.clone_subtree::<31>(&[0xac]).is_none());
+            assert!(tree.clone_subtree::<30>(&[0xab, 0x01]).is_none());
+            assert!(tree.clone_subtree::<30>(&[0xab]).is_none());
+            assert!(tree.clone_subtree::<32>(&[0xab]).is_none());
+
+            for i in 0u32..100 {
+                let mut key = hash(&i.to_be_bytes()).0;
+                key[0] = 0xab;
+                tree.add(key, vec![i as u8]);
+                tree.add(hash(&i.to_be_bytes()), vec![i as u8]);
+            }
+            let subtree: Tree<31> = tree.clone_subtree(&[0xab]).unwrap();
+            let expected: Vec<(Sha256Hash<31>, Vec<u8>)> = tree.entries_under_prefix(&[0xab]).into_iter()
+                .map(|(key, value)| {
+                    let mut stripped = [0u8;31];
+                    stripped.copy_from_slice(&key.0[1..]);
+                    (Sha256Hash(stripped), value)
+                })
+                .collect();
+            assert_eq!(subtree.to_entries(), expected);
+            let mut rebuilt = Tree::with_radix(radix);
+            rebuilt.extend(expected);
+            assert_eq!(subtree.hash(), rebuilt.hash());
+            assert_eq!(subtree.check_invariants(), Ok(()));
+            assert_eq!(tree.clone_subtree::<32>(&[]).unwrap().hash(), tree.hash());
+        }
+    }
+
+    #[test]
+    fn test_entries_roundtrip() {
+        let mut tree = Tree::default();
+        assert!(Tree::<32>::from_entries(tree.to_entries()).is_empty());
+        for i in (0u8..20).rev() {
+            tree.add(Sha256Hash([i;32]), vec![i; i as usize]);
+        }
+        let entries = tree.to_entries();
+        assert_eq!(entries.len(), 20);
+        assert!(entries.windows(2).all(|w| w[0].0 < w[1].0));
+        assert_eq!(Tree::from_entries(entries).hash(), tree.hash());
+    }
+
+    #[test]
+    fn test_into_sorted_vec() {
+        for radix in [Radix::Byte, Radix::Nibble] {
+            let mut tree = Tree::with_radix(radix);
+            assert!(tree.clone().into_sorted_vec().is_empty());
+            for i in 0u32..100 {
+                tree.add(hash(&i.to_be_bytes()), vec![i as u8; 10]);
+            }
+            let entries = tree.to_entries();
+            let shared = tree.clone();
+            assert_eq!(shared.into_sorted_vec(), entries);
+
+            // the values are moved out of the nodes not shared
+            let mut pointers = Vec::new();
+            tree.for_each_leaf(|_, value| pointers.push(value.as_ptr()));
+            let moved = tree.into_sorted_vec();
+            assert_eq!(moved, entries);
+            assert_eq!(moved.iter().map(|(_, value)| value.as_ptr()).collect::<Vec<_>>(), pointers);
+        }
+    }
+
+    #[test]
+    fn test_leaves_sorted_by_value() {
+        let mut tree = Tree::default();
+        assert!(tree.leaves_sorted_by_value().is_empty());
+        for i in 0u8..20 {
+            tree.add(Sha256Hash([i;32]), vec![19 - i % 10, i]);
+        }
+        let sorted = tree.leaves_sorted_by_value();
+        assert_eq!(sorted.len(), 20);
+        assert!(sorted.windows(2).all(|w| w[0].1 < w[1].1));
+        assert_eq!(sorted[0], (Sha256Hash([9;32]), vec![10, 9]));
+        let mut by_key = sorted.clone();
+        by_key.sort();
+        assert_eq!(by_key, tree.to_entries());
+    }
+
+    #[test]
+    fn test_depth_histogram() {
+        let mut rng = XorShiftRng::from_seed([7u8;16]);
+        let mut tree = Tree::default();
+        assert!(tree.depth_histogram().is_empty());
+        tree.add(hash(&[0]), vec![0]);
+        assert_eq!(tree.depth_histogram(), vec![1]);
+        for _ in 0..1000 {
+            tree.add(Sha256Hash(rng.gen()), vec![]);
+        }
+        let histogram = tree.depth_histogram();
+        assert_eq!(histogram.iter().sum::<usize>(), 1001);
+        assert_eq!(histogram.iter().sum::<usize>(), tree.count_under_prefix(&[]));
+        assert_eq!(histogram[0], 0);
+        assert!(histogram.len() <= 5);
+
+        // keys sharing a long prefix are deeper
+        let mut key = [0xaau8;32];
+        tree.add(Sha256Hash(key), vec![]);
+        key[31] = 0;
+        tree.add(Sha256Hash(key), vec![]);
+        assert_eq!(tree.depth_histogram().len(), 33);
+        assert_eq!(tree.depth_histogram()[32], 2);
+    }
+
+    #[test]
+    fn test_path_hashes() {
+        let key = |i: u32| hash(&i.to_be_bytes());
+        for radix in [Radix::Byte, Radix::Nibble] {
+            let mut tree: Tree = Tree::with_radix(radix);
+            assert!(tree.path_hashes(&key(0)).is_empty());
+            tree.add(key(0), vec![0]);
+            assert_eq!(tree.path_hashes(&key(0)), vec![tree.hash()]);
+            for i in 1..300 {
+                tree.add(key(i), vec![i as u8]);
+            }
+            for i in [0, 150, 299] {
+                let hashes = tree.path_hashes(&key(i));
+                let meta = tree.leaf_metadata(&key(i)).unwrap();
+                assert_eq!(hashes.len(), meta.depth + 1);
+                assert_eq!(hashes[0], tree.hash());
+                assert_eq!(hashes[meta.depth], meta.leaf_hash);
+            }
+            // an absent key ends in an empty slot or in the leaf of another key
+            let hashes = tree.path_hashes(&key(1000));
+            assert_eq!(hashes[0], tree.hash());
+            let proof = tree.prove(key(1000));
+            assert_eq!(hashes.len(), proof.levels.len() + proof.leaf.iter().count());
+        }
+    }
+
+    #[test]
+    fn test_children_histogram() {
+        let mut rng = XorShiftRng::from_seed([7u8;16]);
+        for (radix, n) in [(Radix::Byte, 100), (Radix::Nibble, 1000)] {
+            let mut tree: Tree = Tree::with_radix(radix);
+            assert_eq!(tree.children_histogram().iter().sum::<usize>(), 0);
+            for _ in 0..n {
+                tree.add(Sha256Hash(rng.gen()), vec![]);
+            }
+            let histogram = tree.children_histogram();
+            assert_eq!(histogram.len(), radix.width() + 1);
+            let inner_nodes: usize = histogram.iter().sum();
+            let children: usize = histogram.iter().enumerate().map(|(count, nodes)| count * nodes).sum();
+            assert_eq!(children, inner_nodes + n - 1);
+            if radix == Radix::Byte {
+                // fewer keys than slots fill the root, the nodes below it split colliding pairs
+                assert!(histogram[2] * 10 >= (inner_nodes - 1) * 8);
+            }
+        }
+
+        // two keys sharing 31 bytes hang from a chain of inner nodes with a single child
+        let mut tree = Tree::default();
+        let mut key = [0xaau8;32];
+        tree.add(Sha256Hash(key), vec![]);
+        key[31] = 0;
+        tree.add(Sha256Hash(key), vec![]);
+        let histogram = tree.children_histogram();
+        assert_eq!((histogram[1], histogram[2]), (31, 1));
+    }
+
+    #[test]
+    fn test_for_each_leaf() {
+        for radix in [Radix::Byte, Radix::Nibble] {
+            let mut tree = Tree::with_radix(radix);
+            tree.for_each_leaf(|_, _| panic!("empty tree"));
+            for i in 0u32..200 {
+                tree.add(hash(&i.to_be_bytes()), vec![0u8; i as usize % 17]);
+            }
+            let mut leaves = 0;
+            let mut total = 0;
+            tree.for_each_leaf(|remaining_key, value| {
+                assert!(!remaining_key.is_empty());
+                leaves += 1;
+                total += value.len();
+            });
+            let entries = tree.to_entries();
+            assert_eq!(leaves, entries.len());
+            assert_eq!(total, entries.iter().map(|(_, value)| value.len()).sum::<usize>());
+        }
+    }
+
+    #[test]
+    fn test_iter_serialized() {
+        for radix in [Radix::Byte, Radix::Nibble] {
+            for value_hashing in [ValueHashing::Inline, ValueHashing::Digest] {
+                let mut tree: Tree = Tree::builder().radix(radix).value_hashing(value_hashing).build();
+                assert_eq!(tree.iter_serialized().collect::<Vec<_>>(), vec![vec![0x00]]);
+                for i in 0u32..150 {
+                    tree.add(hash(&i.to_be_bytes()), i.to_be_bytes().to_vec());
+                }
+                let nodes: Vec<Vec<u8>> = tree.iter_serialized().collect();
+                assert_eq!(nodes.last(), Some(&tree.serialize()));
+                assert_eq!(hash(nodes.last().unwrap()), tree.hash());
+                // every node is referenced by its parent, serialized after it
+                for (i, node) in nodes.iter().enumerate().take(nodes.len() - 1) {
+                    let node_hash = hash(node);
+                    assert!(nodes[i + 1..].iter().any(|parent| parent.windows(32).any(|window| window == node_hash.0)));
+                }
+                let leaves = nodes.iter().filter(|node| node[0] != 0x01).count();
+                assert_eq!(leaves, 150);
+            }
+        }
+    }
+
+    #[test]
+    fn test_walk_ordered() {
+        use std::ops::ControlFlow;
+        for radix in [Radix::Byte, Radix::Nibble] {
+            let mut tree = Tree::with_radix(radix);
+            for i in 0u32..200 {
+                tree.add(hash(&i.to_be_bytes()), i.to_be_bytes().to_vec());
+            }
+            let entries = tree.to_entries();
+            for stop in [1, 10, 200] {
+                let mut visited = Vec::new();
+                tree.walk_ordered(|key, value| {
+                    visited.push((key.clone(), value.to_vec()));
+                    if visited.len() == stop { ControlFlow::Break(()) } else { ControlFlow::Continue(()) }
+                });
+                assert_eq!(visited, entries[..stop]);
+            }
+        }
+    }
+
+    #[test]
+    fn test_nearest() {
+        let a = hash(&[1]);
+        let b = hash(&[2]);
+        assert_eq!(a.xor_distance(&b), b.xor_distance(&a));
+        assert_eq!(a.xor_distance(&a), [0u8;32]);
+
+        for radix in [Radix::Byte, Radix::Nibble] {
+            let mut tree = Tree::with_radix(radix);
+            assert_eq!(tree.nearest(&a), None);
+            for i in 0u32..200 {
+                tree.add(hash(&i.to_be_bytes()), vec![i as u8]);
+            }
+            let entries = tree.to_entries();
+            for i in 200u32..300 {
+                let target = hash(&i.to_be_bytes());
+                let expected = entries.iter().min_by_key(|(key, _)| key.xor_distance(&target)).cloned();
+                assert_eq!(tree.nearest(&target), expected);
+            }
+            assert_eq!(tree.nearest(&entries[7].0), Some(entries[7].clone()));
+        }
+    }
+
+    #[test]
+    fn test_nearest_shared_prefix() {
+        for radix in [Radix::Byte, Radix::Nibble] {
+            let mut tree = Tree::with_radix(radix);
+            let mut keys = Vec::new();
+            for shared in 0..4 {
+                let mut key = [0xffu8;32];
+                for b in key.iter_mut().take(shared) {
+                    *b = 0x11;
+                }
+                keys.push(Sha256Hash(key));
+                tree.add(Sha256Hash(key), vec![shared as u8]);
+            }
+            let mut target = [0x11u8;32];
+            target[3] = 0x22;
+            assert_eq!(tree.get(target), None);
+            assert_eq!(tree.nearest(&Sha256Hash(target)), Some((keys[3].clone(), vec![3])));
+            target[2] = 0xee;
+            assert_eq!(tree.nearest(&Sha256Hash(target)), Some((keys[2].clone(), vec![2])));
+        }
+    }
+
+    #[test]
+    fn test_leaf_metadata() {
+        let mut tree = Tree::default();
+        assert_eq!(tree.leaf_metadata(&hash(&[0])), None);
+        for i in 0u32..100 {
+            tree.add(hash(&i.to_be_bytes()), vec![0u8; i as usize]);
+        }
+        for i in 0u32..100 {
+            let key = hash(&i.to_be_bytes());
+            let meta = tree.leaf_metadata(&key).unwrap();
+            assert_eq!(meta.depth + meta.remaining_key_len, 32);
+            assert!(meta.depth > 0);
+            assert_eq!(meta.value_len, i as usize);
+            assert_eq!(meta.leaf_hash, Leaf::new(key.0[meta.depth..].to_vec(), vec![0u8; i as usize]).my_hash());
+        }
+        assert_eq!(tree.leaf_metadata(&hash(&100u32.to_be_bytes())), None);
+    }
+
+    #[test]
+    fn test_get_with_metadata() {
+        for value_hashing in [ValueHashing::Inline, ValueHashing::Digest] {
+            let mut tree: Tree = Tree::builder().value_hashing(value_hashing).build();
+            assert_eq!(tree.get_with_metadata(hash(&[0])), None);
+            for i in 0u8..50 {
+                tree.add(hash(&[i]), vec![i; 40]);
+            }
+            for i in 0u8..50 {
+                let key = hash(&[i]);
+                let depth = tree.leaf_metadata(&key).unwrap().depth;
+                let leaf_hash = Leaf::with_value_hashing(key.0[depth..].to_vec(), vec![i; 40], value_hashing).my_hash();
+                assert_eq!(tree.get_with_metadata(&key), Some((vec![i; 40], leaf_hash)));
+            }
+            let mut key = hash(&[0]);
+            key.0[31] ^= 1;
+            assert_eq!(tree.get_with_metadata(key), None);
+        }
+    }
+
+    #[test]
+    fn test_remaining_key_at() {
+        for radix in [Radix::Byte, Radix::Nibble] {
+            let mut tree = Tree::with_radix(radix);
+            assert_eq!(tree.remaining_key_at(&hash(&[0])), None);
+            tree.add(hash(&[0]), vec![0]);
+            assert_eq!(tree.remaining_key_at(&hash(&[0])), Some(radix.digits(&hash(&[0]).0)));
+            for i in 1u32..100 {
+                tree.add(hash(&i.to_be_bytes()), vec![]);
+            }
+            for i in 1u32..100 {
+                let key = hash(&i.to_be_bytes());
+                let remaining_key = tree.remaining_key_at(&key).unwrap();
+                let depth = tree.leaf_metadata(&key).unwrap().depth;
+                assert_eq!(remaining_key.len() + depth, radix.digits(&key.0).len());
+                assert_eq!(remaining_key[..], radix.digits(&key.0)[depth..]);
+            }
+            let mut key = hash(&1u32.to_be_bytes());
+            key.0[31] ^= 1;
+            assert_ne!(tree.remaining_key_at(&key), None);
+            assert_eq!(tree.remaining_key_at(&key).map(|k| k.len()), tree.remaining_key_at(&hash(&1u32.to_be_bytes())).map(|k| k.len()));
+        }
+    }
+
+    #[test]
+    fn test_common_ancestor_hash() {
+        for radix in [Radix::Byte, Radix::Nibble] {
+            let mut tree = Tree::with_radix(radix);
+            let (mut a, mut b) = ([0xabu8;32], [0xabu8;32]);
+            a[1] = 0x01;
+            b[1] = 0x02;
+            let (a, b) = (Sha256Hash(a), Sha256Hash(b));
+            tree.add(&a, vec![1]);
+            assert_eq!(tree.common_ancestor_hash(&a, &a), None);
+            assert_eq!(tree.common_ancestor_hash(&a, &b), None);
+            tree.add(&b, vec![2]);
+            tree.add(Sha256Hash([0x11u8;32]), vec![3]);
+
+            // the root child at 0xab, with nibbles the node below the three shared nibbles
+            let shared = match radix {
+                Radix::Byte => &[0xab][..],
+                Radix::Nibble => &[0x0a, 0x0b, 0x00][..],
+            };
+            let mut node = tree.root.as_ref().unwrap();
+            for digit in shared {
+                node = match node {
+                    Node::InnerNode(inner) => &inner.map[digit],
+                    _ => panic!("not an inner node"),
+                };
+            }
+            let expected = node.my_hash();
+            assert_eq!(tree.common_ancestor_hash(&a, &b), Some(expected.clone()));
+            assert_eq!(tree.common_ancestor_hash(&b, &a), Some(expected));
+            assert_eq!(tree.common_ancestor_hash(&a, &Sha256Hash([0x11u8;32])), Some(tree.hash()));
+            assert_eq!(tree.common_ancestor_hash(&a, &hash(&[0])), None);
+        }
+    }
+
+    #[test]
+    fn test_deterministic_iteration() {
+        let mut entries: Vec<(Sha256Hash, Vec<u8>)> = (0u32..300).map(|i| (hash(&i.to_be_bytes()), vec![i as u8])).collect();
+        let mut rng = XorShiftRng::from_seed([7u8;16]);
+        for radix in [Radix::Byte, Radix::Nibble] {
+            let mut expected = None;
+            for _ in 0..5 {
+                rng.shuffle(&mut entries);
+                let mut tree = Tree::with_radix(radix);
+                tree.extend(entries.clone());
+                let mut visited = Vec::new();
+                for (key, value) in tree.to_entries() {
+                    visited.extend(&key.0);
+                    visited.extend(value);
+                }
+                assert_eq!(visited.len(), 300 * 33);
+                match expected {
+                    None => expected = Some(visited),
+                    Some(ref expected) => assert_eq!(expected, &visited),
+                }
+            }
+            // the same digest with the HashMap and the BTreeMap backend
+            let expected = HEXLOWER.encode(&hash(&expected.unwrap()).0);
+            assert_eq!(expected, "437739698edca4f78d901ecd1a1e5c97d0fdda76fdccdceb37d46d672b621b16");
+        }
+    }
+}
@@ -0,0 +1,1838 @@
+
+extern crate integer_encoding;
+extern crate crypto;
+extern crate data_encoding;
+extern crate rand;
+#[cfg(feature = "tracing")]
+#[macro_use]
+extern crate tracing;
+
+mod batch;
+#[cfg(feature = "blob-store")]
+mod blob;
+mod bounded;
+#[cfg(feature = "proof-cache")]
+mod cache;
+#[cfg(feature = "value-codec")]
+mod codec;
+mod compact;
+mod deserialize;
+mod diff;
+mod graft;
+mod iter;
+mod log;
+mod memory;
+mod multi;
+mod node;
+mod ops;
+mod partial;
+mod prefix;
+mod proof;
+mod range;
+#[cfg(feature = "sparse-merkle")]
+mod smt;
+mod store;
+#[cfg(feature = "tracing")]
+mod trace;
+mod update;
+mod verify;
+mod writer;
+
+use std::borrow::Borrow;
+use std::convert::TryFrom;
+#[cfg(not(feature = "btree-map"))]
+use std::collections::HashMap;
+#[cfg(feature = "btree-map")]
+use std::collections::BTreeMap;
+use std::iter::FromIterator;
+use std::mem;
+use std::sync::Arc;
+use integer_encoding::VarInt;
+use crypto::sha2::Sha256;
+use crypto::digest::Digest;
+use data_encoding::HEXLOWER;
+
+pub use batch::DirtyTree;
+#[cfg(feature = "blob-store")]
+pub use blob::{verify_blob, BlobError, BlobStore};
+pub use bounded::BoundedTree;
+#[cfg(feature = "value-codec")]
+pub use codec::ValueCodec;
+pub use deserialize::{Deserializable, DeserializeError};
+pub use diff::{verify_diff, DiffOp, DiffProof};
+pub use graft::GraftError;
+pub use iter::LeafMeta;
+pub use multi::{verify_multi_proof, MultiProof};
+pub use ops::Op;
+pub use partial::{FillError, LeafOrPruned, PartialTree, Pruned};
+pub use prefix::{verify_prefix, PrefixProof};
+pub use proof::{bulk_verify, combine, verify_proof, InvalidProof, Proof};
+pub use range::{verify_first_n, verify_range, RangeProof};
+#[cfg(feature = "sparse-merkle")]
+pub use smt::{verify_sparse_merkle, SparseMerkleProof, SparseMerkleRoot};
+pub use store::{NodeStore, ProofError, StoreError};
+pub use update::{verify_insert, UpdateProof};
+pub use verify::{TamperError, VerifyError};
+pub use writer::{RwLockTree, TreeReader, TreeWriter};
+
+/// A node hash, or with `N` other than 32 the key of a `Tree<N>`
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Sha256Hash<const N: usize = 32> (pub [u8;N]);  // for testing
+
+impl<const N: usize> Borrow<[u8;N]> for Sha256Hash<N> {
+    fn borrow(&self) -> &[u8;N] {
+        &self.0
+    }
+}
+
+impl<const N: usize> Borrow<[u8;N]> for &Sha256Hash<N> {
+    fn borrow(&self) -> &[u8;N] {
+        &self.0
+    }
+}
+
+/// A raw key that isn't a key of `Tree<N>`, see `Tree::validate_key`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum KeyError {
+    Empty,
+    WrongLength { expected: usize, found: usize },
+}
+
+/// A value rejected by a tree built with `Tree::with_fixed_value_len`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ValueLenError {
+    pub expected: usize,
+    pub found: usize,
+}
+
+/// A key of `Tree<N>` from raw bytes, checked by `Tree::validate_key`
+impl<'a, const N: usize> TryFrom<&'a [u8]> for Sha256Hash<N> {
+    type Error = KeyError;
+
+    fn try_from(key: &'a [u8]) -> Result<Self, KeyError> {
+        Tree::<N>::validate_key(key)?;
+        let mut bytes = [0u8;N];
+        bytes.copy_from_slice(key);
+        Ok(Sha256Hash(bytes))
+    }
+}
+
+impl Sha256Hash {
+    /// The first `len` bytes of the hash followed by zeros
+    fn truncated(&self, len: usize) -> Sha256Hash {
+        let mut bytes = [0u8;32];
+        bytes[..len].copy_from_slice(&self.0[..len]);
+        Sha256Hash(bytes)
+    }
+}
+
+impl<const N: usize> Sha256Hash<N> {
+    /// Bitwise xor, comparing distances as byte arrays orders them as numbers
+    pub fn xor_distance(&self, other: &Sha256Hash<N>) -> [u8;N] {
+        let mut distance = [0u8;N];
+        for (d, (a, b)) in distance.iter_mut().zip(self.0.iter().zip(other.0.iter())) {
+            *d = a ^ b;
+        }
+        distance
+    }
+}
+
+/// How much of the key every inner node consumes
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Radix {
+    /// 256 children per inner node
+    #[default]
+    Byte,
+    /// 16 children per inner node, the tree is twice as deep but inner nodes
+    /// and proofs carry at most 15 siblings per level
+    Nibble,
+}
+
+impl Radix {
+    fn width(self) -> usize {
+        match self {
+            Radix::Byte => 256,
+            Radix::Nibble => 16,
+        }
+    }
+
+    /// Every digit value in order, counting over `usize` so that the bound is plain
+    fn all_digits(self) -> impl Iterator<Item = u8> {
+        (0..self.width()).map(|i| i as u8)
+    }
+
+    /// Split the key in the digits consumed one per level by the inner nodes
+    fn digits(self, key: &[u8]) -> Vec<u8> {
+        match self {
+            Radix::Byte => key.to_vec(),
+            Radix::Nibble => {
+                let mut digits = Vec::with_capacity(key.len() * 2);
+                for b in key {
+                    digits.push(b >> 4);
+                    digits.push(b & 0x0f);
+                }
+                digits
+            },
+        }
+    }
+
+    /// Inverse of `digits` for a full key
+    fn key<const N: usize>(self, digits: &[u8]) -> Sha256Hash<N> {
+        let mut key = [0u8;N];
+        match self {
+            Radix::Byte => key.copy_from_slice(digits),
+            Radix::Nibble => {
+                for (b, pair) in key.iter_mut().zip(digits.chunks(2)) {
+                    *b = pair[0] << 4 | pair[1];
+                }
+            },
+        }
+        Sha256Hash(key)
+    }
+}
+
+/// What the hash of a leaf commits to
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ValueHashing {
+    /// the value bytes
+    #[default]
+    Inline,
+    /// the hash of the value, computed once, so that rehashing a leaf moved down by a split
+    /// takes the same time for any value size. The leaf is serialized with type 0x03 and
+    /// the 32 bytes of the value hash in place of the length prefixed value.
+    Digest,
+}
+
+/// What of the key the hash of a leaf commits to
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum LeafKey {
+    /// the digits below the inner nodes, the position of the leaf commits to the others
+    #[default]
+    Remaining,
+    /// all the digits of the key, so that an entry hashes the same at any depth and a leaf
+    /// moved down by a split keeps its hash. The leaves are longer and commit to the digits
+    /// above them twice, and their serialization, type 0x04 or 0x05 for 0x02 or 0x03, no
+    /// longer tells the remaining key, which is read by walking down to the leaf.
+    Full,
+}
+
+/// Children of an inner node by branch digit. Nothing depends on the iteration order of the
+/// map, walks in key order go through `InnerNode::branches`.
+#[cfg(not(feature = "btree-map"))]
+type ChildMap = HashMap<u8, Arc<Node>>;
+#[cfg(feature = "btree-map")]
+type ChildMap = BTreeMap<u8, Arc<Node>>;
+
+/// Node branching on the next digit of the key, see `Tree::root_node`
+#[derive(Debug, Clone)]
+pub struct InnerNode {
+    map: ChildMap,
+    radix: Radix,
+    /// bytes of the children hashes in the serialization
+    hash_len: usize,
+    /// serialized in place of the empty slots, truncated like the children hashes
+    empty_hash: Option<Sha256Hash>,
+    hash: Option<Sha256Hash>,
+    //father: Box<Option<Node>>,
+}
+
+/// Node holding the value of a key and the digits of the key below the inner nodes
+#[derive(Debug, Clone, Default)]
+pub struct Leaf {
+    remaining_key: Vec<u8>,
+    value: Vec<u8>,
+    /// `Some` with `ValueHashing::Digest`
+    value_hash: Option<Sha256Hash>,
+    /// `Some` with `LeafKey::Full`, all the key digits, serialized instead of `remaining_key`
+    full_key: Option<Vec<u8>>,
+    hash: Option<Sha256Hash>,
+    //father: Box<Option<Node>>,
+}
+
+/// Authenticated tree with keys of `N` bytes.
+/// Cloning a tree is cheap, nodes are shared until modified.
+#[derive(Debug, Clone)]
+pub struct Tree<const N: usize = 32> {
+    root: Option<Node>,
+    radix: Radix,
+    value_hashing: ValueHashing,
+    leaf_key: LeafKey,
+    hash_len: usize,
+    empty_hash: Option<Sha256Hash>,
+    /// length of every value, see `Tree::with_fixed_value_len`
+    fixed_value_len: Option<usize>,
+    checkpoints: Vec<Option<Node>>,
+    #[cfg(feature = "proof-cache")]
+    proof_cache: cache::ProofCache<N>,
+    #[cfg(feature = "value-codec")]
+    value_codec: Option<codec::Codec>,
+}
+
+pub type Sha256Tree = Tree<32>;
+
+/// Options of a new `Tree`, the defaults are those of `Tree::new`
+#[derive(Debug, Clone, Copy)]
+pub struct TreeBuilder<const N: usize = 32> {
+    radix: Radix,
+    value_hashing: ValueHashing,
+    leaf_key: LeafKey,
+    hash_len: usize,
+    empty_hash: Option<[u8;32]>,
+}
+
+impl<const N: usize> Default for TreeBuilder<N> {
+    fn default() -> Self {
+        TreeBuilder {
+            radix: Radix::default(),
+            value_hashing: ValueHashing::default(),
+            leaf_key: LeafKey::default(),
+            hash_len: HASH_LEN,
+            empty_hash: None,
+        }
+    }
+}
+
+impl<const N: usize> TreeBuilder<N> {
+    pub fn radix(mut self, radix: Radix) -> Self {
+        self.radix = radix;
+        self
+    }
+
+    pub fn value_hashing(mut self, value_hashing: ValueHashing) -> Self {
+        self.value_hashing = value_hashing;
+        self
+    }
+
+    /// Commit the leaves to the whole key instead of the digits below the inner nodes,
+    /// see `LeafKey::Full`
+    pub fn leaf_key(mut self, leaf_key: LeafKey) -> Self {
+        self.leaf_key = leaf_key;
+        self
+    }
+
+    /// Truncate the children hashes in the inner nodes, and so the sibling hashes in the
+    /// proofs, to `hash_len` bytes, between 1 and 32. Shorter hashes give smaller proofs
+    /// but finding a second subtree with the same truncated hash, which could be passed
+    /// off in a proof, takes about 2^(8 * hash_len) attempts instead of 2^256, and the
+    /// birthday bound halves that exponent for a prover choosing both subtrees. The root
+    /// hash is not truncated.
+    pub fn hash_len(mut self, hash_len: usize) -> Self {
+        assert!((1..=HASH_LEN).contains(&hash_len), "hash_len must be between 1 and 32");
+        self.hash_len = hash_len;
+        self
+    }
+
+    /// Serialize the empty slots of the inner nodes as a child with hash `empty_hash`
+    /// instead of a 0x00 byte, so that every slot holds a hash of the same length. The
+    /// hashes and the proofs of the tree commit to it, the serialized nodes can't be told
+    /// from nodes with all the slots populated and deserialize as such.
+    pub fn empty_hash(mut self, empty_hash: Sha256Hash) -> Self {
+        self.empty_hash = Some(empty_hash.0);
+        self
+    }
+
+    pub fn build(self) -> Tree<N> {
+        let mut tree = Tree::with_radix(self.radix);
+        tree.value_hashing = self.value_hashing;
+        tree.leaf_key = self.leaf_key;
+        tree.hash_len = self.hash_len;
+        tree.empty_hash = self.truncated_empty_hash();
+        tree
+    }
+
+    /// Only the bytes in the serialization are kept
+    fn truncated_empty_hash(&self) -> Option<Sha256Hash> {
+        self.empty_hash.map(|empty_hash| Sha256Hash(empty_hash).truncated(self.hash_len))
+    }
+
+    /// Whether inner nodes with these options are those of a tree built with this builder.
+    /// The verifiers of proofs holding inner nodes check them against the options of their
+    /// caller: a prover choosing the hash of the empty slots could pass off a populated slot
+    /// as empty.
+    pub(crate) fn builds(&self, radix: Radix, hash_len: usize, empty_hash: Option<&Sha256Hash>) -> bool {
+        self.radix == radix && self.hash_len == hash_len && self.truncated_empty_hash().as_ref() == empty_hash
+    }
+
+    /// Like `builds` for every inner node under `node`
+    pub(crate) fn builds_node(&self, node: &Node) -> bool {
+        match node {
+            Node::InnerNode(inner) => self.builds(inner.radix, inner.hash_len, inner.empty_hash.as_ref()) && inner.map.values().all(|child| self.builds_node(child)),
+            _ => true,
+        }
+    }
+}
+
+/// How `Tree::add_report` changed the shape of the tree
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum InsertOutcome {
+    /// the key split the leaf on its path, branching off it at the new inner node `split_depth` levels down
+    Inserted { split_depth: usize },
+    /// the key was in the tree, with the `old` value
+    Overwritten { old: Vec<u8> },
+    /// the key took an empty slot of an inner node, or the root of the empty tree
+    AddedChild,
+}
+
+/// Identifies a state of the tree saved by `Tree::checkpoint`
+#[derive(Debug)]
+pub struct CheckpointToken(usize);
+
+impl InnerNode {
+    fn new(map: ChildMap, radix: Radix, hash_len: usize, empty_hash: Option<Sha256Hash>) -> Self {
+        InnerNode {
+            map,
+            radix,
+            hash_len,
+            empty_hash,
+            hash:  None,
+            //father: Box::new(None),
+        }
+    }
+
+    /// Hash the serialization, feeding it to the hasher slot by slot instead of building it
+    fn update(&mut self) {
+        let mut hasher = Sha256::new();
+        hasher.input(&[0x01]);  // InnerNode type
+        hasher.input(&self.inside_len().encode_var_vec());
+        for i in self.radix.all_digits() {
+            match self.slot(i) {
+                Some(hash) => {
+                    hasher.input(&[self.hash_len as u8]);
+                    hasher.input(&hash.0[..self.hash_len]);
+                },
+                None => hasher.input(&[0x00]),
+            }
+        }
+        let mut hashed = [0u8;32];
+        hasher.result(&mut hashed);
+        self.hash = Some(Sha256Hash(hashed));
+    }
+
+    /// Hash serialized in the slot of `branch`, `None` for a 0x00 byte
+    fn slot(&self, branch: u8) -> Option<Sha256Hash> {
+        match self.map.get(&branch) {
+            Some(node) => Some(node.my_hash()),
+            None => self.empty_hash.clone(),
+        }
+    }
+
+    /// Length of the serialization after the type and the length, every slot is a 0x00 or
+    /// the length followed by the hash
+    fn inside_len(&self) -> usize {
+        match self.empty_hash {
+            None => self.radix.width() + self.map.len() * self.hash_len,
+            Some(_) => self.radix.width() * (1 + self.hash_len),
+        }
+    }
+
+    /// Branch digits of the children, in order
+    fn branches(&self) -> Vec<u8> {
+        let mut branches: Vec<u8> = self.map.keys().cloned().collect();
+        branches.sort();
+        branches
+    }
+}
+
+impl Leaf {
+    fn new(remaining_key: Vec<u8>, value : Vec<u8>) -> Self {
+        Leaf::with_value_hashing(remaining_key, value, ValueHashing::Inline)
+    }
+
+    fn with_value_hashing(remaining_key: Vec<u8>, value: Vec<u8>, value_hashing: ValueHashing) -> Self {
+        Leaf::keyed(remaining_key, None, value, value_hashing)
+    }
+
+    /// A leaf committing to `full_key` if `Some`, see `LeafKey::Full`
+    fn keyed(remaining_key: Vec<u8>, full_key: Option<Vec<u8>>, value: Vec<u8>, value_hashing: ValueHashing) -> Self {
+        let value_hash = match value_hashing {
+            ValueHashing::Inline => None,
+            ValueHashing::Digest => Some(hash(&value)),
+        };
+        Leaf::from_parts(remaining_key, full_key, value, value_hash)
+    }
+
+    /// `value_hash` must be the hash of `value` and `full_key` end with `remaining_key`, it's not checked
+    fn from_parts(remaining_key: Vec<u8>, full_key: Option<Vec<u8>>, value: Vec<u8>, value_hash: Option<Sha256Hash>) -> Self {
+        let mut leaf = Leaf {
+            remaining_key,
+            value,
+            value_hash,
+            full_key,
+            hash: None,
+            //father: Box::new(None),
+        };
+        leaf.hash = Some(hash(&leaf.serialize()));
+        leaf
+    }
+
+    /// The leaf with the shorter or longer `remaining_key` of another depth. The value hash is
+    /// reused, moving a leaf doesn't hash the value again, nor the leaf with `LeafKey::Full`.
+    fn moved(self, remaining_key: Vec<u8>) -> Self {
+        match self.full_key {
+            Some(_) => Leaf { remaining_key, ..self },
+            None => Leaf::from_parts(remaining_key, None, self.value, self.value_hash),
+        }
+    }
+
+    pub fn value_hashing(&self) -> ValueHashing {
+        match self.value_hash {
+            None => ValueHashing::Inline,
+            Some(_) => ValueHashing::Digest,
+        }
+    }
+
+    pub fn leaf_key(&self) -> LeafKey {
+        match self.full_key {
+            None => LeafKey::Remaining,
+            Some(_) => LeafKey::Full,
+        }
+    }
+}
+
+/// A node of the tree, reached through `Tree::root_node`
+#[derive(Debug, Clone)]
+pub enum Node {
+    InnerNode(InnerNode),
+    Leaf(Leaf),
+    Pruned(Sha256Hash),  // only in PartialTree
+}
+
+pub trait Serializable {
+    fn serialize(&self) -> Vec<u8>;
+
+    /// Number of bytes `serialize` produces
+    fn serialized_len(&self) -> usize {
+        self.serialize().len()
+    }
+}
+
+/// Length of a type byte followed by the length prefixed `inside`
+fn node_len(inside: usize) -> usize {
+    1 + inside.required_space() + inside
+}
+
+impl Serializable for Leaf {
+    fn serialize(&self) -> Vec<u8> {
+        let mut result = Vec::new();
+        let mut inside = Vec::new();
+
+        let key = self.full_key.as_ref().unwrap_or(&self.remaining_key);
+        inside.extend( key.len().encode_var_vec() );
+        inside.extend( key.clone() );
+
+        // with the full key the types are 0x04 and 0x05
+        let full_key = if self.full_key.is_some() { 2 } else { 0 };
+        match self.value_hash {
+            None => {
+                result.push(0x02 + full_key);  // Leaf type
+                inside.extend( self.value.len().encode_var_vec() );
+                inside.extend( self.value.clone() );
+            },
+            Some(ref value_hash) => {
+                result.push(0x03 + full_key);  // Leaf with value hash type
+                inside.extend( &value_hash.0 );
+            },
+        }
+
+        result.extend( inside.len().encode_var_vec() );
+        result.extend( inside);
+
+        result
+    }
+
+    fn serialized_len(&self) -> usize {
+        let key = self.full_key.as_ref().unwrap_or(&self.remaining_key).len();
+        let value = match self.value_hash {
+            None => self.value.len().required_space() + self.value.len(),
+            Some(_) => 32,
+        };
+        node_len(key.required_space() + key + value)
+    }
+}
+
+impl Serializable for Node {
+    fn serialize(&self) -> Vec<u8> {
+        match self {
+            Node::InnerNode(inner) => inner.serialize(),
+            Node::Leaf(leaf) => leaf.serialize(),
+            Node::Pruned(_) => unreachable!("a pruned node is known only by its hash"),
+        }
+    }
+
+    fn serialized_len(&self) -> usize {
+        match self {
+            Node::InnerNode(inner) => inner.serialized_len(),
+            Node::Leaf(leaf) => leaf.serialized_len(),
+            Node::Pruned(_) => unreachable!("a pruned node is known only by its hash"),
+        }
+    }
+}
+
+
+impl Serializable for InnerNode {
+    fn serialize(&self) -> Vec<u8> {
+        let mut result = Vec::new();
+        result.push(0x01);  // InnerNode type
+
+        let mut inside = Vec::new();
+        for i in self.radix.all_digits() {
+            match self.slot(i) {
+                Some(hash) => {
+                    let vec = hash.0[..self.hash_len].to_vec();
+                    inside.extend(vec.len().encode_var_vec());
+                    inside.extend(vec);
+                },
+                None => inside.push(0x00),
+            };
+        }
+        result.extend( inside.len().encode_var_vec() );
+        result.extend( inside);
+
+        result
+    }
+
+    fn serialized_len(&self) -> usize {
+        node_len(self.inside_len())
+    }
+}
+
+trait Hashable {
+    fn my_hash(&self) -> Sha256Hash;
+}
+
+impl Hashable for Leaf {  //this should be a dependent trait of serializable!
+    fn my_hash(&self) -> Sha256Hash {
+        self.hash.clone().unwrap()
+    }
+}
+impl Hashable  for InnerNode {  //this should be a dependent trait of serializable!
+    fn my_hash(&self) -> Sha256Hash {
+        self.hash.clone().unwrap()
+    }
+}
+
+fn hash(bytes: &[u8]) -> Sha256Hash {
+    let mut hashed = [0u8;32];
+    let mut hasher = Sha256::new();
+    hasher.input(bytes);
+    hasher.result(&mut hashed);
+    Sha256Hash(hashed)
+}
+
+impl Node {
+
+    /// Add the key, leaving stale the hashes on its path which `update_hashes` recomputes.
+    /// `full_key` holds all the digits of the key with `LeafKey::Full`.
+    #[allow(clippy::too_many_arguments)]
+    fn add( &mut self, key: Vec<u8> , value: Vec<u8>, radix: Radix, value_hashing: ValueHashing, full_key: Option<&[u8]>, hash_len: usize, empty_hash: &Option<Sha256Hash>) -> Result<(), Pruned> {
+        let new_node = match self {
+            Node::Leaf(leaf) => {
+                if leaf.remaining_key == key {
+                    *leaf = Leaf::keyed(key, full_key.map(<[u8]>::to_vec), value, value_hashing);
+                    return Ok(());
+                }
+                // keys of the same length that aren't equal differ before running out of digits
+                let common = leaf.remaining_key.iter().zip(&key).take_while(|(a, b)| a == b).count();
+                // the old leaf is moved down once, under a chain of inner nodes for the shared digits
+                let mut old = mem::take(leaf);
+                let mut remaining_key = mem::take(&mut old.remaining_key);
+                let branch = remaining_key[common];
+                remaining_key.drain(..=common);
+                let mut map = ChildMap::new();
+                map.insert(branch, Arc::new(Node::Leaf(old.moved(remaining_key))));
+                let new_leaf = Leaf::keyed(key[common + 1..].to_vec(), full_key.map(<[u8]>::to_vec), value, value_hashing);
+                map.insert(key[common], Arc::new(Node::Leaf(new_leaf)));
+                let mut new_node = Node::InnerNode(InnerNode::new(map, radix, hash_len, empty_hash.clone()));
+                for digit in key[..common].iter().rev() {
+                    let mut map = ChildMap::new();
+                    map.insert(*digit, Arc::new(new_node));
+                    new_node = Node::InnerNode(InnerNode::new(map, radix, hash_len, empty_hash.clone()));
+                }
+                Some(new_node)
+            },
+            Node::InnerNode(inner) => {
+                let (a, b) = key.split_at(1);
+                match inner.map.get_mut(&a[0]) {
+                    Some(node) => Arc::make_mut(node).add(b.to_vec(), value, radix, value_hashing, full_key, hash_len, empty_hash)?,
+                    None => {
+                        let new_node = Node::Leaf(Leaf::keyed(b.to_vec(), full_key.map(<[u8]>::to_vec), value, value_hashing));
+                        inner.map.insert(a[0], Arc::new(new_node));
+                    }
+                }
+                inner.hash = None;
+                None
+            },
+            Node::Pruned(hash) => return Err(Pruned(hash.clone())),
+        };
+        if let Some(new_node) = new_node {
+            *self = new_node;
+        }
+        Ok(())
+    }
+
+    fn get(&self, key: Vec<u8>)  -> Result<Option<Vec<u8>>, Pruned> {
+        match self {
+            Node::Leaf(leaf) => {
+                if leaf.remaining_key == key {
+                    Ok(Some(leaf.value.clone()))
+                } else {
+                    Ok(None)
+                }
+            },
+            Node::InnerNode(inner) => {
+                let (a, b) = key.split_at(1);
+                match inner.map.get(&a[0]) {
+                    None => Ok(None),
+                    Some(node) => node.get(b.to_vec()),
+                }
+            }
+            Node::Pruned(hash) => Err(Pruned(hash.clone())),
+        }
+    }
+
+    /// Replace every value with `f(value)` in key order, leaving stale the inner hashes
+    fn map_values(&mut self, f: &mut dyn FnMut(&[u8]) -> Vec<u8>) {
+        match self {
+            Node::Leaf(leaf) => {
+                let value = f(&leaf.value);
+                *leaf = Leaf::keyed(mem::take(&mut leaf.remaining_key), leaf.full_key.take(), value, leaf.value_hashing());
+            },
+            Node::InnerNode(inner) => {
+                for branch in inner.branches() {
+                    Arc::make_mut(inner.map.get_mut(&branch).unwrap()).map_values(f);
+                }
+                inner.hash = None;
+            },
+            Node::Pruned(_) => (),
+        }
+    }
+
+    /// Remove the entries whose remaining key starts with `prefix`, an inner node left with a
+    /// single leaf becomes that leaf, as if the removed entries were never added. Returns
+    /// `true` if nothing is left of the node. The hashes on the path are left stale.
+    fn remove_prefix(&mut self, prefix: &[u8]) -> bool {
+        let inner = match self {
+            Node::Leaf(leaf) => return leaf.remaining_key.starts_with(prefix),
+            Node::InnerNode(_) if prefix.is_empty() => return true,
+            Node::InnerNode(inner) => inner,
+            Node::Pruned(_) => return false,
+        };
+        let branch = prefix[0];
+        let emptied = match inner.map.get_mut(&branch) {
+            None => return false,
+            Some(child) => Arc::make_mut(child).remove_prefix(&prefix[1..]),
+        };
+        if emptied {
+            inner.map.remove(&branch);
+        }
+        inner.hash = None;
+        if inner.map.is_empty() {
+            return true;
+        }
+        if inner.map.len() == 1 {
+            let (branch, child) = mem::take(&mut inner.map).into_iter().next().unwrap();
+            match Arc::try_unwrap(child).unwrap_or_else(|child| (*child).clone()) {
+                Node::Leaf(leaf) => {
+                    let mut remaining_key = vec![branch];
+                    remaining_key.extend(&leaf.remaining_key);
+                    *self = Node::Leaf(leaf.moved(remaining_key));
+                },
+                child => {
+                    inner.map.insert(branch, Arc::new(child));
+                },
+            }
+        }
+        false
+    }
+
+    fn is_stale(&self) -> bool {
+        match self {
+            Node::InnerNode(inner) => inner.hash.is_none(),
+            _ => false,
+        }
+    }
+
+    fn update_hashes(&mut self) {
+        if let Node::InnerNode(inner) = self {
+            if inner.hash.is_none() {
+                for child in inner.map.values_mut() {
+                    if child.is_stale() {
+                        Arc::make_mut(child).update_hashes();
+                    }
+                }
+                inner.update();
+            }
+        }
+    }
+
+    fn my_hash(&self) -> Sha256Hash {
+        match self {
+            Node::Leaf(ref leaf) => leaf.my_hash(),
+            Node::InnerNode(ref inner) => inner.my_hash(),
+            Node::Pruned(ref hash) => hash.clone(),
+        }
+    }
+}
+
+/// Hash of the serialization of an empty tree, `[0x00]`
+const EMPTY_ROOT: Sha256Hash = Sha256Hash([
+    0x6e, 0x34, 0x0b, 0x9c, 0xff, 0xb3, 0x7a, 0x98, 0x9c, 0xa5, 0x44, 0xe6, 0xbb, 0x78, 0x0a, 0x2c,
+    0x78, 0x90, 0x1d, 0x3f, 0xb3, 0x37, 0x38, 0x76, 0x85, 0x11, 0xa3, 0x06, 0x17, 0xaf, 0xa0, 0x1d,
+]);
+
+/// Bytes of a hash, the children hashes are truncated to `TreeBuilder::hash_len`
+const HASH_LEN: usize = 32;
+
+const NO_PRUNED: &str = "a Tree never contains pruned nodes";
+
+impl<const N: usize> Tree<N> {
+    /// Root hash of an empty tree
+    pub const EMPTY_ROOT: Sha256Hash = EMPTY_ROOT;
+
+    pub fn empty_root() -> Sha256Hash {
+        EMPTY_ROOT
+    }
+
+    /// Check that raw bytes are a key of this tree, `N` bytes long. It's advisory, the tree
+    /// doesn't call it: the methods taking `[u8;N]` keys can't be given a key of another
+    /// length, this is for keys read as slices, `Sha256Hash::try_from` checks with it.
+    pub fn validate_key(key: &[u8]) -> Result<(), KeyError> {
+        if key.len() == N {
+            Ok(())
+        } else if key.is_empty() {
+            Err(KeyError::Empty)
+        } else {
+            Err(KeyError::WrongLength { expected: N, found: key.len() })
+        }
+    }
+
+    /// Index of the first byte where the keys differ, `None` if they are equal. Two keys
+    /// share the inner nodes above this byte, twice as many with `Radix::Nibble`.
+    pub fn first_diverging_byte(a: &Sha256Hash<N>, b: &Sha256Hash<N>) -> Option<usize> {
+        a.0.iter().zip(b.0.iter()).position(|(a, b)| a != b)
+    }
+
+    /// An empty tree branching on whole bytes of the key, same as `Tree::default()`
+    pub fn new() -> Self {
+        Tree::with_radix(Radix::default())
+    }
+
+    /// Configure the radix and the value hashing of an empty tree
+    pub fn builder() -> TreeBuilder<N> {
+        TreeBuilder::default()
+    }
+
+    /// The options the tree is built with, the verifiers of its proofs take them
+    pub fn options(&self) -> TreeBuilder<N> {
+        TreeBuilder {
+            radix: self.radix,
+            value_hashing: self.value_hashing,
+            leaf_key: self.leaf_key,
+            hash_len: self.hash_len,
+            empty_hash: self.empty_hash.as_ref().map(|empty_hash| empty_hash.0),
+        }
+    }
+
+    /// An empty tree with the empty slots hashing as `empty_hash`, see `TreeBuilder::empty_hash`
+    pub fn with_empty_hash(empty_hash: Sha256Hash) -> Self {
+        Tree::builder().empty_hash(empty_hash).build()
+    }
+
+    /// An empty tree accepting only values of `len` bytes, so that the leaves and the proofs
+    /// of the keys at the same depth have the same size. Adding a value of another length
+    /// panics, `try_add` returns an error instead.
+    pub fn with_fixed_value_len(len: usize) -> Self {
+        Tree {
+            fixed_value_len: Some(len),
+            ..Tree::new()
+        }
+    }
+
+    pub fn with_radix(radix: Radix) -> Self {
+        Tree {
+            root: None,
+            radix,
+            value_hashing: ValueHashing::default(),
+            leaf_key: LeafKey::default(),
+            hash_len: HASH_LEN,
+            empty_hash: None,
+            fixed_value_len: None,
+            checkpoints: Vec::new(),
+            #[cfg(feature = "proof-cache")]
+            proof_cache: cache::ProofCache::default(),
+            #[cfg(feature = "value-codec")]
+            value_codec: None,
+        }
+    }
+
+    /// Keys are taken as `&Sha256Hash<N>` or as bare `[u8;N]` arrays, by value or by reference.
+    /// Panics if the value is not of the length fixed by `Tree::with_fixed_value_len`, use
+    /// `try_add` to get a `ValueLenError` instead.
+    pub fn add<K: Borrow<[u8;N]>>(&mut self, key: K, value: Vec<u8>) {
+        self.insert(key.borrow(), value);
+        self.update_hashes();
+    }
+
+    /// Like `add`, returning an error if the tree has a fixed value length and `value` is of
+    /// another length.
+    pub fn try_add<K: Borrow<[u8;N]>>(&mut self, key: K, value: Vec<u8>) -> Result<(), ValueLenError> {
+        self.check_value_len(&value)?;
+        self.add(key, value);
+        Ok(())
+    }
+
+    fn check_value_len(&self, value: &[u8]) -> Result<(), ValueLenError> {
+        match self.fixed_value_len {
+            Some(expected) if value.len() != expected => Err(ValueLenError { expected, found: value.len() }),
+            _ => Ok(()),
+        }
+    }
+
+    fn assert_value_len(&self, value: &[u8]) {
+        if let Err(err) = self.check_value_len(value) {
+            panic!("value of {} bytes in a tree of {} bytes values", err.found, err.expected);
+        }
+    }
+
+    /// Add the key only if it's not in the tree, returning whether it was added.
+    pub fn insert_if_absent<K: Borrow<[u8;N]>>(&mut self, key: K, value: Vec<u8>) -> bool {
+        let key = key.borrow();
+        if self.get(key).is_some() {
+            return false;
+        }
+        self.add(key, value);
+        true
+    }
+
+    /// Like `add`, telling whether the key split a leaf, to monitor the nodes rewritten by the inserts.
+    pub fn add_report<K: Borrow<[u8;N]>>(&mut self, key: K, value: Vec<u8>) -> InsertOutcome {
+        let key = key.borrow();
+        let digits = self.radix.digits(key);
+        let outcome = match self.walk(&digits) {
+            (_, None) => InsertOutcome::AddedChild,
+            (depth, Some(leaf)) if leaf.remaining_key[..] == digits[depth..] => InsertOutcome::Overwritten { old: self.decode_value(&leaf.value) },
+            (depth, Some(leaf)) => {
+                let common = leaf.remaining_key.iter().zip(&digits[depth..]).take_while(|(a, b)| a == b).count();
+                InsertOutcome::Inserted { split_depth: depth + common }
+            },
+        };
+        self.add(key, value);
+        outcome
+    }
+
+    /// Add without updating the hashes, many inserts can share a final `update_hashes`
+    fn insert(&mut self, key: &[u8;N], value: Vec<u8>) {
+        #[cfg(feature = "tracing")]
+        self.trace_insert(key);
+        self.assert_value_len(&value);
+        let key = self.radix.digits(key);
+        let value = self.encode_value(value);
+        let full_key = match self.leaf_key {
+            LeafKey::Remaining => None,
+            LeafKey::Full => Some(key.clone()),
+        };
+        match self.root {
+            None => {
+                let new_node = Node::Leaf(Leaf::keyed(key, full_key, value, self.value_hashing));
+                self.root = Some(new_node);
+            },
+            Some(ref mut root) => {
+                root.add(key, value, self.radix, self.value_hashing, full_key.as_deref(), self.hash_len, &self.empty_hash).expect(NO_PRUNED);
+
+            }
+        }
+    }
+
+    /// An empty tree with the same options, with keys of `M` bytes
+    fn empty_like<const M: usize>(&self) -> Tree<M> {
+        Tree {
+            #[cfg(feature = "value-codec")]
+            value_codec: self.value_codec.clone(),
+            empty_hash: self.empty_hash.clone(),
+            fixed_value_len: self.fixed_value_len,
+            ..TreeBuilder { radix: self.radix, value_hashing: self.value_hashing, leaf_key: self.leaf_key, hash_len: self.hash_len, empty_hash: None }.build()
+        }
+    }
+
+    /// The value as stored in the leaves, encoded by the value codec if any
+    fn encode_value(&self, value: Vec<u8>) -> Vec<u8> {
+        #[cfg(feature = "value-codec")]
+        if let Some(ref codec) = self.value_codec {
+            return codec.encode(&value);
+        }
+        value
+    }
+
+    /// Inverse of `encode_value`
+    fn decode_value(&self, value: &[u8]) -> Vec<u8> {
+        #[cfg(feature = "value-codec")]
+        if let Some(ref codec) = self.value_codec {
+            return codec.decode(value);
+        }
+        value.to_vec()
+    }
+
+    fn update_hashes(&mut self) {
+        #[cfg(feature = "proof-cache")]
+        self.proof_cache.clear();
+        if let Some(ref mut root) = self.root {
+            root.update_hashes();
+        }
+        #[cfg(feature = "paranoid")]
+        self.debug_assert_valid();
+    }
+
+    pub fn get<K: Borrow<[u8;N]>>(&self, key: K) -> Option<Vec<u8>> {
+        let key = key.borrow();
+        #[cfg(feature = "tracing")]
+        self.trace_lookup("get", key);
+        match self.root {
+            None => None,
+            Some(ref root) => root.get(self.radix.digits(key)).expect(NO_PRUNED).map(|value| self.decode_value(&value)),
+        }
+    }
+
+    pub fn hash(&self) -> Sha256Hash {
+        match self.root {
+            None => EMPTY_ROOT,
+            Some(ref root) => root.my_hash(),
+        }
+    }
+
+    /// Whether the root hash is `expected`, comparing with the hash kept in the root node
+    /// without cloning it. The hashes are updated after every change, nothing is recomputed.
+    pub fn root_is(&self, expected: &Sha256Hash) -> bool {
+        let root = match self.root {
+            None => return *expected == EMPTY_ROOT,
+            Some(Node::Leaf(ref leaf)) => leaf.hash.as_ref(),
+            Some(Node::InnerNode(ref inner)) => inner.hash.as_ref(),
+            Some(Node::Pruned(ref hash)) => Some(hash),
+        };
+        root == Some(expected)
+    }
+
+    /// The bytes hashed to the root hash, the serialization of the root node or `[0x00]` for
+    /// the empty tree, the same as `serialize`. To compare the root with other implementations.
+    pub fn root_preimage(&self) -> Vec<u8> {
+        self.serialize()
+    }
+
+    /// Replace every value with `f(value)`, recomputing the hashes once at the end.
+    pub fn map_values<F: FnMut(&[u8]) -> Vec<u8>>(&mut self, mut f: F) {
+        let options = self.empty_like::<N>();
+        let mut f = |value: &[u8]| {
+            let value = f(&options.decode_value(value));
+            options.assert_value_len(&value);
+            options.encode_value(value)
+        };
+        if let Some(ref mut root) = self.root {
+            root.map_values(&mut f);
+        }
+        self.update_hashes();
+    }
+
+    /// Move the entries whose key starts with `prefix` to a new tree, with the same options.
+    pub fn split_off(&mut self, prefix: &[u8]) -> Tree<N> {
+        let mut other = self.empty_like();
+        let entries = self.entries_under_prefix(prefix);
+        if entries.is_empty() {
+            return other;
+        }
+        other.extend(entries);
+        let prefix = self.radix.digits(prefix);
+        if self.root.as_mut().unwrap().remove_prefix(&prefix) {
+            self.root = None;
+        }
+        self.update_hashes();
+        other
+    }
+
+    /// Swap in a tree with the same options holding `entries`, built before the swap, and
+    /// return the previous tree with its checkpoints.
+    pub fn replace<I: IntoIterator<Item = (Sha256Hash<N>, Vec<u8>)>>(&mut self, entries: I) -> Tree<N> {
+        let mut tree = self.empty_like();
+        tree.extend(entries);
+        mem::replace(self, tree)
+    }
+
+    pub fn root_hash_hex(&self) -> String {
+        HEXLOWER.encode(&self.hash().0)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.root.is_none()
+    }
+
+    /// Save the current state, nodes are shared with the saved state until modified.
+    pub fn checkpoint(&mut self) -> CheckpointToken {
+        self.checkpoints.push(self.root.clone());
+        CheckpointToken(self.checkpoints.len() - 1)
+    }
+
+    /// Restore the state saved by `token`, releasing it and every checkpoint taken after it.
+    pub fn rollback(&mut self, token: CheckpointToken) {
+        assert!(token.0 < self.checkpoints.len(), "checkpoint already released");
+        self.root = self.checkpoints.drain(token.0..).next().unwrap();
+        #[cfg(feature = "proof-cache")]
+        self.proof_cache.clear();
+    }
+
+    /// Keep the changes made after `token`, releasing it and every checkpoint taken after it.
+    /// Writes no longer need to copy nodes shared with the released states.
+    pub fn commit(&mut self, token: CheckpointToken) {
+        assert!(token.0 < self.checkpoints.len(), "checkpoint already released");
+        self.checkpoints.truncate(token.0);
+    }
+}
+
+impl<const N: usize> Default for Tree<N> {
+    fn default() -> Self {
+        Tree::new()
+    }
+}
+
+impl<const N: usize> Extend<(Sha256Hash<N>, Vec<u8>)> for Tree<N> {
+    fn extend<I: IntoIterator<Item = (Sha256Hash<N>, Vec<u8>)>>(&mut self, iter: I) {
+        for (key, value) in iter {
+            self.insert(&key.0, value);
+        }
+        self.update_hashes();
+    }
+}
+
+impl<const N: usize> FromIterator<(Sha256Hash<N>, Vec<u8>)> for Tree<N> {
+    fn from_iter<I: IntoIterator<Item = (Sha256Hash<N>, Vec<u8>)>>(iter: I) -> Self {
+        let mut tree = Tree::default();
+        tree.extend(iter);
+        tree
+    }
+}
+
+impl<'a, const N: usize> From<&'a [(Sha256Hash<N>, Vec<u8>)]> for Tree<N> {
+    fn from(entries: &'a [(Sha256Hash<N>, Vec<u8>)]) -> Self {
+        entries.iter().cloned().collect()
+    }
+}
+
+impl<const N: usize> Serializable for Tree<N> {
+    fn serialize(&self) -> Vec<u8> {
+        match &self.root {
+            None => vec![0x00],
+            Some(root) => root.serialize()
+        }
+    }
+
+    fn serialized_len(&self) -> usize {
+        match &self.root {
+            None => 1,
+            Some(root) => root.serialized_len()
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use ::*;
+    use integer_encoding::VarInt;
+    use data_encoding::HEXLOWER;
+    use rand::{RngCore, SeedableRng};
+    use rand::prng::XorShiftRng;
+    use std::alloc::{GlobalAlloc, Layout, System};
+    use std::cell::Cell;
+
+    /// Allocations of at least this size are counted, like the values of `fake_bench_add_splits`
+    const BIG_ALLOC: usize = 1024;
+
+    thread_local! {
+        static BIG_ALLOCS: Cell<usize> = const { Cell::new(0) };
+    }
+
+    /// Counts the big allocations of every thread, so that tests running in parallel don't
+    /// count each other's
+    struct CountingAlloc;
+
+    unsafe impl GlobalAlloc for CountingAlloc {
+        unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+            if layout.size() >= BIG_ALLOC {
+                let _ = BIG_ALLOCS.try_with(|count| count.set(count.get() + 1));
+            }
+            System.alloc(layout)
+        }
+
+        unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+            System.dealloc(ptr, layout)
+        }
+    }
+
+    #[global_allocator]
+    static ALLOCATOR: CountingAlloc = CountingAlloc;
+
+    fn random_entries(n: usize) -> Vec<(Sha256Hash, Vec<u8>)> {
+        let mut rng = XorShiftRng::from_seed([3u8;16]);
+        let mut entries = Vec::new();
+        for _ in 0..n {
+            let mut key = [0u8;32];
+            let mut value = [0u8;8];
+            rng.fill_bytes(&mut key);
+            rng.fill_bytes(&mut value);
+            entries.push((Sha256Hash(key), value.to_vec()));
+        }
+        entries
+    }
+
+    #[test]
+    fn fake_bench_add_splits() {
+        // pairs of keys sharing all but the last byte, the second splits the leaf of the first 28 times
+        let mut entries = Vec::new();
+        for i in 0..500u32 {
+            let mut key = [0u8;32];
+            key[..4].copy_from_slice(&hash(&i.to_be_bytes()).0[..4]);
+            entries.push((Sha256Hash(key), vec![0u8;BIG_ALLOC]));
+            key[31] = 1;
+            entries.push((Sha256Hash(key), vec![1u8;BIG_ALLOC]));
+        }
+        // digest leaves serialize the hash of the value, so a big allocation would be a copy of a value
+        let mut tree: Tree = Tree::builder().value_hashing(ValueHashing::Digest).build();
+        for (key, value) in entries.iter().step_by(2) {
+            tree.add(key, value.clone());
+        }
+        let values: Vec<_> = entries.iter().skip(1).step_by(2).map(|(key, value)| (key, value.clone())).collect();
+        let before = BIG_ALLOCS.with(Cell::get);
+        for (key, value) in values {
+            tree.add(key, value);
+        }
+        let big_allocs = BIG_ALLOCS.with(Cell::get) - before;
+        // the paranoid checks copy the values to hash the leaves again
+        assert!(cfg!(feature = "paranoid") || big_allocs == 0, "the splits copied the values of the leaves");
+        entries.sort();
+        let mut batch = Tree::builder().value_hashing(ValueHashing::Digest).build();
+        batch.insert_batch_sorted(&entries);
+        assert_eq!(tree.hash(), batch.hash());
+    }
+
+    #[test]
+    fn test_add_random() {
+        let entries = random_entries(2000);
+        let mut tree = Tree::default();
+        for (key, value) in &entries {
+            tree.add(key, value.clone());
+        }
+        assert_eq!(tree.root_hash_hex(), "2cc93a1a0a3707535f1ce33f9e545f1330be7d14922d98e3d80d7e5773b6e236");
+        for (key, value) in &entries {
+            assert_eq!(tree.get(key).as_ref(), Some(value));
+        }
+        assert_eq!(tree.to_entries().len(), 2000);
+    }
+
+    #[test]
+    fn test_tree() {
+        let mut tree= Tree::default();
+        assert!(tree.is_empty());
+        let a1 = Sha256Hash([0u8;32]);
+        assert!(tree.get(&a1).is_none());
+        //println!("{:?}",tree.serialize());
+
+        let a2 = [0x02].to_vec();
+        tree.add(&a1, a2.clone());
+        assert!(!tree.is_empty());
+        assert_eq!(tree.get(&a1).unwrap(), a2);
+        //println!("{:?}",tree.serialize());
+
+        let b1 = Sha256Hash([1u8;32]);
+        let  b2 = [0x12].to_vec();
+        tree.add(&b1, b2.clone());
+        assert_eq!(tree.get(&a1).unwrap(), a2);
+        assert_eq!(tree.get(&b1).unwrap(), b2);
+        //println!("{:?}",tree);
+        //println!("{:?}",tree.serialize());
+
+        let c1 = Sha256Hash([2u8;32]);
+        let c2 = [0x01].to_vec();
+        tree.add(&c1, c2.clone());
+        assert_eq!(tree.get(&a1).unwrap(), a2);
+        assert_eq!(tree.get(&b1).unwrap(), b2);
+        assert_eq!(tree.get(&c1).unwrap(), c2);
+
+
+        let d1 = Sha256Hash([3u8;32]);
+        let d2 = [0x31].to_vec();
+        tree.add(&d1, d2.clone());
+        assert_eq!(tree.get(&a1).unwrap(), a2);
+        assert_eq!(tree.get(&b1).unwrap(), b2);
+        assert_eq!(tree.get(&c1).unwrap(), c2);
+        assert_eq!(tree.get(&d1).unwrap(), d2);
+
+        //println!("{:?}",tree.serialize());
+    }
+
+    #[test]
+    fn test_shared_prefix_and_overwrite() {
+        let mut tree= Tree::default();
+        let mut a = [0u8;32];
+        let mut b = [0u8;32];
+        a[31] = 1;
+        b[31] = 2;
+        tree.add(Sha256Hash(a), [0x01].to_vec());
+        tree.add(Sha256Hash(b), [0x02].to_vec());
+        assert_eq!(tree.get(Sha256Hash(a)).unwrap(), [0x01]);
+        assert_eq!(tree.get(Sha256Hash(b)).unwrap(), [0x02]);
+        assert!(tree.get(Sha256Hash([0u8;32])).is_none());
+
+        tree.add(Sha256Hash(a), [0x03].to_vec());
+        assert_eq!(tree.get(Sha256Hash(a)).unwrap(), [0x03]);
+        assert_eq!(tree.get(Sha256Hash(b)).unwrap(), [0x02]);
+    }
+
+    #[test]
+    fn test_add_root_hashes() {
+        let mut tree = Tree::new();
+        let mut nibble = Tree::with_radix(Radix::Nibble);
+        for i in 0u8..40 {
+            let mut key = [i / 4; 32];
+            key[31] = i;
+            tree.add(Sha256Hash(key), vec![i]);
+            nibble.add(Sha256Hash(key), vec![i]);
+        }
+        tree.add(Sha256Hash([0u8;32]), vec![0xff]);
+        nibble.add(Sha256Hash([0u8;32]), vec![0xff]);
+        assert_eq!(tree.root_hash_hex(), "f36666326e098803af437150624a99d7ba89c8998df0c78226cae12210056854");
+        assert_eq!(nibble.root_hash_hex(), "0e23e8b8ee45b1cdc6ada6d50baa21411b2c7eba4ead4ec102a0cd0956074398");
+    }
+
+    #[test]
+    fn test_replace() {
+        let mut tree: Tree = Tree::builder().radix(Radix::Nibble).hash_len(16).build();
+        for i in 0u8..10 {
+            tree.add(hash(&[i]), vec![i]);
+        }
+        let token = tree.checkpoint();
+        let entries = tree.to_entries();
+        let root = tree.hash();
+        let new: Vec<_> = (10u8..20).map(|i| (hash(&[i]), vec![i])).collect();
+        let mut old = tree.replace(new.clone());
+        let mut expected = Tree::builder().radix(Radix::Nibble).hash_len(16).build();
+        expected.extend(new.clone());
+        assert_eq!(tree.hash(), expected.hash());
+        assert_eq!(tree.to_entries(), Tree::from_entries(new).to_entries());
+        assert_eq!(old.hash(), root);
+        assert_eq!(old.to_entries(), entries);
+        // the checkpoints go with the previous tree
+        old.add(hash(&[0xff]), vec![]);
+        old.rollback(token);
+        assert_eq!(old.hash(), root);
+
+        assert_eq!(tree.replace(vec![]).hash(), expected.hash());
+        assert!(tree.is_empty());
+    }
+
+    #[test]
+    fn test_split_off() {
+        for radix in [Radix::Byte, Radix::Nibble] {
+            let mut tree = Tree::with_radix(radix);
+            for i in 0u32..1000 {
+                tree.add(hash(&i.to_be_bytes()), vec![i as u8]);
+            }
+            let mut entries = tree.to_entries();
+            let prefix = entries[500].0 .0[0];
+            let original = tree.clone();
+            let other = tree.split_off(&[prefix]);
+            assert!(other.to_entries().len() > 1);
+            assert_eq!(original.entries_under_prefix(&[prefix]), other.to_entries());
+            assert!(tree.to_entries().iter().all(|(key, _)| key.0[0] != prefix));
+            let mut joined = tree.to_entries();
+            joined.extend(other.to_entries());
+            joined.sort();
+            assert_eq!(joined, entries);
+
+            // the same tree as built without the moved entries
+            let mut rebuilt = Tree::with_radix(radix);
+            rebuilt.extend(tree.to_entries());
+            assert_eq!(rebuilt.hash(), tree.hash());
+            assert_eq!(tree.verify_hashes(), Ok(()));
+
+            assert!(tree.split_off(&[prefix]).is_empty());
+            entries.retain(|(key, _)| key.0[0] != prefix);
+            let all = tree.split_off(&[]);
+            assert!(tree.is_empty());
+            assert_eq!(all.to_entries(), entries);
+        }
+
+        // the leaf left alone under the root branch moves up
+        let mut tree = Tree::default();
+        let mut b = [1u8;32];
+        b[1] = 2;
+        for key in &[[1u8;32], b, [3u8;32]] {
+            tree.add(Sha256Hash(*key), vec![key[1]]);
+        }
+        assert_eq!(tree.split_off(&[1, 2]).to_entries(), vec![(Sha256Hash(b), vec![2])]);
+        assert_eq!(tree.hash(), Tree::from_entries(vec![(Sha256Hash([1u8;32]), vec![1]), (Sha256Hash([3u8;32]), vec![3])]).hash());
+    }
+
+    #[test]
+    fn test_bare_array_keys() {
+        let mut tree = Tree::default();
+        let key = [7u8;32];
+        tree.add(key, vec![0x07]);
+        tree.add([8u8;32], vec![0x08]);
+        assert_eq!(tree.get(key), Some(vec![0x07]));
+        assert_eq!(tree.get([8u8;32]), Some(vec![0x08]));
+        assert_eq!(tree.get(Sha256Hash(key)), Some(vec![0x07]));
+        assert_eq!(tree.prove(key), tree.prove(Sha256Hash(key)));
+        assert_eq!(tree.get([9u8;32]), None);
+    }
+
+    #[test]
+    fn test_insert_if_absent() {
+        let mut tree = Tree::default();
+        let key = Sha256Hash([1u8;32]);
+        assert!(tree.insert_if_absent(&key, vec![0x01]));
+        let hash = tree.hash();
+        assert!(!tree.insert_if_absent(&key, vec![0x02]));
+        assert_eq!(tree.get(&key), Some(vec![0x01]));
+        assert_eq!(tree.hash(), hash);
+        assert!(tree.insert_if_absent(Sha256Hash([2u8;32]), vec![0x02]));
+        assert_eq!(tree.get(Sha256Hash([2u8;32])), Some(vec![0x02]));
+    }
+
+    #[test]
+    fn test_add_report() {
+        for radix in [Radix::Byte, Radix::Nibble] {
+            let mut tree = Tree::with_radix(radix);
+            let a = Sha256Hash([0u8;32]);
+            assert_eq!(tree.add_report(&a, vec![0]), InsertOutcome::AddedChild);
+            assert_eq!(tree.add_report(&a, vec![1]), InsertOutcome::Overwritten { old: vec![0] });
+
+            // sharing the first byte with `a`
+            let mut b = [0u8;32];
+            b[1] = 0x11;
+            assert_eq!(tree.add_report(b, vec![2]), InsertOutcome::Inserted { split_depth: radix.digits(&[0]).len() });
+            assert_eq!(tree.leaf_metadata(&a).unwrap().depth, tree.leaf_metadata(&Sha256Hash(b)).unwrap().depth);
+
+            assert_eq!(tree.add_report([0xffu8;32], vec![3]), InsertOutcome::AddedChild);
+            let mut c = [0u8;32];
+            c[31] = 1;
+            assert_eq!(tree.add_report(c, vec![4]), InsertOutcome::Inserted { split_depth: radix.digits(&c).len() - 1 });
+            assert_eq!(tree.add_report(c, vec![5]), InsertOutcome::Overwritten { old: vec![4] });
+
+            let mut expected = Tree::with_radix(radix);
+            for (key, value) in [(a.0, 1), (b, 2), ([0xff;32], 3), (c, 5)] {
+                expected.add(key, vec![value]);
+            }
+            assert_eq!(tree.hash(), expected.hash());
+        }
+    }
+
+    #[test]
+    fn test_extend() {
+        let pairs: Vec<(Sha256Hash, Vec<u8>)> = (0u32..300).map(|i| (hash(&i.to_be_bytes()), i.to_be_bytes().to_vec())).collect();
+        let mut tree= Tree::default();
+        for (key, value) in &pairs {
+            tree.add(key, value.clone());
+        }
+
+        let mut extended: Tree = pairs[..100].iter().cloned().collect();
+        assert!(!extended.is_empty());
+        extended.extend(pairs[100..].iter().cloned());
+        assert_eq!(extended.hash(), tree.hash());
+        for (key, value) in &pairs {
+            assert_eq!(extended.get(key).as_ref(), Some(value));
+        }
+    }
+
+    #[test]
+    fn test_from_slice() {
+        let pairs: Vec<(Sha256Hash, Vec<u8>)> = (0u32..100).map(|i| (hash(&i.to_be_bytes()), vec![i as u8])).collect();
+        let mut tree= Tree::default();
+        for (key, value) in &pairs {
+            tree.add(key, value.clone());
+        }
+        let from = Tree::from(&pairs[..]);
+        assert_eq!(from.hash(), tree.hash());
+        assert_eq!(from.to_entries(), tree.to_entries());
+        assert!(Tree::<32>::from(&[][..]).is_empty());
+    }
+
+    #[test]
+    fn test_map_values() {
+        let mut tree= Tree::default();
+        let mut expected= Tree::default();
+        for i in 0u8..20 {
+            tree.add(Sha256Hash([i;32]), vec![i]);
+            expected.add(Sha256Hash([i;32]), vec![i, i]);
+        }
+        let hash = tree.hash();
+        let mut calls = 0;
+        tree.map_values(|value| {
+            calls += 1;
+            [value, value].concat()
+        });
+        assert_eq!(calls, 20);
+        assert_ne!(tree.hash(), hash);
+        assert_eq!(tree.hash(), expected.hash());
+        for i in 0u8..20 {
+            assert_eq!(tree.get(Sha256Hash([i;32])).unwrap(), [i, i]);
+        }
+    }
+
+    #[test]
+    fn test_tree_20() {
+        let mut tree = Tree::<20>::default();
+        let keys: Vec<Sha256Hash<20>> = (0u8..10).map(|i| Sha256Hash([i;20])).collect();
+        for key in &keys {
+            tree.add(key, key.0[..2].to_vec());
+        }
+        for key in &keys {
+            assert_eq!(tree.get(key).unwrap(), key.0[..2]);
+            assert_eq!(verify_proof(&tree.options(), &tree.hash(), key, &tree.prove(key)), Ok(Some(key.0[..2].to_vec())));
+        }
+        assert!(tree.get(Sha256Hash([10u8;20])).is_none());
+        assert_eq!(tree.entries_under_prefix(&[3]), vec![(Sha256Hash([3u8;20]), vec![3, 3])]);
+
+        let mut nibble = Tree::<20>::with_radix(Radix::Nibble);
+        nibble.extend(keys.iter().map(|key| (key.clone(), vec![])));
+        assert_eq!(nibble.entries_under_prefix(&[]).len(), 10);
+
+        let sha: Sha256Tree = Tree::default();
+        assert!(sha.is_empty());
+    }
+
+    #[test]
+    fn test_varint() {
+        let a = 0usize;
+        let x = a.encode_var_vec();
+        assert_eq!(&x[..],&[0x00]);
+    }
+
+    #[test]
+    fn test_serialize() {
+        let leaf = Leaf::new( [0x01].to_vec(), [0x02].to_vec() );
+        assert_eq!(leaf.serialize(), [0x02,0x04,0x01,0x01,0x01,0x02]);
+    }
+
+    #[test]
+    fn test_serialized_len() {
+        let leaf = Leaf::new( vec![0x01; 200], vec![0x02; 300] );
+        assert_eq!(leaf.serialized_len(), leaf.serialize().len());
+        for radix in [Radix::Byte, Radix::Nibble] {
+            let mut tree= Tree::with_radix(radix);
+            assert_eq!(tree.serialized_len(), tree.serialize().len());
+            for i in 0u8..10 {
+                tree.add(Sha256Hash([i;32]), vec![i; 50 * i as usize]);
+                assert_eq!(tree.serialized_len(), tree.serialize().len());
+            }
+        }
+    }
+
+    #[test]
+    fn test_hash() {
+        let leaf = Leaf::new( [0x01].to_vec(), [0x02].to_vec() );
+        assert_eq!(leaf.serialize(), [0x02,0x04,0x01,0x01,0x01,0x02]);
+        let b = HEXLOWER.decode("f5c058ec832bd6b8e5cb6f1bcdb60dfdcb44d397ba9f95d18a79cd0db92e4dc1".as_bytes()).unwrap();
+        assert_eq!(leaf.my_hash().0.to_vec(), b);
+    }
+
+    #[test]
+    fn test_inner_hash_streamed() {
+        for radix in [Radix::Byte, Radix::Nibble] {
+            for children in [0u8, 1, 2, 15, 16] {
+                let map = (0..children).map(|i| (i, Arc::new(Node::Leaf(Leaf::new(vec![i], vec![i]))))).collect();
+                let mut inner = InnerNode::new(map, radix, HASH_LEN, None);
+                inner.update();
+                assert_eq!(inner.my_hash(), hash(&inner.serialize()));
+            }
+        }
+        let mut map = ChildMap::new();
+        for i in 0u8..=255 {
+            map.insert(i, Arc::new(Node::Leaf(Leaf::new(vec![i], vec![i]))));
+        }
+        let mut inner = InnerNode::new(map, Radix::Byte, HASH_LEN, None);
+        inner.update();
+        assert_eq!(inner.my_hash(), hash(&inner.serialize()));
+    }
+
+    #[test]
+    fn test_inner_slots() {
+        for (radix, width) in [(Radix::Byte, 256), (Radix::Nibble, 16)] {
+            let digits: Vec<u8> = radix.all_digits().collect();
+            assert_eq!(digits.len(), width);
+            assert_eq!(digits.last(), Some(&((width - 1) as u8)));
+
+            let mut map = ChildMap::new();
+            map.insert(digits[width - 1], Arc::new(Node::Leaf(Leaf::new(vec![1], vec![1]))));
+            let mut inner = InnerNode::new(map, radix, HASH_LEN, None);
+            inner.update();
+            let bytes = inner.serialize();
+            assert_eq!(bytes.len(), inner.serialized_len());
+            // the empty slots, then the last one with the hash
+            let inside = &bytes[bytes.len() - (width - 1) - 33..];
+            assert_eq!(inside.len(), width + 32);
+            assert!(inside[..width - 1].iter().all(|b| *b == 0x00));
+            assert_eq!(inside[width - 1], 0x20);
+            assert_eq!(inner.my_hash(), hash(&bytes));
+        }
+    }
+
+    #[test]
+    fn test_value_hashing() {
+        let a = Leaf::with_value_hashing(vec![0x01], vec![0xaa; 10000], ValueHashing::Digest);
+        let b = Leaf::with_value_hashing(vec![0x01], vec![0xab; 10000], ValueHashing::Digest);
+        assert_ne!(a.my_hash(), b.my_hash());
+        assert_ne!(a.my_hash(), Leaf::new(vec![0x01], vec![0xaa; 10000]).my_hash());
+        // type, length, remaining key and value hash
+        assert_eq!(a.serialize().len(), 1 + 1 + 2 + 32);
+        assert_eq!(a.serialized_len(), a.serialize().len());
+
+        let mut inline: Tree = Tree::new();
+        let mut digest: Tree = Tree::builder().value_hashing(ValueHashing::Digest).build();
+        for i in 0u32..50 {
+            inline.add(hash(&i.to_be_bytes()), vec![i as u8; 1000]);
+            digest.add(hash(&i.to_be_bytes()), vec![i as u8; 1000]);
+        }
+        assert_ne!(inline.hash(), digest.hash());
+        assert_eq!(digest.serialized_len(), digest.serialize().len());
+        assert_eq!(digest.verify_hashes(), Ok(()));
+        for i in 0u32..50 {
+            let key = hash(&i.to_be_bytes());
+            assert_eq!(digest.get(&key), Some(vec![i as u8; 1000]));
+            assert_eq!(verify_proof(&digest.options(), &digest.hash(), &key, &digest.prove(&key)), Ok(Some(vec![i as u8; 1000])));
+        }
+    }
+
+    #[test]
+    fn test_hash_slice() {
+        let buffer: Vec<u8> = (0u8..100).collect();
+        let owned = buffer[10..50].to_vec();
+        assert_eq!(hash(&buffer[10..50]), hash(&owned));
+        assert_ne!(hash(&buffer[10..51]), hash(&owned));
+    }
+
+    #[test]
+    fn test_checkpoint() {
+        let mut tree= Tree::default();
+        tree.add(Sha256Hash([0u8;32]), [0x01].to_vec());
+        tree.add(Sha256Hash([1u8;32]), [0x02].to_vec());
+        let hash = tree.hash();
+
+        let token = tree.checkpoint();
+        tree.add(Sha256Hash([0u8;32]), [0x03].to_vec());
+        for i in 2u8..6 {
+            tree.add(Sha256Hash([i;32]), [i].to_vec());
+        }
+        assert_ne!(tree.hash(), hash);
+        tree.rollback(token);
+        assert_eq!(tree.hash(), hash);
+        assert_eq!(tree.get(Sha256Hash([0u8;32])).unwrap(), [0x01]);
+        assert_eq!(tree.get(Sha256Hash([1u8;32])).unwrap(), [0x02]);
+        for i in 2u8..6 {
+            assert!(tree.get(Sha256Hash([i;32])).is_none());
+        }
+
+        let token = tree.checkpoint();
+        tree.add(Sha256Hash([2u8;32]), [0x02].to_vec());
+        let inner = tree.checkpoint();
+        tree.add(Sha256Hash([3u8;32]), [0x03].to_vec());
+        tree.rollback(inner);
+        tree.commit(token);
+        assert_eq!(tree.get(Sha256Hash([2u8;32])).unwrap(), [0x02]);
+        assert!(tree.get(Sha256Hash([3u8;32])).is_none());
+        assert!(tree.checkpoints.is_empty());
+    }
+
+    #[test]
+    fn test_new() {
+        let tree: Tree = Tree::new();
+        assert!(tree.is_empty());
+        assert_eq!(tree.hash(), hash(&[0x00]));
+        assert_eq!(tree.hash(), Tree::<32>::empty_root());
+        assert_eq!(Tree::<32>::default().hash(), Sha256Tree::EMPTY_ROOT);
+        assert_eq!(tree.hash(), Tree::<32>::default().hash());
+        assert_eq!(tree.radix, Radix::Byte);
+    }
+
+    #[test]
+    fn test_root_hash_hex() {
+        let mut tree= Tree::default();
+        assert_eq!(tree.root_hash_hex(), HEXLOWER.encode(&hash(&[0x00]).0));
+        tree.add(Sha256Hash([0u8;32]), [0x02].to_vec());
+        tree.add(Sha256Hash([1u8;32]), [0x12].to_vec());
+        assert_eq!(tree.root_hash_hex(), HEXLOWER.encode(&tree.hash().0));
+        assert_eq!(tree.root_hash_hex().len(), 64);
+    }
+
+    #[test]
+    fn test_root_is() {
+        let tree: Tree = Tree::new();
+        assert!(tree.root_is(&EMPTY_ROOT));
+        assert!(!tree.root_is(&hash(&[1])));
+        let tree = std::sync::Arc::new(Tree::<32>::from_entries((0u32..100).map(|i| (hash(&i.to_be_bytes()), vec![i as u8]))));
+        let root = tree.hash();
+        // a shared borrow of the tree is enough
+        let shared = tree.clone();
+        assert!(shared.root_is(&root));
+        assert!(!shared.root_is(&EMPTY_ROOT));
+        let mut leaf: Tree = Tree::new();
+        leaf.add(hash(&[2]), vec![2]);
+        assert!(leaf.root_is(&leaf.hash()));
+        assert!(!leaf.root_is(&root));
+    }
+
+    #[test]
+    fn test_first_diverging_byte() {
+        let a = Sha256Hash([7u8;32]);
+        assert_eq!(Tree::first_diverging_byte(&a, &a), None);
+        for i in [0, 5, 31] {
+            let mut b = a.clone();
+            b.0[i] = 8;
+            b.0[31] ^= 1;
+            assert_eq!(Tree::first_diverging_byte(&a, &b), Some(i));
+            assert_eq!(Tree::first_diverging_byte(&b, &a), Some(i));
+        }
+        assert_eq!(Tree::first_diverging_byte(&Sha256Hash([1u8;4]), &Sha256Hash([1, 1, 1, 0])), Some(3));
+    }
+
+    #[test]
+    fn test_root_preimage() {
+        let empty: Tree = Tree::new();
+        assert_eq!(empty.root_preimage(), vec![0x00]);
+        assert_eq!(hash(&empty.root_preimage()), empty.hash());
+        let builders = [
+            Tree::builder(),
+            Tree::builder().radix(Radix::Nibble).value_hashing(ValueHashing::Digest),
+            Tree::builder().hash_len(8).empty_hash(hash(&[2])).leaf_key(LeafKey::Full),
+        ];
+        for builder in builders {
+            let mut tree: Tree = builder.build();
+            tree.add(hash(&[0]), vec![0]);
+            assert_eq!(hash(&tree.root_preimage()), tree.hash());
+            for i in 1..100u8 {
+                tree.add(hash(&[i]), vec![i]);
+            }
+            assert_eq!(hash(&tree.root_preimage()), tree.hash());
+        }
+    }
+
+    #[test]
+    fn test_validate_key() {
+        assert_eq!(Tree::<32>::validate_key(&[]), Err(KeyError::Empty));
+        assert_eq!(Tree::<32>::validate_key(&[1u8;31]), Err(KeyError::WrongLength { expected: 32, found: 31 }));
+        assert_eq!(Tree::<32>::validate_key(&[1u8;33]), Err(KeyError::WrongLength { expected: 32, found: 33 }));
+        assert_eq!(Tree::<32>::validate_key(&[1u8;32]), Ok(()));
+        assert_eq!(Tree::<4>::validate_key(&[1u8;4]), Ok(()));
+        assert_eq!(Tree::<4>::validate_key(&[1u8;32]), Err(KeyError::WrongLength { expected: 4, found: 32 }));
+    }
+
+    #[test]
+    fn test_try_from_slice() {
+        assert_eq!(Sha256Hash::<32>::try_from(&[1u8;32][..]), Ok(Sha256Hash([1u8;32])));
+        assert_eq!(Sha256Hash::<32>::try_from(&[1u8;16][..]), Err(KeyError::WrongLength { expected: 32, found: 16 }));
+        assert_eq!(Sha256Hash::<32>::try_from(&[1u8;33][..]), Err(KeyError::WrongLength { expected: 32, found: 33 }));
+        assert_eq!(Sha256Hash::<32>::try_from(&[][..]), Err(KeyError::Empty));
+        assert_eq!(Sha256Hash::<4>::try_from(&[1u8, 2, 3, 4][..]), Ok(Sha256Hash([1, 2, 3, 4])));
+        let mut tree: Tree = Tree::new();
+        let key = Sha256Hash::try_from(&hash(&[1]).0[..]).unwrap();
+        tree.add(&key, vec![1]);
+        assert_eq!(tree.get(hash(&[1])), Some(vec![1]));
+    }
+
+    #[test]
+    fn test_full_key_leaves() {
+        use std::collections::HashMap;
+        let key = |i: u32| hash(&i.to_be_bytes());
+        for radix in [Radix::Byte, Radix::Nibble] {
+            for value_hashing in [ValueHashing::Inline, ValueHashing::Digest] {
+                let build = |leaf_key, n: u32| {
+                    let mut tree: Tree = Tree::builder().radix(radix).value_hashing(value_hashing).leaf_key(leaf_key).build();
+                    for i in 0..n {
+                        tree.add(key(i), i.to_be_bytes().to_vec());
+                    }
+                    tree
+                };
+                let single = build(LeafKey::Full, 1);
+                let tree = build(LeafKey::Full, 200);
+                let meta = tree.leaf_metadata(&key(0)).unwrap();
+                assert!(meta.depth > 0);
+                assert_eq!(meta.leaf_hash, single.leaf_metadata(&key(0)).unwrap().leaf_hash);
+                assert_ne!(meta.leaf_hash, build(LeafKey::Remaining, 200).leaf_metadata(&key(0)).unwrap().leaf_hash);
+                assert_eq!(tree.check_invariants(), Ok(()));
+
+                let root = tree.hash();
+                for i in [0, 7, 199, 500] {
+                    let proof = tree.prove(key(i));
+                    let expected = if i < 200 { Some(i.to_be_bytes().to_vec()) } else { None };
+                    assert_eq!(verify_proof(&tree.options(), &root, &key(i), &proof), Ok(expected.clone()));
+                    let compact = Proof::from_compact(&proof.to_compact()).unwrap();
+                    assert_eq!(verify_proof(&tree.options(), &root, &key(i), &compact), Ok(expected));
+                }
+
+                let mut store = HashMap::new();
+                tree.persist(&mut store);
+                assert_eq!(tree.get_at_root(&store, &root, &key(7)), Some(7u32.to_be_bytes().to_vec()));
+                let proof = tree.try_prove_at_root(&store, &root, &key(7)).unwrap();
+                assert_eq!(verify_proof(&tree.options(), &root, &key(7), &proof), Ok(Some(7u32.to_be_bytes().to_vec())));
+
+                let proof = tree.prove_insert(&key(500), vec![5]);
+                let mut updated = tree.clone();
+                updated.add(key(500), vec![5]);
+                assert!(verify_insert(&tree.options(), &proof, &key(500), &[5], &root, &updated.hash()));
+
+                let mut rest = tree.clone();
+                let split = rest.split_off(&key(0).0[..1]);
+                assert_eq!(split.leaf_metadata(&key(0)).unwrap().leaf_hash, meta.leaf_hash);
+                assert_eq!(rest.check_invariants(), Ok(()));
+                assert_eq!(split.check_invariants(), Ok(()));
+            }
+        }
+        let mut tree: Tree = Tree::builder().leaf_key(LeafKey::Full).build();
+        assert_eq!(tree.graft(0xaa, Tree::new()), Err(GraftError::LeafKeyMismatch));
+        tree.add(key(0), vec![0]);
+        assert_eq!(tree.hash(), tree.deep_clone().hash());
+    }
+
+    #[test]
+    fn test_fixed_value_len() {
+        let mut tree: Tree = Tree::with_fixed_value_len(4);
+        assert_eq!(tree.try_add(hash(&[1]), vec![1, 2, 3]), Err(ValueLenError { expected: 4, found: 3 }));
+        assert_eq!(tree.try_add(hash(&[1]), vec![1;5]), Err(ValueLenError { expected: 4, found: 5 }));
+        assert!(tree.is_empty());
+        assert_eq!(tree.try_add(hash(&[1]), vec![1;4]), Ok(()));
+        assert_eq!(tree.get(hash(&[1])), Some(vec![1;4]));
+        tree.add(hash(&[2]), vec![2;4]);
+        assert_eq!(tree.split_off(&hash(&[2]).0[..1]).try_add(hash(&[3]), vec![3]), Err(ValueLenError { expected: 4, found: 1 }));
+        let mut tree: Tree = Tree::new();
+        assert_eq!(tree.try_add(hash(&[1]), vec![1]), Ok(()));
+    }
+
+    #[test]
+    fn test_keys_differing_in_the_last_digit() {
+        // keys differ only in the last digit, under inner hashes truncated to a byte
+        for radix in [Radix::Byte, Radix::Nibble] {
+            let mut tree: Tree<1> = Tree::builder().radix(radix).hash_len(1).build();
+            for i in 0..=255u8 {
+                tree.add([i], vec![i]);
+            }
+            tree.add([7], vec![0xff]);
+            for i in 0..=255u8 {
+                assert_eq!(tree.get([i]), Some(if i == 7 { vec![0xff] } else { vec![i] }));
+            }
+            assert_eq!(tree.to_entries().len(), 256);
+            assert_eq!(tree.check_invariants(), Ok(()));
+
+            let mut tree: Tree = Tree::builder().radix(radix).hash_len(1).build();
+            let mut key = [0xaau8;32];
+            tree.add(key, vec![0]);
+            key[31] ^= 1;
+            tree.add(key, vec![1]);
+            assert_eq!(tree.get(key), Some(vec![1]));
+            assert_eq!(tree.depth_histogram().len(), radix.digits(&key).len() + 1);
+        }
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_fixed_value_len_add() {
+        let mut tree: Tree = Tree::with_fixed_value_len(4);
+        tree.add(hash(&[1]), vec![1]);
+    }
+}
+
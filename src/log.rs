@@ -0,0 +1,79 @@
+use {Node, Sha256Hash, Tree, NO_PRUNED};
+
+/// Key of the log entry `index`, the index big endian in the last 8 bytes
+fn index_key<const N: usize>(index: u64) -> Sha256Hash<N> {
+    assert!(N >= 8, "the keys must hold a u64 index");
+    let mut key = [0u8;N];
+    key[N - 8..].copy_from_slice(&index.to_be_bytes());
+    Sha256Hash(key)
+}
+
+impl<const N: usize> Tree<N> {
+    /// The largest key, following the last branch of every inner node
+    fn last_key(&self) -> Option<Sha256Hash<N>> {
+        let mut node = self.root.as_ref()?;
+        let mut digits = Vec::new();
+        loop {
+            match node {
+                Node::InnerNode(inner) => {
+                    let branch = *inner.branches().last().expect("inner nodes have children");
+                    digits.push(branch);
+                    node = &inner.map[&branch];
+                },
+                Node::Leaf(leaf) => {
+                    digits.extend(&leaf.remaining_key);
+                    return Some(self.radix.key(&digits));
+                },
+                Node::Pruned(_) => panic!("{}", NO_PRUNED),
+            }
+        }
+    }
+
+    /// Add `value` to a tree used as an append only log, under the index following the
+    /// largest one in the tree, and return the index. The entries are keyed by their index
+    /// big endian in the last 8 bytes of the key, see `get_index`.
+    pub fn append(&mut self, value: Vec<u8>) -> u64 {
+        let index = match self.last_key() {
+            None => 0,
+            Some(key) => {
+                let mut last = [0u8;8];
+                last.copy_from_slice(&key.0[N - 8..]);
+                u64::from_be_bytes(last).checked_add(1).expect("log index overflow")
+            },
+        };
+        self.add(index_key(index), value);
+        index
+    }
+
+    /// The value appended with the given index
+    pub fn get_index(&self, index: u64) -> Option<Vec<u8>> {
+        self.get(index_key::<N>(index))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ::*;
+
+    #[test]
+    fn test_append() {
+        for radix in [Radix::Byte, Radix::Nibble] {
+            let mut log = Tree::with_radix(radix);
+            assert_eq!(log.get_index(0), None);
+            for i in 0u64..300 {
+                assert_eq!(log.append(i.to_le_bytes().to_vec()), i);
+            }
+            for i in 0u64..300 {
+                assert_eq!(log.get_index(i), Some(i.to_le_bytes().to_vec()));
+            }
+            assert_eq!(log.get_index(300), None);
+            let values: Vec<_> = log.to_entries().into_iter().map(|(_, value)| value).collect();
+            assert_eq!(values, (0u64..300).map(|i| i.to_le_bytes().to_vec()).collect::<Vec<_>>());
+
+            let mut key = [0u8;32];
+            key[24..].copy_from_slice(&1000u64.to_be_bytes());
+            log.add(key, vec![]);
+            assert_eq!(log.append(vec![1]), 1001);
+        }
+    }
+}
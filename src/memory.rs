@@ -0,0 +1,168 @@
+use std::mem;
+use std::sync::Arc;
+use {ChildMap, InnerNode, Node, Tree};
+
+/// every bucket of the map holds an entry and a control byte
+#[cfg(not(feature = "btree-map"))]
+fn map_usage(map: &ChildMap) -> usize {
+    map.capacity() * (mem::size_of::<(u8, Arc<Node>)>() + 1)
+}
+
+/// the entries without the tree nodes around them, a `BTreeMap` has no spare capacity
+#[cfg(feature = "btree-map")]
+fn map_usage(map: &ChildMap) -> usize {
+    map.len() * mem::size_of::<(u8, Arc<Node>)>()
+}
+
+impl Node {
+    /// Heap bytes owned by the node, its own allocation excluded
+    fn memory_usage(&self) -> usize {
+        match self {
+            Node::Leaf(leaf) => leaf.remaining_key.capacity() + leaf.value.capacity(),
+            Node::InnerNode(inner) => {
+                let buckets = map_usage(&inner.map);
+                // an `Arc` allocation holds the two reference counts and the node
+                let children = inner.map.len() * (2 * mem::size_of::<usize>() + mem::size_of::<Node>());
+                buckets + children + inner.map.values().map(|child| child.memory_usage()).sum::<usize>()
+            },
+            Node::Pruned(_) => 0,
+        }
+    }
+
+    /// Drop the excess capacity of the node and of the children not shared with others
+    fn shrink_to_fit(&mut self) {
+        match self {
+            Node::Leaf(leaf) => {
+                leaf.remaining_key.shrink_to_fit();
+                leaf.value.shrink_to_fit();
+            },
+            Node::InnerNode(inner) => {
+                #[cfg(not(feature = "btree-map"))]
+                inner.map.shrink_to_fit();
+                for child in inner.map.values_mut() {
+                    if let Some(child) = Arc::get_mut(child) {
+                        child.shrink_to_fit();
+                    }
+                }
+            },
+            Node::Pruned(_) => (),
+        }
+    }
+
+    /// Copy of the node with every child in a new allocation
+    fn deep_clone(&self) -> Node {
+        match self {
+            Node::InnerNode(inner) => {
+                let mut copy = InnerNode::new(ChildMap::new(), inner.radix, inner.hash_len, inner.empty_hash.clone());
+                copy.map = inner.map.iter().map(|(branch, child)| (*branch, Arc::new(child.deep_clone()))).collect();
+                copy.hash = inner.hash.clone();
+                Node::InnerNode(copy)
+            },
+            node => node.clone(),
+        }
+    }
+}
+
+impl<const N: usize> Tree<N> {
+    /// Estimate of the heap bytes used by the nodes of the current state, the cached hashes
+    /// are stored in the nodes. Nodes shared with checkpoints or clones are counted anyway.
+    pub fn memory_usage(&self) -> usize {
+        match self.root {
+            None => 0,
+            Some(ref root) => root.memory_usage(),
+        }
+    }
+
+    /// Reclaim the capacity left over by removals, the hashes don't change. Nodes shared
+    /// with checkpoints or clones are skipped rather than copied.
+    pub fn shrink_to_fit(&mut self) {
+        if let Some(ref mut root) = self.root {
+            root.shrink_to_fit();
+        }
+    }
+
+    /// Copy of the tree sharing no node with this one, unlike `clone` sharing the nodes
+    /// until either tree modifies them. The copy has no checkpoints.
+    pub fn deep_clone(&self) -> Tree<N> {
+        let mut tree = self.empty_like();
+        tree.root = self.root.as_ref().map(Node::deep_clone);
+        tree
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ::*;
+
+    #[test]
+    fn test_memory_usage() {
+        let mut tree = Tree::default();
+        let mut last = tree.memory_usage();
+        assert_eq!(last, 0);
+        for i in 0u32..200 {
+            tree.add(hash(&i.to_be_bytes()), vec![0u8; 10]);
+            let usage = tree.memory_usage();
+            assert!(usage > last);
+            last = usage;
+        }
+        assert!(last > 200 * 10);
+    }
+
+    fn node_addresses(node: &Node, addresses: &mut Vec<*const Node>) {
+        if let Node::InnerNode(inner) = node {
+            for (_, child) in inner.children() {
+                addresses.push(child);
+                node_addresses(child, addresses);
+            }
+        }
+    }
+
+    #[test]
+    fn test_deep_clone() {
+        let mut tree = Tree::default();
+        for i in 0u32..300 {
+            tree.add(hash(&i.to_be_bytes()), vec![i as u8]);
+        }
+        let root = tree.hash();
+        let shared = tree.clone();
+        let mut copy = tree.deep_clone();
+        assert_eq!(copy.hash(), root);
+        assert_eq!(copy.to_entries(), tree.to_entries());
+
+        let (mut original_nodes, mut shared_nodes, mut copy_nodes) = (Vec::new(), Vec::new(), Vec::new());
+        node_addresses(tree.root_node().unwrap(), &mut original_nodes);
+        node_addresses(shared.root_node().unwrap(), &mut shared_nodes);
+        node_addresses(copy.root_node().unwrap(), &mut copy_nodes);
+        assert_eq!(shared_nodes, original_nodes);
+        assert!(copy_nodes.iter().all(|node| !original_nodes.contains(node)));
+
+        for i in 0u32..300 {
+            copy.add(hash(&i.to_be_bytes()), vec![0xff]);
+        }
+        assert_eq!(tree.hash(), root);
+        assert_eq!(tree.get(hash(&[0, 0, 0, 7])), Some(vec![7]));
+    }
+
+    // a `BTreeMap` keeps no spare capacity
+    #[cfg(not(feature = "btree-map"))]
+    #[test]
+    fn test_shrink_to_fit() {
+        let mut tree = Tree::default();
+        for i in 0u32..2000 {
+            tree.add(hash(&i.to_be_bytes()), vec![0u8; 10]);
+        }
+        for branch in 2u8..=255 {
+            tree.split_off(&[branch]);
+        }
+        let root = tree.hash();
+        let before = tree.memory_usage();
+        tree.shrink_to_fit();
+        let after = tree.memory_usage();
+        assert!(after < before, "{} not less than {}", after, before);
+        assert_eq!(tree.hash(), root);
+        assert_eq!(tree.verify_hashes(), Ok(()));
+
+        tree.shrink_to_fit();
+        assert_eq!(tree.memory_usage(), after);
+    }
+}
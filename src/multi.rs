@@ -0,0 +1,285 @@
+use std::io::{self, Write};
+use integer_encoding::VarInt;
+use proof::{content_leaf, fold_levels, inner_hash, path_end, write_leaf, write_level, write_radix, LeafContent, Siblings};
+use {node_len, InvalidProof, Leaf, Node, Proof, Radix, Sha256Hash, Tree, TreeBuilder, HASH_LEN, NO_PRUNED};
+
+/// Proof levels below the shared ones and the leaf of one of the keys
+type Tail = (Vec<Siblings>, Option<LeafContent>);
+
+/// Proves two keys at once, holding the levels of the inner nodes shared by their paths once,
+/// see `Proof::merge`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MultiProof {
+    radix: Radix,
+    hash_len: usize,
+    empty_hash: Option<Sha256Hash>,
+    /// levels of the inner nodes on the paths of both keys
+    shared: Vec<Siblings>,
+    /// siblings of the inner node where the paths split, but the hashes of the two paths,
+    /// which the verifier computes. `None` if the proofs weren't split there.
+    branch: Option<Siblings>,
+    tails: [Tail; 2],
+}
+
+impl Proof {
+    /// Combine the proofs of two keys of the same tree. The levels of the inner nodes both
+    /// paths go through are kept once, and where the paths split the siblings hashing the
+    /// other path are dropped, so the result is smaller than the two proofs.
+    pub fn merge(a: Proof, b: Proof) -> MultiProof {
+        assert!(a.radix == b.radix && a.hash_len == b.hash_len && a.empty_hash == b.empty_hash, "proofs of trees with different options");
+        let depth = a.levels.iter().zip(&b.levels).take_while(|(a, b)| a == b).count();
+        let branch = match (a.levels.get(depth), b.levels.get(depth)) {
+            (Some(siblings_a), Some(siblings_b)) => {
+                let only_a: Vec<_> = siblings_a.iter().filter(|sibling| !siblings_b.contains(sibling)).collect();
+                let only_b: Vec<_> = siblings_b.iter().filter(|sibling| !siblings_a.contains(sibling)).collect();
+                let split = match (only_a.first(), only_b.first()) {
+                    (Some(a), Some(b)) => a.0 != b.0,
+                    _ => true,
+                };
+                if only_a.len() <= 1 && only_b.len() <= 1 && split {
+                    Some(siblings_a.iter().filter(|sibling| siblings_b.contains(sibling)).cloned().collect())
+                } else {
+                    None
+                }
+            },
+            _ => None,
+        };
+        let below = if branch.is_some() { depth + 1 } else { depth };
+        MultiProof {
+            radix: a.radix,
+            hash_len: a.hash_len,
+            empty_hash: a.empty_hash,
+            shared: a.levels[..depth].to_vec(),
+            branch,
+            tails: [(a.levels[below..].to_vec(), a.leaf), (b.levels[below..].to_vec(), b.leaf)],
+        }
+    }
+}
+
+impl MultiProof {
+    /// The radix header and the shared levels, the branch level or `0x00` if there is none,
+    /// then the levels and the leaf of every key as in a single proof.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut result = Vec::new();
+        self.write_to(&mut result).expect("writing to a Vec doesn't fail");
+        result
+    }
+
+    fn write_to<W: Write>(&self, w: &mut W) -> io::Result<()> {
+        write_radix(w, self.radix, self.hash_len, self.empty_hash.as_ref())?;
+        w.write_all(&self.shared.len().encode_var_vec())?;
+        for siblings in &self.shared {
+            write_level(w, siblings, self.hash_len)?;
+        }
+        match self.branch {
+            None => w.write_all(&[0x00])?,
+            Some(ref siblings) => write_level(w, siblings, self.hash_len)?,
+        }
+        for (levels, leaf) in &self.tails {
+            for siblings in levels {
+                write_level(w, siblings, self.hash_len)?;
+            }
+            match leaf {
+                None => w.write_all(&[0x00])?,
+                Some(leaf) => write_leaf(w, &content_leaf(leaf, &[]))?,
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Leaf {
+    /// Bytes of the leaf in a proof, holding the remaining key and the value
+    fn proof_len(&self) -> usize {
+        let key = self.remaining_key.len().required_space() + self.remaining_key.len();
+        let value = self.value.len().required_space() + self.value.len();
+        match self.value_hash {
+            None => node_len(key + value),
+            Some(ref value_hash) => node_len(key + value_hash.0.len()) + value,
+        }
+    }
+}
+
+impl Node {
+    /// Bytes of the levels and the leaves below the node in a proof of `keys` sharing the
+    /// levels, every inner node on their paths is written once
+    fn batch_proof_size(&self, keys: &[&[u8]], radix: Radix, hash_len: usize) -> usize {
+        match self {
+            Node::Leaf(leaf) => keys.len() * leaf.proof_len(),
+            Node::InnerNode(inner) => {
+                let mut size = 0;
+                let mut siblings = inner.map.len();
+                for digit in radix.all_digits() {
+                    let rest: Vec<&[u8]> = keys.iter().filter(|key| key[0] == digit).map(|key| &key[1..]).collect();
+                    if rest.is_empty() {
+                        continue;
+                    }
+                    size += match inner.map.get(&digit) {
+                        None => rest.len(),
+                        Some(child) => {
+                            siblings -= 1;
+                            child.batch_proof_size(&rest, radix, hash_len)
+                        },
+                    };
+                }
+                size + 1 + siblings.required_space() + siblings * (1 + hash_len)
+            },
+            Node::Pruned(_) => panic!("{}", NO_PRUNED),
+        }
+    }
+}
+
+impl<const N: usize> Tree<N> {
+    /// Bytes of a proof of `keys` sharing the levels of the inner nodes on more paths, computed
+    /// walking the paths without building it. For two keys it's the length of the bytes of
+    /// `Proof::merge` of their proofs.
+    pub fn batch_proof_size(&self, keys: &[Sha256Hash<N>]) -> usize {
+        let header = match self.hash_len {
+            HASH_LEN => 1,
+            _ => 2,
+        } + self.empty_hash.as_ref().map_or(0, |_| self.hash_len);
+        let digits: Vec<Vec<u8>> = keys.iter().map(|key| self.radix.digits(&key.0)).collect();
+        let digits: Vec<&[u8]> = digits.iter().map(|key| &key[..]).collect();
+        let root = match self.root {
+            None => return header + 2 + keys.len(),
+            Some(ref root) => root,
+        };
+        // the levels all the paths go through are counted, followed by the marker of the
+        // missing split if the paths end together
+        let mut shared = 0;
+        let mut node = root;
+        let split = loop {
+            match node {
+                Node::InnerNode(inner) if !digits.is_empty() && digits.iter().all(|key| key[shared] == digits[0][shared]) => {
+                    shared += 1;
+                    match inner.map.get(&digits[0][shared - 1]) {
+                        Some(child) => node = child,
+                        None => break false,
+                    }
+                },
+                Node::InnerNode(_) => break !digits.is_empty(),
+                _ => break false,
+            }
+        };
+        let marker = if split { 0 } else { 1 };
+        header + shared.required_space() + marker + root.batch_proof_size(&digits, self.radix, self.hash_len)
+    }
+}
+
+/// Check `proof` for the keys `a` and `b`, in the order of the merged proofs, against `root`
+/// of a tree built with `options`, returning the value proven for each key as `verify_proof`
+/// does.
+pub fn verify_multi_proof<const N: usize>(options: &TreeBuilder<N>, root: &Sha256Hash, a: &Sha256Hash<N>, b: &Sha256Hash<N>, proof: &MultiProof) -> Result<[Option<Vec<u8>>; 2], InvalidProof> {
+    let (radix, hash_len, empty_hash) = (options.radix, options.hash_len, options.truncated_empty_hash());
+    let digits = [radix.digits(&a.0), radix.digits(&b.0)];
+    let depth = proof.shared.len();
+    if depth > digits[0].len() || digits[0][..depth] != digits[1][..depth] {
+        return Err(InvalidProof);
+    }
+    let below = if proof.branch.is_some() { depth + 1 } else { depth };
+    let mut ends = Vec::new();
+    for ((levels, leaf), digits) in proof.tails.iter().zip(&digits) {
+        let (child, value) = path_end(leaf.as_ref(), digits, below + levels.len())?;
+        ends.push((fold_levels(radix, hash_len, empty_hash.as_ref(), levels, &digits[below..], child)?, value));
+    }
+    let (b_hash, b_value) = ends.pop().expect("two tails");
+    let (a_hash, a_value) = ends.pop().expect("two tails");
+    let child = match proof.branch {
+        None if a_hash == b_hash => a_hash,
+        None => return Err(InvalidProof),
+        Some(ref siblings) => {
+            let (branch_a, branch_b) = (digits[0][depth], digits[1][depth]);
+            let taken = |digit: u8| digit == branch_a || digit == branch_b || usize::from(digit) >= radix.width();
+            if branch_a == branch_b || siblings.iter().any(|(digit, _)| taken(*digit)) {
+                return Err(InvalidProof);
+            }
+            let paths = a_hash.map(|hash| (branch_a, hash)).into_iter().chain(b_hash.map(|hash| (branch_b, hash)));
+            Some(inner_hash(radix, hash_len, empty_hash.as_ref(), siblings.iter().cloned().chain(paths)))
+        },
+    };
+    if fold_levels(radix, hash_len, empty_hash.as_ref(), &proof.shared, &digits[0], child)?.as_ref() == Some(root) {
+        Ok([a_value, b_value])
+    } else {
+        Err(InvalidProof)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ::*;
+
+    #[test]
+    fn test_merge() {
+        let key = |i: u32| hash(&i.to_be_bytes());
+        for radix in [Radix::Byte, Radix::Nibble] {
+            let mut tree = Tree::with_radix(radix);
+            for i in 0..300u32 {
+                tree.add(key(i), i.to_be_bytes().to_vec());
+            }
+            let root = tree.hash();
+            let prefix = |i: u32| radix.digits(&key(i).0)[0];
+            let (a, b) = (0..300).flat_map(|a| (a + 1..300).map(move |b| (a, b))).find(|&(a, b)| prefix(a) == prefix(b)).unwrap();
+            for (a, b) in [(key(a), key(b)), (key(a), key(1000)), (key(1000), key(1001)), (key(a), key(a))] {
+                let (proof_a, proof_b) = (tree.prove(&a), tree.prove(&b));
+                let separate = proof_a.to_bytes().len() + proof_b.to_bytes().len();
+                let merged = Proof::merge(proof_a, proof_b);
+                assert!(merged.to_bytes().len() < separate);
+                assert_eq!(verify_multi_proof(&tree.options(), &root, &a, &b, &merged), Ok([tree.get(&a), tree.get(&b)]));
+                assert_eq!(verify_multi_proof(&tree.options(), &hash(&[1]), &a, &b, &merged), Err(InvalidProof));
+                if a != b {
+                    assert_eq!(verify_multi_proof(&tree.options(), &root, &b, &a, &merged), Err(InvalidProof));
+                }
+            }
+        }
+        let empty: Tree = Tree::new();
+        assert_eq!(empty.batch_proof_size(&[key(0), key(1)]), Proof::merge(empty.prove(key(0)), empty.prove(key(1))).to_bytes().len());
+        let merged = Proof::merge(empty.prove(key(0)), empty.prove(key(1)));
+        assert_eq!(verify_multi_proof(&empty.options(), &EMPTY_ROOT, &key(0), &key(1), &merged), Ok([None, None]));
+    }
+
+    #[test]
+    fn test_forged_empty_hash() {
+        let key = hash(&0u32.to_be_bytes());
+        let mut tree = Tree::with_radix(Radix::Nibble);
+        for i in 0..200u32 {
+            tree.add(hash(&i.to_be_bytes()), i.to_be_bytes().to_vec());
+        }
+        let root = tree.hash();
+        let digit = Radix::Nibble.digits(&key.0)[0];
+        let child = match tree.root_node() {
+            Some(Node::InnerNode(inner)) => inner.child(digit).unwrap().my_hash(),
+            other => panic!("{:?}", other),
+        };
+        // the path ends in the full root, in the slot hashing as the empty slots
+        let mut forged = tree.prove(&key);
+        forged.empty_hash = Some(child.clone());
+        forged.levels.truncate(1);
+        forged.leaf = None;
+        let forged = Proof::merge(forged.clone(), forged);
+
+        let prover_options = Tree::<32>::builder().radix(Radix::Nibble).empty_hash(child);
+        assert_eq!(verify_multi_proof(&prover_options, &root, &key, &key, &forged), Ok([None, None]));
+        assert_eq!(verify_multi_proof(&tree.options(), &root, &key, &key, &forged), Err(InvalidProof));
+    }
+
+    #[test]
+    fn test_batch_proof_size() {
+        let key = |i: u32| hash(&i.to_be_bytes());
+        for radix in [Radix::Byte, Radix::Nibble] {
+            for (value_hashing, hash_len) in [(ValueHashing::Inline, 32), (ValueHashing::Digest, 16)] {
+                let mut tree: Tree = Tree::builder().radix(radix).value_hashing(value_hashing).hash_len(hash_len).build();
+                for i in 0..300u32 {
+                    tree.add(key(i), vec![i as u8; 40]);
+                }
+                let pairs = (0..300).map(|i| (i, i + 1)).chain([(2, 2), (3, 1000), (1000, 1001)]);
+                for (a, b) in pairs {
+                    let merged = Proof::merge(tree.prove(key(a)), tree.prove(key(b)));
+                    assert_eq!(tree.batch_proof_size(&[key(a), key(b)]), merged.to_bytes().len());
+                }
+                let keys: Vec<Sha256Hash> = (0..50).map(key).collect();
+                let separate: usize = keys.iter().map(|key| tree.prove(key).to_bytes().len()).sum();
+                assert!(tree.batch_proof_size(&keys) < separate);
+            }
+        }
+    }
+}
@@ -0,0 +1,117 @@
+use {Hashable, InnerNode, Leaf, Node, Radix, Sha256Hash, Tree};
+
+impl Node {
+    pub fn hash(&self) -> Sha256Hash {
+        self.my_hash()
+    }
+}
+
+impl InnerNode {
+    /// The children with their branch digit, in branch order
+    pub fn children(&self) -> impl Iterator<Item = (u8, &Node)> {
+        self.branches().into_iter().map(move |branch| (branch, &*self.map[&branch]))
+    }
+
+    pub fn child(&self, branch: u8) -> Option<&Node> {
+        self.map.get(&branch).map(|child| &**child)
+    }
+
+    pub fn radix(&self) -> Radix {
+        self.radix
+    }
+
+    /// The hash of the child in every slot, `None` for the empty ones and for the slots
+    /// past 16 with `Radix::Nibble`, to commit to the children in other ways.
+    pub fn child_hash_array(&self) -> [Option<Sha256Hash>; 256] {
+        std::array::from_fn(|branch| self.child(branch as u8).map(|child| child.hash()))
+    }
+
+    pub fn hash(&self) -> Sha256Hash {
+        self.my_hash()
+    }
+}
+
+impl Leaf {
+    /// The key digits not consumed by the inner nodes above the leaf
+    pub fn remaining_key(&self) -> &[u8] {
+        &self.remaining_key
+    }
+
+    /// The value as stored, encoded if the tree has a value codec
+    pub fn value(&self) -> &[u8] {
+        &self.value
+    }
+
+    pub fn hash(&self) -> Sha256Hash {
+        self.my_hash()
+    }
+}
+
+impl<const N: usize> Tree<N> {
+    /// The root, `None` if the tree is empty, for walks the tree methods don't provide.
+    pub fn root_node(&self) -> Option<&Node> {
+        self.root.as_ref()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ::*;
+
+    #[test]
+    fn test_root_node() {
+        let mut tree = Tree::default();
+        assert!(tree.root_node().is_none());
+        for i in 0u8..4 {
+            tree.add(Sha256Hash([i;32]), vec![i]);
+            let mut key = [i;32];
+            key[1] = 0xff;
+            tree.add(Sha256Hash(key), vec![i]);
+        }
+        let root = match tree.root_node() {
+            Some(Node::InnerNode(inner)) => inner,
+            other => panic!("{:?}", other),
+        };
+        assert_eq!(root.hash(), tree.hash());
+        assert_eq!(root.radix(), Radix::Byte);
+        assert_eq!(root.children().map(|(branch, _)| branch).collect::<Vec<_>>(), vec![0, 1, 2, 3]);
+
+        let inner = match root.child(2) {
+            Some(Node::InnerNode(inner)) => inner,
+            other => panic!("{:?}", other),
+        };
+        let (branch, leaf) = match inner.children().last() {
+            Some((branch, Node::Leaf(leaf))) => (branch, leaf),
+            other => panic!("{:?}", other),
+        };
+        assert_eq!(branch, 0xff);
+        assert_eq!(leaf.remaining_key(), &[2u8;30][..]);
+        assert_eq!(leaf.value(), &[2]);
+        assert_eq!(leaf.value_hashing(), ValueHashing::Inline);
+        let mut key = [2u8;32];
+        key[1] = 0xff;
+        assert_eq!(leaf.hash(), tree.leaf_metadata(&Sha256Hash(key)).unwrap().leaf_hash);
+        assert!(root.child(4).is_none());
+    }
+
+    #[test]
+    fn test_child_hash_array() {
+        for radix in [Radix::Byte, Radix::Nibble] {
+            let mut tree = Tree::with_radix(radix);
+            let keys: Vec<Sha256Hash> = (0u32..20).map(|i| hash(&i.to_be_bytes())).collect();
+            for key in &keys {
+                tree.add(key, vec![1]);
+            }
+            let root = match tree.root_node() {
+                Some(Node::InnerNode(inner)) => inner,
+                other => panic!("{:?}", other),
+            };
+            let slots = root.child_hash_array();
+            let first_digits: Vec<u8> = keys.iter().map(|key| radix.digits(&key.0)[0]).collect();
+            for (branch, slot) in slots.iter().enumerate() {
+                assert_eq!(slot.is_some(), first_digits.contains(&(branch as u8)));
+                assert_eq!(slot.clone(), root.child(branch as u8).map(|child| child.hash()));
+            }
+        }
+    }
+}
@@ -0,0 +1,68 @@
+use {Sha256Hash, Tree};
+
+/// A write of a log replayed by `Tree::apply_ops`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Op<const N: usize = 32> {
+    Insert(Sha256Hash<N>, Vec<u8>),
+    /// removing a key not in the tree does nothing
+    Remove(Sha256Hash<N>),
+}
+
+impl<const N: usize> Tree<N> {
+    /// Apply `ops` in order, recomputing the hashes once at the end. The tree is the same as
+    /// adding the inserted entries with `add` and splitting off the removed keys one by one.
+    pub fn apply_ops(&mut self, ops: &[Op<N>]) {
+        for op in ops {
+            match op {
+                Op::Insert(key, value) => self.insert(&key.0, value.clone()),
+                Op::Remove(key) => {
+                    #[cfg(feature = "tracing")]
+                    self.trace_lookup("remove", &key.0);
+                    let digits = self.radix.digits(&key.0);
+                    if self.root.as_mut().is_some_and(|root| root.remove_prefix(&digits)) {
+                        self.root = None;
+                    }
+                },
+            }
+        }
+        self.update_hashes();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ::*;
+
+    #[test]
+    fn test_apply_ops() {
+        let key = |i: u32| hash(&i.to_be_bytes());
+        for radix in [Radix::Byte, Radix::Nibble] {
+            let mut ops = Vec::new();
+            for i in 0..500u32 {
+                ops.push(match i % 5 {
+                    0 | 1 => Op::Insert(key(i % 150), i.to_be_bytes().to_vec()),
+                    2 => Op::Remove(key(i % 100)),
+                    3 => Op::Remove(key(1000 + i)),
+                    _ => Op::Insert(key(i % 40), vec![]),
+                });
+            }
+            let mut replayed = Tree::with_radix(radix);
+            replayed.apply_ops(&ops);
+            let mut tree = Tree::with_radix(radix);
+            for op in &ops {
+                match op {
+                    Op::Insert(key, value) => tree.add(key, value.clone()),
+                    Op::Remove(key) => drop(tree.split_off(&key.0)),
+                }
+            }
+            assert!(!tree.is_empty());
+            assert_eq!(replayed.hash(), tree.hash());
+            assert_eq!(replayed.to_entries(), tree.to_entries());
+            assert_eq!(replayed.check_invariants(), Ok(()));
+
+            let removes: Vec<_> = tree.to_entries().into_iter().map(|(key, _)| Op::Remove(key)).collect();
+            replayed.apply_ops(&removes);
+            assert_eq!(replayed.hash(), EMPTY_ROOT);
+        }
+    }
+}
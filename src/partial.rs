@@ -0,0 +1,498 @@
+use std::sync::Arc;
+use {ChildMap, Deserializable, DeserializeError, InnerNode, Leaf, LeafKey, Node, Radix, Sha256Hash, Tree, TreeBuilder, ValueHashing, EMPTY_ROOT, HASH_LEN};
+
+/// Returned when an operation reaches a subtree that has been pruned, the
+/// client has to fetch the subtree committed by the hash and retry.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Pruned(pub Sha256Hash);
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FillError {
+    /// the node doesn't hash to the pruned hash, the actual hash is returned
+    HashMismatch(Sha256Hash),
+    /// no subtree is pruned with the given hash
+    NotPruned,
+    /// an inner node branching on a different radix
+    RadixMismatch,
+    /// a leaf with the value hash, its serialization doesn't contain the value
+    ValueMissing,
+    /// a leaf committing to its full key, too short to be as deep as the pruned node
+    KeyTooShort,
+    Deserialize(DeserializeError),
+}
+
+/// An entry of a partial tree, or a subtree of it known only by its hash
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LeafOrPruned<const N: usize = 32> {
+    Leaf(Sha256Hash<N>, Vec<u8>),
+    /// the hash of the subtree and the key digits leading to it, as taken by `PartialTree::prune`
+    Pruned(Sha256Hash, Vec<u8>),
+}
+
+/// A tree keeping some subtrees only as their hash, so that a stateless client
+/// can store the parts of the tree it cares about and still know the root.
+#[derive(Debug, Clone)]
+pub struct PartialTree<const N: usize = 32> {
+    root: Option<Node>,
+    radix: Radix,
+    /// used for the added leaves
+    value_hashing: ValueHashing,
+    leaf_key: LeafKey,
+    hash_len: usize,
+    empty_hash: Option<Sha256Hash>,
+}
+
+impl<const N: usize> Default for PartialTree<N> {
+    fn default() -> Self {
+        PartialTree::from(Tree::new())
+    }
+}
+
+impl<const N: usize> PartialTree<N> {
+    /// The added leaves hash their value inline and commit to the remaining key, the
+    /// serialization doesn't tell
+    pub(crate) fn from_root(root: Node) -> Self {
+        let (radix, hash_len, empty_hash) = match root {
+            Node::InnerNode(ref inner) => (inner.radix, inner.hash_len, inner.empty_hash.clone()),
+            _ => (Radix::default(), HASH_LEN, None),
+        };
+        PartialTree {
+            root: Some(root),
+            radix,
+            value_hashing: ValueHashing::default(),
+            leaf_key: LeafKey::default(),
+            hash_len,
+            empty_hash,
+        }
+    }
+
+    pub fn add(&mut self, key: &Sha256Hash<N>, value: Vec<u8>) -> Result<(), Pruned> {
+        let key = self.radix.digits(&key.0);
+        let full_key = match self.leaf_key {
+            LeafKey::Remaining => None,
+            LeafKey::Full => Some(key.clone()),
+        };
+        match self.root {
+            None => {
+                self.root = Some(Node::Leaf(Leaf::keyed(key, full_key, value, self.value_hashing)));
+                Ok(())
+            },
+            Some(ref mut root) => {
+                root.add(key, value, self.radix, self.value_hashing, full_key.as_deref(), self.hash_len, &self.empty_hash)?;
+                root.update_hashes();
+                Ok(())
+            },
+        }
+    }
+
+    /// Remove `key`, returning whether it was in the tree. Fails with the pruned node on the
+    /// path, or with the pruned sibling of the leaf when it would be the only child left,
+    /// which collapses in place of the parent only if it's a leaf.
+    pub fn remove(&mut self, key: &Sha256Hash<N>) -> Result<bool, Pruned> {
+        let digits = self.radix.digits(&key.0);
+        #[cfg(feature = "tracing")]
+        ::trace::trace_remove(self.root.as_ref(), &digits);
+        let root = match self.root {
+            None => return Ok(false),
+            Some(ref mut root) => root,
+        };
+        let mut node = &*root;
+        let mut parent = None;
+        for digit in &digits {
+            match node {
+                Node::InnerNode(inner) => match inner.map.get(digit) {
+                    None => return Ok(false),
+                    Some(child) => {
+                        parent = Some((inner, *digit));
+                        node = child;
+                    },
+                },
+                _ => break,
+            }
+        }
+        match node {
+            Node::Leaf(leaf) if digits.ends_with(&leaf.remaining_key) => (),
+            Node::Pruned(hash) => return Err(Pruned(hash.clone())),
+            _ => return Ok(false),
+        }
+        if let Some((inner, branch)) = parent {
+            if inner.map.len() == 2 {
+                if let Some(Node::Pruned(hash)) = inner.map.iter().find(|(digit, _)| **digit != branch).map(|(_, sibling)| &**sibling) {
+                    return Err(Pruned(hash.clone()));
+                }
+            }
+        }
+        if root.remove_prefix(&digits) {
+            self.root = None;
+        } else {
+            root.update_hashes();
+        }
+        Ok(true)
+    }
+
+    /// Add the leaves with the value hashing and the leaf key of `options`, returns false if
+    /// the inner nodes have other options than those it builds
+    pub(crate) fn take_options(&mut self, options: &TreeBuilder<N>) -> bool {
+        self.value_hashing = options.value_hashing;
+        self.leaf_key = options.leaf_key;
+        options.builds(self.radix, self.hash_len, self.empty_hash.as_ref()) && self.root.as_ref().is_none_or(|root| options.builds_node(root))
+    }
+
+    /// Recompute the hash of every node but the pruned ones, see `Tree::verify_hashes`
+    pub fn verify_hashes(&self) -> Result<(), Sha256Hash> {
+        match self.root {
+            None => Ok(()),
+            Some(ref root) => root.verify_hashes(),
+        }
+    }
+
+    pub fn get(&self, key: &Sha256Hash<N>) -> Result<Option<Vec<u8>>, Pruned> {
+        match self.root {
+            None => Ok(None),
+            Some(ref root) => root.get(self.radix.digits(&key.0)),
+        }
+    }
+
+    pub fn hash(&self) -> Sha256Hash {
+        match self.root {
+            None => EMPTY_ROOT,
+            Some(ref root) => root.my_hash(),
+        }
+    }
+
+    /// The entries in key order, with a marker in place of the pruned subtrees telling
+    /// where the keys they hide may be.
+    pub fn iter_pruned(&self) -> impl Iterator<Item = LeafOrPruned<N>> {
+        let mut items = Vec::new();
+        if let Some(ref root) = self.root {
+            root.leaves_or_pruned(self.radix, &mut Vec::new(), &mut items);
+        }
+        items.into_iter()
+    }
+
+    /// Replace the subtree reached following `prefix` with its hash, returning it.
+    /// Returns `None` if `prefix` doesn't lead to a node.
+    pub fn prune(&mut self, prefix: &[u8]) -> Option<Sha256Hash> {
+        match self.root {
+            None => None,
+            Some(ref mut root) => root.prune(prefix),
+        }
+    }
+
+    /// Replace the subtrees pruned as `hash` with the serialized `node`, fetched by the client
+    /// after an operation returned `Pruned(hash)`. The children of an inner node are known
+    /// only by their hash, so they stay pruned until filled in turn.
+    pub fn fill(&mut self, hash: &Sha256Hash, node: &[u8]) -> Result<(), FillError> {
+        let actual = ::hash(node);
+        if actual != *hash {
+            return Err(FillError::HashMismatch(actual));
+        }
+        let node = match Node::deserialize(node).map_err(FillError::Deserialize)? {
+            Node::Pruned(_) => return Err(FillError::ValueMissing),
+            Node::InnerNode(ref inner) if inner.radix != self.radix => return Err(FillError::RadixMismatch),
+            node => node,
+        };
+        match self.root {
+            Some(ref mut root) if root.contains_pruned(hash) => root.fill(hash, &node, 0),
+            _ => Err(FillError::NotPruned),
+        }
+    }
+}
+
+impl<const N: usize> Tree<N> {
+    /// Partial tree keeping only the nodes on the paths of `keys`, with the same root hash.
+    pub fn prune_to_keys(&self, keys: &[Sha256Hash<N>]) -> PartialTree<N> {
+        let digits: Vec<Vec<u8>> = keys.iter().map(|key| self.radix.digits(&key.0)).collect();
+        let digits: Vec<&[u8]> = digits.iter().map(|key| &key[..]).collect();
+        PartialTree {
+            root: self.root.as_ref().map(|root| root.prune_to_keys(&digits)),
+            radix: self.radix,
+            value_hashing: self.value_hashing,
+            leaf_key: self.leaf_key,
+            hash_len: self.hash_len,
+            empty_hash: self.empty_hash.clone(),
+        }
+    }
+}
+
+impl<const N: usize> From<Tree<N>> for PartialTree<N> {
+    fn from(tree: Tree<N>) -> Self {
+        PartialTree {
+            root: tree.root,
+            radix: tree.radix,
+            value_hashing: tree.value_hashing,
+            leaf_key: tree.leaf_key,
+            hash_len: tree.hash_len,
+            empty_hash: tree.empty_hash,
+        }
+    }
+}
+
+impl Node {
+    /// Copy of the node with the subtrees not on the path of any of `keys` pruned
+    fn prune_to_keys(&self, keys: &[&[u8]]) -> Node {
+        match self {
+            Node::InnerNode(inner) => {
+                let mut map = ChildMap::new();
+                for (branch, child) in &inner.map {
+                    let rest: Vec<&[u8]> = keys.iter().filter(|key| key[0] == *branch).map(|key| &key[1..]).collect();
+                    let node = if rest.is_empty() {
+                        Node::Pruned(child.my_hash())
+                    } else {
+                        child.prune_to_keys(&rest)
+                    };
+                    map.insert(*branch, Arc::new(node));
+                }
+                let mut pruned = InnerNode::new(map, inner.radix, inner.hash_len, inner.empty_hash.clone());
+                pruned.hash = inner.hash.clone();
+                Node::InnerNode(pruned)
+            },
+            _ => self.clone(),
+        }
+    }
+
+    fn leaves_or_pruned<const N: usize>(&self, radix: Radix, path: &mut Vec<u8>, items: &mut Vec<LeafOrPruned<N>>) {
+        match self {
+            Node::Leaf(leaf) => {
+                let mut digits = path.clone();
+                digits.extend(&leaf.remaining_key);
+                items.push(LeafOrPruned::Leaf(radix.key(&digits), leaf.value.clone()));
+            },
+            Node::InnerNode(inner) => {
+                for branch in inner.branches() {
+                    path.push(branch);
+                    inner.map[&branch].leaves_or_pruned(radix, path, items);
+                    path.pop();
+                }
+            },
+            Node::Pruned(hash) => items.push(LeafOrPruned::Pruned(hash.clone(), path.clone())),
+        }
+    }
+
+    /// Replace the nodes pruned as `hash` with `node`, `depth` levels down
+    fn fill(&mut self, hash: &Sha256Hash, node: &Node, depth: usize) -> Result<(), FillError> {
+        match self {
+            Node::Pruned(pruned) if pruned == hash => {
+                *self = node.clone().at_depth(depth).ok_or(FillError::KeyTooShort)?;
+            },
+            Node::InnerNode(inner) => {
+                for child in inner.map.values_mut() {
+                    if child.contains_pruned(hash) {
+                        Arc::make_mut(child).fill(hash, node, depth + 1)?;
+                    }
+                }
+            },
+            _ => (),
+        }
+        Ok(())
+    }
+
+    fn contains_pruned(&self, hash: &Sha256Hash) -> bool {
+        match self {
+            Node::Pruned(pruned) => pruned == hash,
+            Node::InnerNode(inner) => inner.map.values().any(|child| child.contains_pruned(hash)),
+            Node::Leaf(_) => false,
+        }
+    }
+
+    fn prune(&mut self, prefix: &[u8]) -> Option<Sha256Hash> {
+        if prefix.is_empty() {
+            let hash = self.my_hash();
+            *self = Node::Pruned(hash.clone());
+            return Some(hash);
+        }
+        match self {
+            Node::InnerNode(inner) => Arc::make_mut(inner.map.get_mut(&prefix[0])?).prune(&prefix[1..]),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ::*;
+
+    fn four_keys_tree() -> (Tree, Vec<Sha256Hash>) {
+        let mut tree = Tree::default();
+        let keys: Vec<Sha256Hash> = (0u8..4).map(|i| Sha256Hash([i;32])).collect();
+        for (i, key) in keys.iter().enumerate() {
+            tree.add(key, vec![i as u8]);
+        }
+        (tree, keys)
+    }
+
+    #[test]
+    fn test_prune_all_but_one() {
+        let (tree, keys) = four_keys_tree();
+        let root = tree.hash();
+        let mut partial = PartialTree::from(tree);
+
+        let mut pruned = Vec::new();
+        for i in 1u8..4 {
+            pruned.push(partial.prune(&[i]).unwrap());
+        }
+        assert_eq!(partial.hash(), root);
+        assert_eq!(partial.get(&keys[0]), Ok(Some(vec![0])));
+        for (key, hash) in keys[1..].iter().zip(pruned) {
+            assert_eq!(partial.get(key), Err(Pruned(hash)));
+        }
+        assert!(partial.prune(&[4]).is_none());
+    }
+
+    #[test]
+    fn test_prune_to_keys() {
+        let mut tree = Tree::default();
+        for i in 0u32..100 {
+            tree.add(hash(&i.to_be_bytes()), vec![i as u8]);
+        }
+        let (a, b) = (hash(&3u32.to_be_bytes()), hash(&70u32.to_be_bytes()));
+        let partial = tree.prune_to_keys(&[a.clone(), b.clone()]);
+        assert_eq!(partial.hash(), tree.hash());
+        assert_eq!(partial.get(&a), Ok(Some(vec![3])));
+        assert_eq!(partial.get(&b), Ok(Some(vec![70])));
+        assert!(partial.get(&hash(&50u32.to_be_bytes())).is_err());
+        for key in &[a, b] {
+            assert_eq!(verify_proof(&tree.options(), &partial.hash(), key, &tree.prove(key)), Ok(partial.get(key).unwrap()));
+        }
+        assert_eq!(tree.prune_to_keys(&[]).hash(), tree.hash());
+        assert!(tree.prune_to_keys(&[]).get(&hash(&3u32.to_be_bytes())).is_err());
+
+        // the paths of absent keys are kept too, reading them proves the absence
+        for radix in [Radix::Byte, Radix::Nibble] {
+            let mut tree = Tree::with_radix(radix);
+            for i in 0u32..300 {
+                tree.add(hash(&i.to_be_bytes()), vec![i as u8]);
+            }
+            let keys: Vec<Sha256Hash> = [5u32, 150, 1000, 1001].iter().map(|i| hash(&i.to_be_bytes())).collect();
+            let partial = tree.prune_to_keys(&keys);
+            assert_eq!(partial.hash(), tree.hash());
+            for key in &keys {
+                assert_eq!(partial.get(key), Ok(tree.get(key)));
+            }
+            // the other keys are pruned, but those in the leaves on the paths of the absent keys
+            let others = (0u32..300).filter(|i| ![5, 150].contains(i)).map(|i| partial.get(&hash(&i.to_be_bytes())));
+            assert!(others.filter(Result::is_ok).count() <= 2);
+            assert!(partial.iter_pruned().any(|item| matches!(item, LeafOrPruned::Pruned(..))));
+        }
+    }
+
+    #[test]
+    fn test_add_on_partial() {
+        let (tree, keys) = four_keys_tree();
+        let mut partial = PartialTree::from(tree);
+        let pruned = partial.prune(&[1]).unwrap();
+        let root = partial.hash();
+
+        assert_eq!(partial.add(&keys[1], vec![0xff]), Err(Pruned(pruned)));
+        assert_eq!(partial.hash(), root);
+
+        let (mut tree, _) = four_keys_tree();
+        let key = Sha256Hash([4u8;32]);
+        tree.add(&key, vec![4]);
+        partial.add(&key, vec![4]).unwrap();
+        assert_eq!(partial.hash(), tree.hash());
+        assert_eq!(partial.get(&key), Ok(Some(vec![4])));
+    }
+
+    #[test]
+    fn test_iter_pruned() {
+        let (tree, keys) = four_keys_tree();
+        assert_eq!(PartialTree::<32>::default().iter_pruned().count(), 0);
+        let full: Vec<_> = PartialTree::from(tree.clone()).iter_pruned().collect();
+        let entries: Vec<_> = tree.to_entries().into_iter().map(|(key, value)| LeafOrPruned::Leaf(key, value)).collect();
+        assert_eq!(full, entries);
+
+        let partial = tree.prune_to_keys(&keys[1..3]);
+        let items: Vec<_> = partial.iter_pruned().collect();
+        let leaf_hash = |i: usize| tree.leaf_metadata(&keys[i]).unwrap().leaf_hash;
+        assert_eq!(items, vec![
+            LeafOrPruned::Pruned(leaf_hash(0), vec![0]),
+            LeafOrPruned::Leaf(keys[1].clone(), vec![1]),
+            LeafOrPruned::Leaf(keys[2].clone(), vec![2]),
+            LeafOrPruned::Pruned(leaf_hash(3), vec![3]),
+        ]);
+
+        let mut partial = PartialTree::from(tree.clone());
+        let hash = partial.prune(&[]).unwrap();
+        assert_eq!(partial.iter_pruned().collect::<Vec<_>>(), vec![LeafOrPruned::Pruned(hash, vec![])]);
+    }
+
+    #[test]
+    fn test_fill() {
+        let mut tree = Tree::default();
+        for i in 0u8..4 {
+            tree.add(Sha256Hash([i;32]), vec![i]);
+            let mut key = [i;32];
+            key[1] = 0xff;
+            tree.add(Sha256Hash(key), vec![i]);
+        }
+        let mut store = std::collections::HashMap::new();
+        tree.persist(&mut store);
+        let root = tree.hash();
+        let mut partial = PartialTree::from(tree.clone());
+        let pruned = partial.prune(&[2]).unwrap();
+        let mut key = [2u8;32];
+        key[1] = 0xff;
+        assert_eq!(partial.get(&Sha256Hash(key)), Err(Pruned(pruned.clone())));
+
+        assert_eq!(partial.fill(&pruned, &[0x00]), Err(FillError::HashMismatch(hash(&[0x00]))));
+        assert_eq!(partial.fill(&hash(&[0x00]), &[0x00]), Err(FillError::Deserialize(DeserializeError::UnknownNodeType(0x00))));
+        let leaf = tree.leaf_metadata(&Sha256Hash([1u8;32])).unwrap().leaf_hash;
+        assert_eq!(partial.fill(&leaf, &store[&leaf]), Err(FillError::NotPruned));
+
+        // the inner node comes with its children pruned
+        partial.fill(&pruned, &store[&pruned]).unwrap();
+        assert_eq!(partial.hash(), root);
+        let child = match partial.get(&Sha256Hash(key)) {
+            Err(Pruned(child)) => child,
+            other => panic!("{:?}", other),
+        };
+        partial.fill(&child, &store[&child]).unwrap();
+        assert_eq!(partial.get(&Sha256Hash(key)), Ok(Some(vec![2])));
+        // the leaf under the other branch has the same remaining key and value, so the same hash
+        assert_eq!(partial.get(&Sha256Hash([2u8;32])), Ok(Some(vec![2])));
+        assert_eq!(partial.hash(), root);
+        assert_eq!(partial.fill(&child, &store[&child]), Err(FillError::NotPruned));
+
+        let mut nibble = PartialTree::from(Tree::<32>::with_radix(Radix::Nibble));
+        nibble.add(&Sha256Hash([1u8;32]), vec![1]).unwrap();
+        nibble.add(&Sha256Hash([2u8;32]), vec![2]).unwrap();
+        assert_eq!(nibble.fill(&root, &store[&root]), Err(FillError::RadixMismatch));
+
+        // a leaf committing to its full key fills a node as deep as its last digit, not deeper
+        let mut short: Tree<1> = Tree::builder().leaf_key(LeafKey::Full).build();
+        short.add([0u8], vec![0]);
+        short.add([1u8], vec![1]);
+        let mut store = std::collections::HashMap::new();
+        short.persist(&mut store);
+        let leaf = short.leaf_metadata(&Sha256Hash([1u8])).unwrap().leaf_hash;
+        let mut partial = short.prune_to_keys(&[Sha256Hash([0u8])]);
+        partial.fill(&leaf, &store[&leaf]).unwrap();
+        assert_eq!(partial.get(&Sha256Hash([1u8])), Ok(Some(vec![1])));
+        assert_eq!(partial.hash(), short.hash());
+        let inner = |digit: u8, child: Node| Node::InnerNode(InnerNode::new(std::iter::once((digit, Arc::new(child))).collect(), Radix::Byte, HASH_LEN, None));
+        let mut deep: PartialTree<2> = PartialTree::from_root(inner(0, inner(1, Node::Pruned(leaf.clone()))));
+        assert_eq!(deep.fill(&leaf, &store[&leaf]), Err(FillError::KeyTooShort));
+    }
+
+    #[test]
+    fn test_remove_on_partial() {
+        let mut tree = Tree::default();
+        let mut key = [1u8;32];
+        tree.add(key, vec![1]);
+        key[31] = 2;
+        tree.add(key, vec![2]);
+        let mut partial = tree.prune_to_keys(&[Sha256Hash(key)]);
+        key[31] = 1;
+        let sibling = tree.leaf_metadata(&Sha256Hash(key)).unwrap().leaf_hash;
+        key[31] = 2;
+        assert_eq!(partial.remove(&Sha256Hash(key)), Err(Pruned(sibling)));
+        let mut partial = PartialTree::from(tree);
+        assert_eq!(partial.remove(&Sha256Hash([3u8;32])), Ok(false));
+        assert_eq!(partial.remove(&Sha256Hash(key)), Ok(true));
+        assert_eq!(partial.remove(&Sha256Hash(key)), Ok(false));
+        let mut expected = Tree::default();
+        expected.add([1u8;32], vec![1]);
+        assert_eq!(partial.hash(), expected.hash());
+    }
+}
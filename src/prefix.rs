@@ -0,0 +1,161 @@
+use proof::{content_leaf, fold_levels, LeafContent, Siblings};
+use {Hashable, Node, Sha256Hash, Tree, TreeBuilder, EMPTY_ROOT, NO_PRUNED};
+
+/// Proves the hash of the subtree holding the keys under a prefix, the hash of the tree
+/// `Tree::clone_subtree` would return for the prefix, against the root of the whole tree.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PrefixProof {
+    /// one entry for every inner node on the path of the prefix
+    levels: Vec<Siblings>,
+    /// the leaf reached before the end of the prefix, if any
+    leaf: Option<LeafContent>,
+    subtree_root: Sha256Hash,
+}
+
+impl PrefixProof {
+    /// Hash of the subtree under the prefix, `Tree::EMPTY_ROOT` if no key has the prefix
+    pub fn subtree_root(&self) -> &Sha256Hash {
+        &self.subtree_root
+    }
+}
+
+impl<const N: usize> Tree<N> {
+    /// Prove the hash of the subtree under `prefix`, see `verify_prefix`.
+    pub fn prove_prefix(&self, prefix: &[u8]) -> PrefixProof {
+        let digits = self.radix.digits(prefix);
+        let mut levels = Vec::new();
+        let mut leaf = None;
+        let mut node = self.root.as_ref();
+        for digit in &digits {
+            node = match node {
+                Some(Node::InnerNode(inner)) => {
+                    levels.push(inner.siblings(*digit));
+                    inner.map.get(digit).map(|child| &**child)
+                },
+                Some(Node::Leaf(reached)) => {
+                    leaf = Some(reached.content());
+                    break;
+                },
+                Some(Node::Pruned(_)) => panic!("{}", NO_PRUNED),
+                None => break,
+            };
+        }
+        let subtree_root = match (&leaf, node) {
+            (Some(leaf), _) => subtree_leaf(leaf, &digits[..levels.len()], &digits[levels.len()..]),
+            (None, Some(node)) => node.my_hash(),
+            (None, None) => EMPTY_ROOT,
+        };
+        PrefixProof {
+            levels,
+            leaf,
+            subtree_root,
+        }
+    }
+}
+
+/// Hash of the subtree under the prefix when a leaf is reached below the `above` digits with
+/// `rest` digits of the prefix left, the leaf without them or the empty tree if its key
+/// doesn't have them
+fn subtree_leaf(leaf: &LeafContent, above: &[u8], rest: &[u8]) -> Sha256Hash {
+    let remaining_key = &leaf.0;
+    if remaining_key.starts_with(rest) {
+        content_leaf(leaf, above).moved(remaining_key[rest.len()..].to_vec()).my_hash()
+    } else {
+        EMPTY_ROOT
+    }
+}
+
+/// Check that the keys under `prefix` in the tree built with `options` with the given `root`
+/// are those of a subtree hashing to `subtree_root`, `Tree::EMPTY_ROOT` if there are none.
+pub fn verify_prefix<const N: usize>(options: &TreeBuilder<N>, proof: &PrefixProof, prefix: &[u8], subtree_root: &Sha256Hash, root: &Sha256Hash) -> bool {
+    let digits = options.radix.digits(prefix);
+    let depth = proof.levels.len();
+    if depth > digits.len() || proof.subtree_root != *subtree_root {
+        return false;
+    }
+    let child = match proof.leaf {
+        Some(ref leaf) => {
+            if depth == digits.len() || subtree_leaf(leaf, &digits[..depth], &digits[depth..]) != *subtree_root {
+                return false;
+            }
+            Some(content_leaf(leaf, &digits[..depth]).my_hash())
+        },
+        // the path ends in an empty slot, or in the empty tree
+        None if *subtree_root == EMPTY_ROOT => None,
+        None if depth == digits.len() => Some(subtree_root.clone()),
+        None => return false,
+    };
+    let folded = fold_levels(options.radix, options.hash_len, options.truncated_empty_hash().as_ref(), &proof.levels, &digits, child);
+    match folded {
+        Ok(Some(hash)) => hash == *root,
+        Ok(None) => *root == EMPTY_ROOT,
+        Err(_) => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ::*;
+
+    #[test]
+    fn test_prove_prefix() {
+        for radix in [Radix::Byte, Radix::Nibble] {
+            let empty: Tree = Tree::with_radix(radix);
+            let proof = empty.prove_prefix(&[1]);
+            assert_eq!(proof.subtree_root(), &EMPTY_ROOT);
+            assert!(verify_prefix(&empty.options(), &proof, &[1], &EMPTY_ROOT, &empty.hash()));
+
+            let mut tree: Tree = Tree::with_radix(radix);
+            for i in 0u32..300 {
+                tree.add(hash(&i.to_be_bytes()), vec![i as u8]);
+            }
+            let root = tree.hash();
+            for prefix in [vec![], vec![0x10], vec![0x10, 0x20], tree.to_entries()[7].0 .0[..3].to_vec(), tree.to_entries()[7].0 .0[..20].to_vec()] {
+                let proof = tree.prove_prefix(&prefix);
+                let subtree_root = match prefix.len() {
+                    0 => tree.hash(),
+                    1 => tree.clone_subtree::<31>(&prefix).map(|subtree| subtree.hash()).unwrap_or(EMPTY_ROOT),
+                    2 => tree.clone_subtree::<30>(&prefix).map(|subtree| subtree.hash()).unwrap_or(EMPTY_ROOT),
+                    3 => tree.clone_subtree::<29>(&prefix).map(|subtree| subtree.hash()).unwrap_or(EMPTY_ROOT),
+                    _ => tree.clone_subtree::<12>(&prefix).map(|subtree| subtree.hash()).unwrap_or(EMPTY_ROOT),
+                };
+                assert_eq!(proof.subtree_root(), &subtree_root, "{:?}", prefix);
+                assert!(verify_prefix(&tree.options(), &proof, &prefix, &subtree_root, &root));
+                assert!(!verify_prefix(&tree.options(), &proof, &prefix, &hash(&[1]), &root));
+                assert!(!verify_prefix(&tree.options(), &proof, &prefix, &subtree_root, &hash(&[1])));
+                if !prefix.is_empty() {
+                    let mut other = prefix.clone();
+                    other[0] ^= 1;
+                    assert!(!verify_prefix(&tree.options(), &proof, &other, &subtree_root, &root) || subtree_root == EMPTY_ROOT);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_forged_empty_hash() {
+        let mut tree: Tree = Tree::with_radix(Radix::Nibble);
+        for i in 0u32..200 {
+            tree.add(hash(&i.to_be_bytes()), vec![i as u8]);
+        }
+        let root = tree.hash();
+        let prefix = hash(&0u32.to_be_bytes()).0[..1].to_vec();
+        let inner = match tree.root_node() {
+            Some(Node::InnerNode(inner)) => inner,
+            other => panic!("{:?}", other),
+        };
+        let digit = Radix::Nibble.digits(&prefix)[0];
+        let child = inner.child(digit).unwrap().my_hash();
+        // the keys under the prefix are hidden in the full root, in the slot hashing as the
+        // empty slots
+        let forged = PrefixProof {
+            levels: vec![inner.siblings(digit)],
+            leaf: None,
+            subtree_root: EMPTY_ROOT,
+        };
+
+        let prover_options = Tree::<32>::builder().radix(Radix::Nibble).empty_hash(child);
+        assert!(verify_prefix(&prover_options, &forged, &prefix, &EMPTY_ROOT, &root));
+        assert!(!verify_prefix(&tree.options(), &forged, &prefix, &EMPTY_ROOT, &root));
+    }
+}
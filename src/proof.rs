@@ -0,0 +1,784 @@
+use std::borrow::Borrow;
+use std::collections::HashSet;
+use std::io::{self, Write};
+use std::sync::Arc;
+use integer_encoding::VarInt;
+use {Hashable, InnerNode, Leaf, LeafKey, Node, Pruned, Radix, Serializable, Sha256Hash, Tree, TreeBuilder, ValueHashing, EMPTY_ROOT, HASH_LEN, NO_PRUNED};
+
+/// Populated children of an inner node other than the one the path continues into, the
+/// empty slots are left out and known to the verifier by the missing branch digits
+pub(crate) type Siblings = Vec<(u8, Sha256Hash)>;
+
+/// Remaining key, value, value hashing and leaf key of a leaf
+pub(crate) type LeafContent = (Vec<u8>, Vec<u8>, ValueHashing, LeafKey);
+
+impl Leaf {
+    pub(crate) fn content(&self) -> LeafContent {
+        (self.remaining_key.clone(), self.value.clone(), self.value_hashing(), self.leaf_key())
+    }
+}
+
+/// The leaf with `content` below the inner nodes of the `above` key digits, which complete
+/// the key it commits to with `LeafKey::Full`
+pub(crate) fn content_leaf(content: &LeafContent, above: &[u8]) -> Leaf {
+    let (ref remaining_key, ref value, value_hashing, leaf_key) = *content;
+    let full_key = match leaf_key {
+        LeafKey::Remaining => None,
+        LeafKey::Full => Some([above, remaining_key].concat()),
+    };
+    Leaf::keyed(remaining_key.clone(), full_key, value.clone(), value_hashing)
+}
+
+/// Proves the value stored for a key, or its absence, against a root hash.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Proof {
+    pub(crate) radix: Radix,
+    /// bytes of the sibling hashes, the rest is zeroed
+    pub(crate) hash_len: usize,
+    /// hash of the empty slots, see `TreeBuilder::empty_hash`
+    pub(crate) empty_hash: Option<Sha256Hash>,
+    /// one entry for every inner node on the path
+    pub(crate) levels: Vec<Siblings>,
+    /// the leaf the path ends in, `None` if it ends in an empty slot
+    pub(crate) leaf: Option<LeafContent>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InvalidProof;
+
+/// The radix byte, with the high bit set if the hashes are truncated, followed then by their
+/// length, and the next bit set if the empty slots have a hash, followed then by it truncated
+pub(crate) fn write_radix<W: Write>(w: &mut W, radix: Radix, hash_len: usize, empty_hash: Option<&Sha256Hash>) -> io::Result<()> {
+    let mut radix = match radix {
+        Radix::Byte => 0x00,
+        Radix::Nibble => 0x01,
+    };
+    if empty_hash.is_some() {
+        radix |= 0x40;
+    }
+    if hash_len == HASH_LEN {
+        w.write_all(&[radix])?;
+    } else {
+        w.write_all(&[radix | 0x80, hash_len as u8])?;
+    }
+    match empty_hash {
+        Some(empty_hash) => w.write_all(&empty_hash.0[..hash_len]),
+        None => Ok(()),
+    }
+}
+
+/// Levels are marked with the InnerNode type byte, so the proof can be written while walking the path
+pub(crate) fn write_level<W: Write>(w: &mut W, siblings: &[(u8, Sha256Hash)], hash_len: usize) -> io::Result<()> {
+    w.write_all(&[0x01])?;
+    w.write_all(&siblings.len().encode_var_vec())?;
+    for (branch, hash) in siblings {
+        w.write_all(&[*branch])?;
+        w.write_all(&hash.0[..hash_len])?;
+    }
+    Ok(())
+}
+
+/// The serialization of a leaf with the value hash is followed by the length prefixed value.
+/// The proof doesn't hold the digits above the leaf, it's written as the root of a tree,
+/// with `LeafKey::Full` the key is the remaining key and the verifier completes it.
+pub(crate) fn write_leaf<W: Write>(w: &mut W, leaf: &Leaf) -> io::Result<()> {
+    w.write_all(&leaf.serialize())?;
+    if leaf.value_hash.is_some() {
+        w.write_all(&leaf.value.len().encode_var_vec())?;
+        w.write_all(&leaf.value)?;
+    }
+    Ok(())
+}
+
+impl Proof {
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut result = Vec::new();
+        self.write_to(&mut result).expect("writing to a Vec doesn't fail");
+        result
+    }
+
+    fn write_to<W: Write>(&self, w: &mut W) -> io::Result<()> {
+        write_radix(w, self.radix, self.hash_len, self.empty_hash.as_ref())?;
+        for siblings in &self.levels {
+            write_level(w, siblings, self.hash_len)?;
+        }
+        match self.leaf {
+            None => w.write_all(&[0x00]),
+            Some(ref leaf) => write_leaf(w, &content_leaf(leaf, &[])),
+        }
+    }
+}
+
+/// Check `proof` for `key` against `root` of a tree built with `options`, returning the
+/// proven value or `None` if the proof shows the key is absent.
+///
+/// The proof carries no branch digits of the path, they are taken from `key`, and the
+/// value is returned only if the leaf remaining key completes the consumed digits to
+/// `key`, so a proof cannot be replayed for another key sharing the leaf suffix. The inner
+/// nodes are hashed with `options`, the options written in the proof are not trusted.
+pub fn verify_proof<const N: usize>(options: &TreeBuilder<N>, root: &Sha256Hash, key: &Sha256Hash<N>, proof: &Proof) -> Result<Option<Vec<u8>>, InvalidProof> {
+    let digits = options.radix.digits(&key.0);
+    let (child, value) = path_end(proof.leaf.as_ref(), &digits, proof.levels.len())?;
+    if fold_levels(options.radix, options.hash_len, options.truncated_empty_hash().as_ref(), &proof.levels, &digits, child)?.as_ref() == Some(root) {
+        Ok(value)
+    } else {
+        Err(InvalidProof)
+    }
+}
+
+/// Hash of the node the path of `digits` ends in at `depth`, `None` for an empty slot, and
+/// the value if `leaf` holds the key of `digits`.
+pub(crate) fn path_end(leaf: Option<&LeafContent>, digits: &[u8], depth: usize) -> Result<(Option<Sha256Hash>, Option<Vec<u8>>), InvalidProof> {
+    if depth > digits.len() {
+        return Err(InvalidProof);
+    }
+    match leaf {
+        None if depth == 0 => Ok((Some(EMPTY_ROOT), None)),
+        None => Ok((None, None)),
+        Some(leaf) => {
+            let (ref remaining_key, ref value, _, _) = *leaf;
+            if depth + remaining_key.len() != digits.len() {
+                return Err(InvalidProof);
+            }
+            let found = if remaining_key[..] == digits[depth..] { Some(value.clone()) } else { None };
+            Ok((Some(content_leaf(leaf, &digits[..depth]).my_hash()), found))
+        },
+    }
+}
+
+/// Check that every item is a proof of its key holding its value against `root` of a tree
+/// built with `options`, returning the index of the first that isn't. The nodes
+/// authenticated by a proof are remembered by their position, so the proofs sharing them
+/// stop hashing up when they reach one.
+pub fn bulk_verify<const N: usize>(options: &TreeBuilder<N>, root: &Sha256Hash, items: &[(Sha256Hash<N>, Vec<u8>, Proof)]) -> Result<(), usize> {
+    // digits of the path and hash of the authenticated nodes
+    let mut known: HashSet<(Vec<u8>, Sha256Hash)> = HashSet::new();
+    let empty_hash = options.truncated_empty_hash();
+    for (i, (key, value, proof)) in items.iter().enumerate() {
+        let digits = options.radix.digits(&key.0);
+        let depth = proof.levels.len();
+        let mut child = match proof.leaf {
+            Some(ref leaf) if depth <= digits.len() && leaf.0[..] == digits[depth..] && leaf.1 == *value => {
+                content_leaf(leaf, &digits[..depth]).my_hash()
+            },
+            _ => return Err(i),
+        };
+        let position = |depth: usize, hash: &Sha256Hash| (digits[..depth].to_vec(), hash.clone());
+        let mut path = vec![position(depth, &child)];
+        let mut authenticated = false;
+        for level in (0..depth).rev() {
+            if known.contains(path.last().expect("starts with the leaf")) {
+                authenticated = true;
+                break;
+            }
+            let folded = fold_levels(options.radix, options.hash_len, empty_hash.as_ref(), &proof.levels[level..=level], &digits[level..], Some(child));
+            child = match folded {
+                Ok(Some(hash)) => hash,
+                _ => return Err(i),
+            };
+            path.push(position(level, &child));
+        }
+        if !authenticated && child != *root {
+            return Err(i);
+        }
+        known.extend(path);
+    }
+    Ok(())
+}
+
+/// Hash of the inner node of a tree built with the default options but `radix`, with the
+/// given branch digits and children hashes, as the verification folds the proof levels.
+pub fn combine(radix: Radix, children: &[(u8, Sha256Hash)]) -> Sha256Hash {
+    assert!(children.iter().all(|(branch, _)| usize::from(*branch) < radix.width()), "branch digit out of the radix");
+    inner_hash(radix, HASH_LEN, None, children.iter().cloned())
+}
+
+/// Hash of an inner node with the given children hashes
+pub(crate) fn inner_hash<I: IntoIterator<Item = (u8, Sha256Hash)>>(radix: Radix, hash_len: usize, empty_hash: Option<&Sha256Hash>, children: I) -> Sha256Hash {
+    let map = children.into_iter().map(|(branch, hash)| (branch, Arc::new(Node::Pruned(hash)))).collect();
+    let mut inner = InnerNode::new(map, radix, hash_len, empty_hash.cloned());
+    inner.update();
+    inner.my_hash()
+}
+
+/// Hash the path up from `child`, the hash at the end of the `digits` path or `None` for an
+/// empty slot, through the siblings of every level.
+pub(crate) fn fold_levels(radix: Radix, hash_len: usize, empty_hash: Option<&Sha256Hash>, levels: &[Siblings], digits: &[u8], mut child: Option<Sha256Hash>) -> Result<Option<Sha256Hash>, InvalidProof> {
+    for (siblings, &branch) in levels.iter().zip(digits.iter()).rev() {
+        if siblings.iter().any(|(sibling, _)| *sibling == branch || usize::from(*sibling) >= radix.width()) {
+            return Err(InvalidProof);
+        }
+        let path = child.map(|hash| (branch, hash));
+        child = Some(inner_hash(radix, hash_len, empty_hash, siblings.iter().cloned().chain(path)));
+    }
+    Ok(child)
+}
+
+impl InnerNode {
+    /// The sibling hashes are truncated as in the serialization
+    pub(crate) fn siblings(&self, branch: u8) -> Siblings {
+        self.radix.all_digits()
+            .filter(|i| *i != branch)
+            .filter_map(|i| self.map.get(&i).map(|node| (i, node.my_hash().truncated(self.hash_len))))
+            .collect()
+    }
+}
+
+impl Node {
+    fn prove(&self, key: &[u8], levels: &mut Vec<Siblings>) -> Result<Option<LeafContent>, Pruned> {
+        match self {
+            Node::Leaf(leaf) => Ok(Some(leaf.content())),
+            Node::InnerNode(inner) => {
+                levels.push(inner.siblings(key[0]));
+                match inner.map.get(&key[0]) {
+                    None => Ok(None),
+                    Some(node) => node.prove(&key[1..], levels),
+                }
+            },
+            Node::Pruned(hash) => Err(Pruned(hash.clone())),
+        }
+    }
+}
+
+/// Proofs for sorted keys, each walk restarts from the deepest inner node shared with the previous key
+struct Proofs<'a, const N: usize> {
+    tree: &'a Tree<N>,
+    keys: std::vec::IntoIter<Sha256Hash<N>>,
+    digits: Vec<u8>,
+    /// inner nodes on the path of the previous key, with the proof level of the branch taken
+    path: Vec<(&'a InnerNode, Siblings)>,
+}
+
+impl<'a, const N: usize> Iterator for Proofs<'a, N> {
+    type Item = (Sha256Hash<N>, Option<Proof>);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let key = self.keys.next()?;
+        let digits = self.tree.radix.digits(&key.0);
+        let common = digits.iter().zip(&self.digits).take_while(|(a, b)| a == b).count();
+        self.path.truncate(common);
+        self.digits = digits;
+
+        let mut node = match self.path.last() {
+            None => self.tree.root.as_ref(),
+            Some((inner, _)) => inner.map.get(&self.digits[self.path.len() - 1]).map(|node| &**node),
+        };
+        let leaf = loop {
+            match node {
+                None => break None,
+                Some(Node::Leaf(leaf)) => break Some(leaf),
+                Some(Node::InnerNode(inner)) => {
+                    let branch = self.digits[self.path.len()];
+                    self.path.push((inner, inner.siblings(branch)));
+                    node = inner.map.get(&branch).map(|node| &**node);
+                },
+                Some(Node::Pruned(_)) => panic!("{}", NO_PRUNED),
+            }
+        };
+        let proof = match leaf {
+            Some(leaf) if leaf.remaining_key[..] == self.digits[self.path.len()..] => Some(Proof {
+                radix: self.tree.radix,
+                hash_len: self.tree.hash_len,
+                empty_hash: self.tree.empty_hash.clone(),
+                levels: self.path.iter().map(|(_, siblings)| siblings.clone()).collect(),
+                leaf: Some(leaf.content()),
+            }),
+            _ => None,
+        };
+        Some((key, proof))
+    }
+}
+
+impl<const N: usize> Tree<N> {
+    /// Inclusion proofs of `keys` in key order, `None` for the keys not in the tree. Keys
+    /// sharing a prefix share the walk down the inner nodes of the prefix.
+    pub fn proofs_for<'a>(&'a self, keys: &'a [Sha256Hash<N>]) -> impl Iterator<Item = (Sha256Hash<N>, Option<Proof>)> + 'a {
+        let mut keys = keys.to_vec();
+        keys.sort();
+        Proofs {
+            tree: self,
+            keys: keys.into_iter(),
+            digits: Vec::new(),
+            path: Vec::new(),
+        }
+    }
+
+    /// Proving only reads the tree, an `Arc<Tree>` can be proven from many threads at once.
+    #[cfg(not(feature = "proof-cache"))]
+    pub fn prove<K: Borrow<[u8;N]>>(&self, key: K) -> Proof {
+        self.build_proof(&Sha256Hash(*key.borrow()))
+    }
+
+    /// Proofs are cached until the next mutation of the tree, the cache is locked only to
+    /// look up and store a proof so threads proving different keys don't wait on each other.
+    #[cfg(feature = "proof-cache")]
+    pub fn prove<K: Borrow<[u8;N]>>(&self, key: K) -> Proof {
+        let key = Sha256Hash(*key.borrow());
+        self.proof_cache.get_or_insert_with(&key, || self.build_proof(&key))
+    }
+
+    fn build_proof(&self, key: &Sha256Hash<N>) -> Proof {
+        #[cfg(feature = "tracing")]
+        self.trace_lookup("prove", &key.0);
+        let mut levels = Vec::new();
+        let leaf = match self.root {
+            None => None,
+            Some(ref root) => root.prove(&self.radix.digits(&key.0), &mut levels).expect(NO_PRUNED),
+        };
+        Proof {
+            radix: self.radix,
+            hash_len: self.hash_len,
+            empty_hash: self.empty_hash.clone(),
+            levels,
+            leaf,
+        }
+    }
+
+    /// Populated branches and hashes of the siblings of the path of `key` in the inner node at
+    /// `depth`, those in the proof level `depth`. `None` if the path has no inner node there.
+    pub fn siblings_at(&self, key: &Sha256Hash<N>, depth: usize) -> Option<Vec<(u8, Sha256Hash)>> {
+        let digits = self.radix.digits(&key.0);
+        let mut node = self.root.as_ref()?;
+        for digit in digits.get(..depth)? {
+            match node {
+                Node::InnerNode(inner) => node = inner.map.get(digit)?,
+                _ => return None,
+            }
+        }
+        match node {
+            Node::InnerNode(inner) => Some(inner.siblings(digits[depth])),
+            _ => None,
+        }
+    }
+
+    /// Write the proof for `key` while walking its path, the bytes are the same of
+    /// `prove(key).to_bytes()`. Returns whether the key is in the tree.
+    pub fn prove_to<W: Write>(&self, key: &Sha256Hash<N>, w: &mut W) -> io::Result<bool> {
+        #[cfg(feature = "tracing")]
+        self.trace_lookup("prove", &key.0);
+        write_radix(w, self.radix, self.hash_len, self.empty_hash.as_ref())?;
+        let digits = self.radix.digits(&key.0);
+        let mut rest = &digits[..];
+        let mut node = match self.root {
+            None => {
+                w.write_all(&[0x00])?;
+                return Ok(false);
+            },
+            Some(ref root) => root,
+        };
+        loop {
+            match node {
+                Node::Leaf(leaf) => {
+                    write_leaf(w, leaf)?;
+                    return Ok(leaf.remaining_key == rest);
+                },
+                Node::InnerNode(inner) => {
+                    write_level(w, &inner.siblings(rest[0]), self.hash_len)?;
+                    match inner.map.get(&rest[0]) {
+                        None => {
+                            w.write_all(&[0x00])?;
+                            return Ok(false);
+                        },
+                        Some(child) => node = child,
+                    }
+                    rest = &rest[1..];
+                },
+                Node::Pruned(_) => panic!("{}", NO_PRUNED),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ::*;
+
+    fn key(i: u32) -> Sha256Hash {
+        hash(&i.to_be_bytes())
+    }
+
+    fn tree(radix: Radix, n: u32) -> Tree {
+        let mut tree = Tree::with_radix(radix);
+        for i in 0..n {
+            tree.add(key(i), i.to_be_bytes().to_vec());
+        }
+        tree
+    }
+
+    #[test]
+    fn test_prove() {
+        for radix in [Radix::Byte, Radix::Nibble] {
+            let empty = Tree::with_radix(radix);
+            assert_eq!(verify_proof(&empty.options(), &empty.hash(), &key(0), &empty.prove(key(0))), Ok(None));
+
+            let tree = tree(radix, 100);
+            let root = tree.hash();
+            for i in 0..100 {
+                let proof = tree.prove(key(i));
+                assert_eq!(verify_proof(&tree.options(), &root, &key(i), &proof), Ok(Some(i.to_be_bytes().to_vec())));
+                assert_eq!(verify_proof(&tree.options(), &key(i), &key(i), &proof), Err(InvalidProof));
+            }
+            for i in 100..200 {
+                assert_eq!(tree.get(key(i)), None);
+                assert_eq!(verify_proof(&tree.options(), &root, &key(i), &tree.prove(key(i))), Ok(None));
+            }
+        }
+    }
+
+    #[test]
+    fn test_proof_omits_empty_slots() {
+        for radix in [Radix::Byte, Radix::Nibble] {
+            let tree = tree(radix, 100);
+            for i in 0..200 {
+                let proof = tree.prove(key(i));
+                let digits = radix.digits(&key(i).0);
+                let mut node = tree.root_node();
+                let mut len = 1;
+                for (siblings, digit) in proof.levels.iter().zip(&digits) {
+                    let inner = match node {
+                        Some(Node::InnerNode(inner)) => inner,
+                        other => panic!("{:?}", other),
+                    };
+                    let populated: Vec<_> = inner.children().map(|(branch, _)| branch).filter(|branch| branch != digit).collect();
+                    assert_eq!(siblings.iter().map(|(branch, _)| *branch).collect::<Vec<_>>(), populated);
+                    len += 1 + siblings.len().required_space() + siblings.len() * 33;
+                    node = inner.child(*digit);
+                }
+                let leaf = match proof.leaf {
+                    Some(ref leaf) => ::proof::content_leaf(leaf, &[]).serialize().len(),
+                    None => 1,
+                };
+                assert_eq!(proof.to_bytes().len(), len + leaf);
+            }
+        }
+    }
+
+    #[test]
+    fn test_combine() {
+        for radix in [Radix::Byte, Radix::Nibble] {
+            let tree = tree(radix, 100);
+            for i in 0..100 {
+                let proof = tree.prove(key(i));
+                let digits = radix.digits(&key(i).0);
+                let (remaining_key, value, _, _) = proof.leaf.clone().unwrap();
+                let mut hash = Leaf::new(remaining_key, value).my_hash();
+                for (siblings, digit) in proof.levels.iter().zip(&digits).rev() {
+                    let mut children = siblings.clone();
+                    children.push((*digit, hash));
+                    hash = combine(radix, &children);
+                }
+                assert_eq!(hash, tree.hash());
+            }
+        }
+
+        let (left, right) = (hash(&[0]), hash(&[1]));
+        let mut bytes = vec![0x01, 0x50, 0x20];
+        bytes.extend(&left.0);
+        bytes.push(0x20);
+        bytes.extend(&right.0);
+        bytes.extend(&[0u8; 14]);
+        assert_eq!(combine(Radix::Nibble, &[(1, right.clone()), (0, left.clone())]), hash(&bytes));
+    }
+
+    #[test]
+    fn test_bulk_verify() {
+        for radix in [Radix::Byte, Radix::Nibble] {
+            let mut tree = Tree::with_radix(radix);
+            let empty: [(Sha256Hash, Vec<u8>, Proof); 0] = [];
+            assert_eq!(bulk_verify(&tree.options(), &tree.hash(), &empty), Ok(()));
+            tree.add(hash(&[0]), vec![0]);
+            let single = [(hash(&[0]), vec![0], tree.prove(hash(&[0])))];
+            assert_eq!(bulk_verify(&tree.options(), &tree.hash(), &single), Ok(()));
+            for i in 1u8..100 {
+                tree.add(hash(&[i]), vec![i]);
+            }
+            let mut items: Vec<_> = (0u8..100).map(|i| (hash(&[i]), vec![i], tree.prove(hash(&[i])))).collect();
+            assert_eq!(bulk_verify(&tree.options(), &tree.hash(), &items), Ok(()));
+            assert_eq!(bulk_verify(&tree.options(), &hash(&[1]), &items), Err(0));
+
+            // a sibling of a path shared with already verified proofs
+            let mut tampered = items.clone();
+            let level = tampered[60].2.levels.len() - 1;
+            tampered[60].2.levels[level][0].1 = hash(&[0xff]);
+            assert_eq!(bulk_verify(&tree.options(), &tree.hash(), &tampered), Err(60));
+
+            items[40].1 = vec![0xff];
+            assert_eq!(bulk_verify(&tree.options(), &tree.hash(), &items), Err(40));
+            items[40].1 = vec![40];
+            items[50].0 = hash(&[0xff]);
+            assert_eq!(bulk_verify(&tree.options(), &tree.hash(), &items), Err(50));
+            let absent = tree.prove(hash(&[0xff]));
+            items[50] = (hash(&[0xff]), vec![], absent);
+            assert_eq!(bulk_verify(&tree.options(), &tree.hash(), &items), Err(50));
+        }
+    }
+
+    #[test]
+    fn test_siblings_at() {
+        let tree = tree(Radix::Byte, 100);
+        let key = key(7);
+        let siblings = tree.siblings_at(&key, 0).unwrap();
+        let root = match tree.root {
+            Some(Node::InnerNode(ref inner)) => inner,
+            _ => panic!("not an inner node"),
+        };
+        assert_eq!(siblings.len(), root.map.len() - 1);
+        for (branch, hash) in &siblings {
+            assert_ne!(*branch, key.0[0]);
+            assert_eq!(root.map[branch].my_hash(), *hash);
+        }
+        let proof = tree.prove(&key);
+        for (depth, level) in proof.levels.iter().enumerate() {
+            assert_eq!(tree.siblings_at(&key, depth).as_ref(), Some(level));
+        }
+        assert_eq!(tree.siblings_at(&key, proof.levels.len()), None);
+        assert_eq!(tree.siblings_at(&key, 40), None);
+        assert_eq!(Tree::<32>::default().siblings_at(&key, 0), None);
+    }
+
+    #[test]
+    fn test_prove_single_leaf_root() {
+        for radix in [Radix::Byte, Radix::Nibble] {
+            let tree = tree(radix, 1);
+            let proof = tree.prove(key(0));
+            assert!(proof.levels.is_empty());
+            assert_eq!(verify_proof(&tree.options(), &tree.hash(), &key(0), &proof), Ok(Some(0u32.to_be_bytes().to_vec())));
+            assert_eq!(verify_proof(&tree.options(), &tree.hash(), &key(1), &tree.prove(key(1))), Ok(None));
+        }
+    }
+
+    #[test]
+    fn test_proof_bound_to_key() {
+        let mut tree = Tree::default();
+        let mut a = [0u8;32];
+        let mut b = [0u8;32];
+        a[0] = 1;
+        b[0] = 2;
+        tree.add(Sha256Hash(a), vec![0x0a]);
+        tree.add(Sha256Hash([3u8;32]), vec![0x03]);
+        let root = tree.hash();
+        let proof = tree.prove(Sha256Hash(a));
+        assert_eq!(verify_proof(&tree.options(), &root, &Sha256Hash(a), &proof), Ok(Some(vec![0x0a])));
+
+        // b has the same remaining key as a at depth 1, but sits under another branch
+        assert_eq!(verify_proof(&tree.options(), &root, &Sha256Hash(b), &proof), Err(InvalidProof));
+
+        // c goes through a's branch, the proof shows a's leaf so c is absent
+        let mut c = a;
+        c[31] = 1;
+        assert_eq!(verify_proof(&tree.options(), &root, &Sha256Hash(c), &proof), Ok(None));
+
+        // claiming c's remaining key for a's value doesn't match the committed leaf
+        let mut forged = proof.clone();
+        forged.leaf = Some((c[1..].to_vec(), vec![0x0a], ValueHashing::Inline, LeafKey::Remaining));
+        assert_eq!(verify_proof(&tree.options(), &root, &Sha256Hash(c), &forged), Err(InvalidProof));
+    }
+
+    #[test]
+    fn test_proofs_for() {
+        for radix in [Radix::Byte, Radix::Nibble] {
+            let tree = tree(radix, 100);
+            let root = tree.hash();
+            let keys: Vec<Sha256Hash> = (0..200).rev().map(key).collect();
+            let mut count = 0;
+            let mut last = None;
+            for (key, proof) in tree.proofs_for(&keys) {
+                assert!(last < Some(key.clone()));
+                last = Some(key.clone());
+                match proof {
+                    Some(proof) => {
+                        assert_eq!(verify_proof(&tree.options(), &root, &key, &proof).unwrap(), tree.get(&key));
+                        assert_eq!(proof, tree.prove(&key));
+                        count += 1;
+                    },
+                    None => assert_eq!(tree.get(&key), None),
+                }
+            }
+            assert_eq!(count, 100);
+        }
+        assert_eq!(Tree::default().proofs_for(&[key(0)]).next(), Some((key(0), None)));
+    }
+
+    #[test]
+    fn test_prove_to() {
+        for radix in [Radix::Byte, Radix::Nibble] {
+            let mut tree = Tree::with_radix(radix);
+            for n in 0..30 {
+                for i in 0..32 {
+                    let mut streamed = Vec::new();
+                    let present = tree.prove_to(&key(i), &mut streamed).unwrap();
+                    assert_eq!(present, i < n);
+                    assert_eq!(streamed, tree.prove(key(i)).to_bytes());
+                }
+                tree.add(key(n), vec![n as u8]);
+            }
+        }
+    }
+
+    #[test]
+    fn test_truncated_hashes() {
+        for radix in [Radix::Byte, Radix::Nibble] {
+            let full = tree(radix, 200);
+            let mut truncated = Tree::builder().radix(radix).hash_len(16).build();
+            for i in 0..200 {
+                truncated.add(key(i), i.to_be_bytes().to_vec());
+            }
+            assert_ne!(truncated.hash(), full.hash());
+            assert!(truncated.serialized_len() < full.serialized_len());
+            let root = truncated.hash();
+            for i in 0..250 {
+                let proof = truncated.prove(key(i));
+                let bytes = proof.to_bytes();
+                assert!(bytes.len() < full.prove(key(i)).to_bytes().len());
+                let mut streamed = Vec::new();
+                truncated.prove_to(&key(i), &mut streamed).unwrap();
+                assert_eq!(streamed, bytes);
+                assert_eq!(verify_proof(&truncated.options(), &root, &key(i), &proof), Ok(truncated.get(key(i))));
+
+                // the verification hashes the siblings as truncated by the verifier's options
+                assert_eq!(verify_proof(&full.options(), &root, &key(i), &proof), Err(InvalidProof));
+            }
+
+            let mut tampered = truncated.prove(key(0));
+            let level = tampered.levels.iter_mut().find(|siblings| !siblings.is_empty()).unwrap();
+            level[0].1 .0[0] ^= 1;
+            assert_eq!(verify_proof(&truncated.options(), &root, &key(0), &tampered), Err(InvalidProof));
+
+            let update = truncated.prove_insert(&key(300), vec![1]);
+            let mut after = truncated.clone();
+            after.add(key(300), vec![1]);
+            assert!(verify_insert(&truncated.options(), &update, &key(300), &[1], &root, &after.hash()));
+
+            let (start, end) = (key(0).min(key(1)), key(0).max(key(1)));
+            assert!(verify_range(&truncated.options(), &truncated.prove_range(&start, &end), &start, &end, &root).is_some());
+
+            let partial = PartialTree::<32>::deserialize(&truncated.serialize()).unwrap();
+            assert_eq!(partial.hash(), root);
+        }
+    }
+
+    #[test]
+    fn test_empty_hash() {
+        let empty_hash = hash(b"empty");
+        for radix in [Radix::Byte, Radix::Nibble] {
+            for hash_len in [32, 16] {
+                let plain = Tree::builder().radix(radix).hash_len(hash_len);
+                let mut tree = plain.empty_hash(empty_hash.clone()).build();
+                let mut other = plain.empty_hash(hash(b"other")).build();
+                let mut plain = plain.build();
+                assert_eq!(tree.hash(), plain.hash());
+                for i in 0..200 {
+                    tree.add(key(i), i.to_be_bytes().to_vec());
+                    other.add(key(i), i.to_be_bytes().to_vec());
+                    plain.add(key(i), i.to_be_bytes().to_vec());
+                }
+                let root = tree.hash();
+                assert_ne!(root, plain.hash());
+                assert_ne!(root, other.hash());
+                assert_eq!(tree.verify_hashes(), Ok(()));
+                assert_eq!(tree.check_invariants(), Ok(()));
+
+                // every slot holds a hash
+                let inside = radix.width() * (1 + hash_len);
+                assert_eq!(tree.serialize().len(), 1 + inside.required_space() + inside);
+                assert_eq!(tree.serialized_len(), tree.serialize().len());
+                assert_eq!(hash(&tree.serialize()), root);
+
+                for i in 0..250 {
+                    let proof = tree.prove(key(i));
+                    let mut streamed = Vec::new();
+                    tree.prove_to(&key(i), &mut streamed).unwrap();
+                    assert_eq!(streamed, proof.to_bytes());
+                    assert_eq!(verify_proof(&tree.options(), &root, &key(i), &proof), Ok(tree.get(key(i))));
+                    assert_eq!(verify_proof(&plain.options(), &root, &key(i), &proof), Err(InvalidProof));
+                    assert_eq!(verify_proof(&other.options(), &root, &key(i), &proof), Err(InvalidProof));
+                }
+
+                let update = tree.prove_insert(&key(300), vec![1]);
+                let mut after = tree.clone();
+                after.add(key(300), vec![1]);
+                assert!(verify_insert(&tree.options(), &update, &key(300), &[1], &root, &after.hash()));
+
+                let (start, end) = (key(0).min(key(1)), key(0).max(key(1)));
+                assert!(verify_range(&tree.options(), &tree.prove_range(&start, &end), &start, &end, &root).is_some());
+
+                let mut partial = tree.prune_to_keys(&[key(300)]);
+                partial.add(&key(300), vec![1]).unwrap();
+                assert_eq!(partial.hash(), after.hash());
+
+                let mut batch: Tree = Tree::builder().radix(radix).hash_len(hash_len).empty_hash(empty_hash.clone()).build();
+                batch.insert_batch_sorted(&tree.to_entries());
+                assert_eq!(batch.hash(), root);
+            }
+        }
+        assert_eq!(Tree::<32>::with_empty_hash(empty_hash.clone()).hash(), Tree::<32>::new().hash());
+    }
+
+    #[test]
+    fn test_forged_empty_hash() {
+        // every slot of the root is populated, so a child passed off as the empty slots
+        // hash serializes the root as it is
+        let tree = tree(Radix::Nibble, 200);
+        let root = tree.hash();
+        let digit = Radix::Nibble.digits(&key(0).0)[0];
+        let inner = match tree.root_node() {
+            Some(Node::InnerNode(inner)) => inner,
+            other => panic!("{:?}", other),
+        };
+        assert_eq!(inner.children().count(), 16);
+        let child = inner.child(digit).unwrap().my_hash();
+        let mut forged = tree.prove(key(0));
+        forged.empty_hash = Some(child.clone());
+        forged.levels.truncate(1);
+        forged.leaf = None;
+
+        let prover_options = Tree::<32>::builder().radix(Radix::Nibble).empty_hash(child);
+        assert_eq!(verify_proof(&prover_options, &root, &key(0), &forged), Ok(None));
+        assert_eq!(verify_proof(&tree.options(), &root, &key(0), &forged), Err(InvalidProof));
+        let compact = Proof::from_compact(&forged.to_compact()).unwrap();
+        assert_eq!(compact.empty_hash, forged.empty_hash);
+        assert_eq!(verify_proof(&tree.options(), &root, &key(0), &compact), Err(InvalidProof));
+        let items = [(key(1), 1u32.to_be_bytes().to_vec(), tree.prove(key(1))), (key(0), vec![], forged)];
+        assert_eq!(bulk_verify(&tree.options(), &root, &items), Err(1));
+    }
+
+    #[test]
+    fn test_proof_size_by_radix() {
+        let byte = tree(Radix::Byte, 200);
+        let nibble = tree(Radix::Nibble, 200);
+        assert_ne!(byte.hash(), nibble.hash());
+
+        let mut byte_len = 0;
+        let mut nibble_len = 0;
+        for i in 0..200 {
+            byte_len += byte.prove(key(i)).to_bytes().len();
+            let proof = nibble.prove(key(i));
+            assert!(proof.levels.iter().all(|siblings| siblings.len() <= 15));
+            nibble_len += proof.to_bytes().len();
+        }
+        assert!(nibble_len < byte_len);
+    }
+
+    #[test]
+    fn test_prove_from_threads() {
+        use std::sync::Arc;
+        use std::thread;
+        for radix in [Radix::Byte, Radix::Nibble] {
+            let shared = Arc::new(tree(radix, 300));
+            let root = shared.hash();
+            let handles: Vec<_> = (0..8u32).map(|t| {
+                let shared = shared.clone();
+                let root = root.clone();
+                thread::spawn(move || {
+                    for i in (t..300).step_by(8) {
+                        let proof = shared.prove(key(i));
+                        assert_eq!(verify_proof(&shared.options(), &root, &key(i), &proof), Ok(Some(i.to_be_bytes().to_vec())));
+                    }
+                })
+            }).collect();
+            for handle in handles {
+                handle.join().unwrap();
+            }
+        }
+    }
+}
@@ -0,0 +1,293 @@
+use std::sync::Arc;
+use {ChildMap, Hashable, InnerNode, Leaf, Node, Radix, Sha256Hash, Tree, TreeBuilder, EMPTY_ROOT};
+
+/// Proves every entry with key between a start and an end key, both included.
+///
+/// It holds the part of the tree covering the range, with the subtrees lying outside
+/// of it pruned, so the verifier can tell that no entry has been left out.
+#[derive(Debug, Clone)]
+pub struct RangeProof {
+    radix: Radix,
+    root: Option<Node>,
+}
+
+/// Whether the keys starting with `path` can fall between `start` and `end`
+fn intersects(path: &[u8], start: &[u8], end: &[u8]) -> bool {
+    path >= &start[..path.len()] && path <= &end[..path.len()]
+}
+
+impl Node {
+    fn prove_range(&self, path: &mut Vec<u8>, start: &[u8], end: &[u8]) -> Node {
+        match self {
+            Node::InnerNode(inner) => {
+                let mut map = ChildMap::new();
+                for (branch, child) in &inner.map {
+                    path.push(*branch);
+                    let node = if intersects(path, start, end) {
+                        child.prove_range(path, start, end)
+                    } else {
+                        Node::Pruned(child.my_hash())
+                    };
+                    path.pop();
+                    map.insert(*branch, Arc::new(node));
+                }
+                let mut proof = InnerNode::new(map, inner.radix, inner.hash_len, inner.empty_hash.clone());
+                proof.hash = inner.hash.clone();
+                Node::InnerNode(proof)
+            },
+            _ => self.clone(),
+        }
+    }
+
+    /// Recompute the hash of a range proof node pushing the entries in the range, returns
+    /// `None` if a pruned subtree could hold keys of the range.
+    fn verify_range(&self, path: &mut Vec<u8>, start: &[u8], end: &[u8], radix: Radix, entries: &mut Vec<(Vec<u8>, Vec<u8>)>) -> Option<Sha256Hash> {
+        match self {
+            Node::Leaf(leaf) => {
+                let mut key = path.clone();
+                key.extend(&leaf.remaining_key);
+                if key.len() != start.len() {
+                    return None;
+                }
+                // the full key is completed from the path, it commits to the position
+                let full_key = leaf.full_key.as_ref().map(|_| key.clone());
+                let hash = Leaf::keyed(leaf.remaining_key.clone(), full_key, leaf.value.clone(), leaf.value_hashing()).my_hash();
+                if &key[..] >= start && &key[..] <= end {
+                    entries.push((key, leaf.value.clone()));
+                }
+                Some(hash)
+            },
+            Node::InnerNode(inner) => {
+                if inner.radix != radix || path.len() >= start.len() {
+                    return None;
+                }
+                let mut map = ChildMap::new();
+                for branch in inner.branches() {
+                    if usize::from(branch) >= radix.width() {
+                        return None;
+                    }
+                    path.push(branch);
+                    let hash = inner.map[&branch].verify_range(path, start, end, radix, entries);
+                    path.pop();
+                    map.insert(branch, Arc::new(Node::Pruned(hash?)));
+                }
+                let mut rebuilt = InnerNode::new(map, radix, inner.hash_len, inner.empty_hash.clone());
+                rebuilt.update();
+                Some(rebuilt.my_hash())
+            },
+            Node::Pruned(hash) if !intersects(path, start, end) => Some(hash.clone()),
+            Node::Pruned(_) => None,
+        }
+    }
+}
+
+impl<const N: usize> Tree<N> {
+    /// Prove the entries with key between `start` and `end`, both included.
+    pub fn prove_range(&self, start: &Sha256Hash<N>, end: &Sha256Hash<N>) -> RangeProof {
+        let start = self.radix.digits(&start.0);
+        let end = self.radix.digits(&end.0);
+        RangeProof {
+            radix: self.radix,
+            root: self.root.as_ref().map(|root| root.prove_range(&mut Vec::new(), &start, &end)),
+        }
+    }
+
+    /// The `n` entries with the lowest keys and the proof that no lower key is left out,
+    /// see `verify_first_n`. The proof covers all the keys up to the last entry, or all
+    /// the keys if the tree has fewer than `n` entries.
+    pub fn prove_first_n(&self, n: usize) -> (Vec<(Sha256Hash<N>, Vec<u8>)>, RangeProof) {
+        let entries: Vec<_> = self.to_entries().into_iter().take(n).collect();
+        let end = first_n_end(&entries, n);
+        let proof = self.prove_range(&Sha256Hash([0u8;N]), &end);
+        (entries, proof)
+    }
+}
+
+/// Last key covered by the proof of the first `n` entries
+fn first_n_end<const N: usize>(entries: &[(Sha256Hash<N>, Vec<u8>)], n: usize) -> Sha256Hash<N> {
+    match entries.last() {
+        _ if n == 0 => Sha256Hash([0u8;N]),
+        Some((key, _)) if entries.len() == n => key.clone(),
+        _ => Sha256Hash([0xffu8;N]),
+    }
+}
+
+/// Check that `entries` are the `n` entries with the lowest keys, in order, of the tree
+/// built with `options` with the given `root`, or all of its entries if it has fewer than `n`.
+pub fn verify_first_n<const N: usize>(options: &TreeBuilder<N>, proof: &RangeProof, entries: &[(Sha256Hash<N>, Vec<u8>)], n: usize, root: &Sha256Hash) -> bool {
+    if entries.len() > n {
+        return false;
+    }
+    let proven = verify_range(options, proof, &Sha256Hash([0u8;N]), &first_n_end(entries, n), root);
+    match proven {
+        // only the zero key can be in the range
+        Some(_) if n == 0 => entries.is_empty(),
+        Some(proven) => proven == entries,
+        None => false,
+    }
+}
+
+/// Check `proof` against `root` of a tree built with `options`, returning all the entries
+/// with key between `start` and `end`, both included, sorted by key. `None` if the proof is
+/// invalid. The entries are read from the proof as it is checked, there is nothing else to
+/// compare them with.
+pub fn verify_range<const N: usize>(options: &TreeBuilder<N>, proof: &RangeProof, start: &Sha256Hash<N>, end: &Sha256Hash<N>, root: &Sha256Hash) -> Option<Vec<(Sha256Hash<N>, Vec<u8>)>> {
+    if options.radix != proof.radix || !proof.root.as_ref().is_none_or(|root| options.builds_node(root)) {
+        return None;
+    }
+    let start = proof.radix.digits(&start.0);
+    let end = proof.radix.digits(&end.0);
+    let mut entries = Vec::new();
+    let hash = match proof.root {
+        None => EMPTY_ROOT,
+        Some(ref node) => node.verify_range(&mut Vec::new(), &start, &end, proof.radix, &mut entries)?,
+    };
+    if hash != *root {
+        return None;
+    }
+    Some(entries.into_iter().map(|(digits, value)| (proof.radix.key(&digits), value)).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use ::*;
+    use std::sync::Arc;
+
+    /// Drop the leaf of `key` from the proof, or replace it with its hash if `prune`
+    fn drop_leaf(proof: &mut RangeProof, key: &Sha256Hash, prune: bool) {
+        let mut node = proof.root.as_mut().unwrap();
+        for digit in proof.radix.digits(&key.0) {
+            let inner = match node {
+                Node::InnerNode(inner) => inner,
+                _ => panic!("not an inner node"),
+            };
+            if let Node::Leaf(_) = *inner.map[&digit] {
+                let leaf = inner.map.remove(&digit).unwrap();
+                if prune {
+                    inner.map.insert(digit, Arc::new(Node::Pruned(leaf.my_hash())));
+                }
+                return;
+            }
+            node = Arc::make_mut(inner.map.get_mut(&digit).unwrap());
+        }
+    }
+
+    /// Change the value of the leaf of `key` in the proof
+    fn tamper_value(proof: &mut RangeProof, key: &Sha256Hash) {
+        let mut node = proof.root.as_mut().unwrap();
+        for digit in proof.radix.digits(&key.0) {
+            node = match node {
+                Node::InnerNode(inner) => Arc::make_mut(inner.map.get_mut(&digit).unwrap()),
+                Node::Leaf(leaf) => {
+                    let mut value = leaf.value.clone();
+                    value[0] ^= 1;
+                    *leaf = Leaf::new(leaf.remaining_key.clone(), value);
+                    return;
+                },
+                Node::Pruned(_) => panic!("the leaf is pruned"),
+            };
+        }
+    }
+
+    #[test]
+    fn test_range_proof() {
+        for radix in [Radix::Byte, Radix::Nibble] {
+            let empty = Tree::with_radix(radix);
+            let (min, max) = (Sha256Hash([0u8;32]), Sha256Hash([0xffu8;32]));
+            assert_eq!(verify_range(&empty.options(), &empty.prove_range(&min, &max), &min, &max, &empty.hash()), Some(vec![]));
+
+            let mut tree = Tree::with_radix(radix);
+            for i in 0u32..100 {
+                tree.add(hash(&i.to_be_bytes()), vec![i as u8]);
+            }
+            let root = tree.hash();
+            let entries = tree.to_entries();
+            let (start, end) = (&entries[40].0, &entries[44].0);
+            let proof = tree.prove_range(start, end);
+            assert_eq!(verify_range(&tree.options(), &proof, start, end, &root), Some(entries[40..45].to_vec()));
+            assert_eq!(verify_range(&tree.options(), &proof, &entries[39].0, end, &root), None);
+            assert_eq!(verify_range(&tree.options(), &proof, start, end, &hash(&[0x00])), None);
+            assert_eq!(verify_range(&tree.options(), &tree.prove_range(&min, &max), &min, &max, &root), Some(entries.clone()));
+
+            let mut tampered = proof.clone();
+            tamper_value(&mut tampered, &entries[42].0);
+            assert_eq!(verify_range(&tree.options(), &tampered, start, end, &root), None);
+
+            let mut dropped = proof.clone();
+            drop_leaf(&mut dropped, &entries[42].0, false);
+            assert_eq!(verify_range(&tree.options(), &dropped, start, end, &root), None);
+
+            // the root still matches but the pruned subtree is inside the range
+            let mut hidden = proof.clone();
+            drop_leaf(&mut hidden, &entries[42].0, true);
+            assert_eq!(verify_range(&tree.options(), &hidden, start, end, &root), None);
+        }
+    }
+
+    #[test]
+    fn test_first_n() {
+        for radix in [Radix::Byte, Radix::Nibble] {
+            let mut tree = Tree::with_radix(radix);
+            let (first, proof) = tree.prove_first_n(5);
+            assert!(first.is_empty());
+            assert!(verify_first_n(&tree.options(), &proof, &first, 5, &tree.hash()));
+            for i in 0u32..100 {
+                tree.add(hash(&i.to_be_bytes()), vec![i as u8]);
+            }
+            let root = tree.hash();
+            let entries = tree.to_entries();
+
+            let (first, proof) = tree.prove_first_n(10);
+            assert_eq!(first, entries[..10].to_vec());
+            assert!(verify_first_n(&tree.options(), &proof, &first, 10, &root));
+            assert!(!verify_first_n(&tree.options(), &proof, &first, 10, &hash(&[0x00])));
+            assert!(!verify_first_n(&tree.options(), &proof, &first[..9], 10, &root));
+            assert!(!verify_first_n(&tree.options(), &proof, &first[1..], 10, &root));
+            let mut dropped = first.clone();
+            dropped.remove(4);
+            assert!(!verify_first_n(&tree.options(), &proof, &dropped, 10, &root));
+            let mut reordered = first.clone();
+            reordered.swap(2, 3);
+            assert!(!verify_first_n(&tree.options(), &proof, &reordered, 10, &root));
+            let mut skipping = first[..9].to_vec();
+            skipping.push(entries[10].clone());
+            assert!(!verify_first_n(&tree.options(), &proof, &skipping, 10, &root));
+
+            let (all, proof) = tree.prove_first_n(200);
+            assert_eq!(all, entries);
+            assert!(verify_first_n(&tree.options(), &proof, &all, 200, &root));
+            assert!(!verify_first_n(&tree.options(), &proof, &all[..99], 200, &root));
+
+            let (none, proof) = tree.prove_first_n(0);
+            assert!(none.is_empty());
+            assert!(verify_first_n(&tree.options(), &proof, &none, 0, &root));
+            assert!(!verify_first_n(&tree.options(), &proof, &entries[..1], 0, &root));
+        }
+    }
+
+    #[test]
+    fn test_forged_empty_hash() {
+        let mut tree = Tree::with_radix(Radix::Nibble);
+        for i in 0u32..200 {
+            tree.add(hash(&i.to_be_bytes()), vec![i as u8]);
+        }
+        let root = tree.hash();
+        let key = hash(&0u32.to_be_bytes());
+        let digit = Radix::Nibble.digits(&key.0)[0];
+        // the entries of the range are hidden in the full root, in the slot hashing as the
+        // empty slots
+        let mut forged = tree.prove_range(&key, &key);
+        let child = match forged.root {
+            Some(Node::InnerNode(ref mut inner)) => {
+                let child = inner.map.remove(&digit).unwrap().my_hash();
+                inner.empty_hash = Some(child.clone());
+                child
+            },
+            ref other => panic!("{:?}", other),
+        };
+
+        let prover_options = Tree::<32>::builder().radix(Radix::Nibble).empty_hash(child);
+        assert_eq!(verify_range(&prover_options, &forged, &key, &key, &root), Some(vec![]));
+        assert_eq!(verify_range(&tree.options(), &forged, &key, &key, &root), None);
+    }
+}
@@ -0,0 +1,181 @@
+use {hash, Sha256Hash, Tree};
+
+/// Root of the fixed depth sparse Merkle tree holding the entries of a `Tree`.
+///
+/// The sparse Merkle tree has one level for every bit of the key, most significant bit
+/// of the first byte at the top, a 0 bit going left. A key in the tree is a leaf hashing
+/// to `sha256(value)`, an absent key a leaf of 32 zero bytes, and a node hashes to
+/// `sha256(left || right)`, so the empty subtree of every height has a default hash.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SparseMerkleRoot(pub Sha256Hash);
+
+/// Sibling hashes on the path of a key in the sparse Merkle tree, one for every bit of the
+/// key listed bottom up, see `verify_sparse_merkle`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SparseMerkleProof {
+    pub siblings: Vec<Sha256Hash>,
+}
+
+/// Hashes of the empty subtrees by height, from the empty leaf to the empty tree of `bits` levels
+fn default_hashes(bits: usize) -> Vec<Sha256Hash> {
+    let mut defaults = vec![Sha256Hash([0u8;32])];
+    for height in 0..bits {
+        let below = &defaults[height];
+        defaults.push(node_hash(below, below));
+    }
+    defaults
+}
+
+fn node_hash(left: &Sha256Hash, right: &Sha256Hash) -> Sha256Hash {
+    let mut bytes = left.0.to_vec();
+    bytes.extend(&right.0);
+    hash(&bytes)
+}
+
+fn bit(key: &[u8], i: usize) -> bool {
+    key[i / 8] & (0x80 >> (i % 8)) != 0
+}
+
+/// Hash of the subtree at depth `depth` holding `entries`, sorted by key and sharing the
+/// first `depth` bits of the key
+fn subtree_hash<const N: usize>(entries: &[(Sha256Hash<N>, Vec<u8>)], depth: usize, defaults: &[Sha256Hash]) -> Sha256Hash {
+    let bits = N * 8;
+    if entries.is_empty() {
+        return defaults[bits - depth].clone();
+    }
+    if depth == bits {
+        return hash(&entries[0].1);
+    }
+    let split = entries.partition_point(|(key, _)| !bit(&key.0, depth));
+    let (left, right) = entries.split_at(split);
+    node_hash(&subtree_hash(left, depth + 1, defaults), &subtree_hash(right, depth + 1, defaults))
+}
+
+impl<const N: usize> Tree<N> {
+    /// Root of the sparse Merkle tree with the same entries, for verifiers expecting a fixed
+    /// depth tree of one bit per level instead of the path compressed trie.
+    pub fn to_sparse_merkle(&self) -> SparseMerkleRoot {
+        SparseMerkleRoot(subtree_hash(&self.to_entries(), 0, &default_hashes(N * 8)))
+    }
+
+    /// Proof of the value of `key`, or of its absence, against `to_sparse_merkle`
+    pub fn prove_sparse_merkle(&self, key: &Sha256Hash<N>) -> SparseMerkleProof {
+        let defaults = default_hashes(N * 8);
+        let entries = self.to_entries();
+        let mut entries = &entries[..];
+        let mut siblings = Vec::with_capacity(N * 8);
+        for depth in 0..N * 8 {
+            let split = entries.partition_point(|(other, _)| !bit(&other.0, depth));
+            let (left, right) = entries.split_at(split);
+            let (path, sibling) = if bit(&key.0, depth) { (right, left) } else { (left, right) };
+            siblings.push(subtree_hash(sibling, depth + 1, &defaults));
+            entries = path;
+        }
+        siblings.reverse();
+        SparseMerkleProof { siblings }
+    }
+}
+
+/// Check that `proof` shows `key` holding `value` in the sparse Merkle tree of `root`, or
+/// with `value` `None` that `key` is absent.
+pub fn verify_sparse_merkle<const N: usize>(root: &SparseMerkleRoot, key: &Sha256Hash<N>, value: Option<&[u8]>, proof: &SparseMerkleProof) -> bool {
+    if proof.siblings.len() != N * 8 {
+        return false;
+    }
+    let mut current = match value {
+        Some(value) => hash(value),
+        None => Sha256Hash([0u8;32]),
+    };
+    for (i, sibling) in proof.siblings.iter().enumerate() {
+        current = if bit(&key.0, N * 8 - 1 - i) {
+            node_hash(sibling, &current)
+        } else {
+            node_hash(&current, sibling)
+        };
+    }
+    current == root.0
+}
+
+#[cfg(test)]
+mod tests {
+    use ::*;
+    use std::collections::HashMap;
+
+    /// Straightforward sparse Merkle tree, hashing the populated nodes level by level bottom up
+    fn reference_root(entries: &[(Sha256Hash, Vec<u8>)]) -> Sha256Hash {
+        let mut empty = Sha256Hash([0u8;32]);
+        let mut level: HashMap<Vec<bool>, Sha256Hash> = entries.iter()
+            .map(|(key, value)| {
+                let bits = (0..256).map(|i| key.0[i / 8] >> (7 - i % 8) & 1 == 1).collect();
+                (bits, hash(value))
+            })
+            .collect();
+        for _ in 0..256 {
+            let mut parents = HashMap::new();
+            for path in level.keys() {
+                let parent = path[..path.len() - 1].to_vec();
+                if parents.contains_key(&parent) {
+                    continue;
+                }
+                let child = |bit: bool| {
+                    let mut path = parent.clone();
+                    path.push(bit);
+                    level.get(&path).cloned().unwrap_or_else(|| empty.clone())
+                };
+                let mut bytes = child(false).0.to_vec();
+                bytes.extend(&child(true).0);
+                parents.insert(parent, hash(&bytes));
+            }
+            let mut bytes = empty.0.to_vec();
+            bytes.extend(&empty.0);
+            empty = hash(&bytes);
+            level = parents;
+        }
+        level.get(&Vec::new()).cloned().unwrap_or(empty)
+    }
+
+    #[test]
+    fn test_sparse_merkle_root() {
+        for radix in [Radix::Byte, Radix::Nibble] {
+            let mut tree = Tree::with_radix(radix);
+            assert_eq!(tree.to_sparse_merkle().0, reference_root(&[]));
+            for i in 0u32..50 {
+                tree.add(hash(&i.to_be_bytes()), i.to_be_bytes().to_vec());
+                if i % 10 == 0 {
+                    assert_eq!(tree.to_sparse_merkle().0, reference_root(&tree.to_entries()));
+                }
+            }
+            // keys sharing all the bits but the last
+            tree.add(Sha256Hash([0u8;32]), vec![0]);
+            let mut key = [0u8;32];
+            key[31] = 1;
+            tree.add(Sha256Hash(key), vec![1]);
+            assert_eq!(tree.to_sparse_merkle().0, reference_root(&tree.to_entries()));
+        }
+    }
+
+    #[test]
+    fn test_sparse_merkle_proof() {
+        let mut tree = Tree::default();
+        let root = tree.to_sparse_merkle();
+        let absent = hash(&[0xff]);
+        assert!(verify_sparse_merkle(&root, &absent, None, &tree.prove_sparse_merkle(&absent)));
+        for i in 0u32..50 {
+            tree.add(hash(&i.to_be_bytes()), i.to_be_bytes().to_vec());
+        }
+        let root = tree.to_sparse_merkle();
+        for i in 0u32..50 {
+            let key = hash(&i.to_be_bytes());
+            let proof = tree.prove_sparse_merkle(&key);
+            assert_eq!(proof.siblings.len(), 256);
+            assert!(verify_sparse_merkle(&root, &key, Some(&i.to_be_bytes()), &proof));
+            assert!(!verify_sparse_merkle(&root, &key, Some(&[0]), &proof));
+            assert!(!verify_sparse_merkle(&root, &key, None, &proof));
+            assert!(!verify_sparse_merkle(&root, &absent, Some(&i.to_be_bytes()), &proof));
+        }
+        let proof = tree.prove_sparse_merkle(&absent);
+        assert!(verify_sparse_merkle(&root, &absent, None, &proof));
+        assert!(!verify_sparse_merkle(&root, &absent, Some(&[0]), &proof));
+        assert!(!verify_sparse_merkle(&root, &absent, None, &SparseMerkleProof { siblings: proof.siblings[1..].to_vec() }));
+    }
+}
@@ -0,0 +1,381 @@
+use std::collections::HashMap;
+use deserialize::{hashed_leaf, read_hashed_leaf};
+use {Deserializable, Leaf, Node, Proof, Serializable, Sha256Hash, Tree, EMPTY_ROOT, HASH_LEN};
+
+/// Keeps serialized nodes by their hash. Since nodes are content addressed,
+/// storing several versions of a tree shares their common subtrees and every
+/// stored root remains readable. The values of `ValueHashing::Digest` leaves are
+/// kept by their hash too.
+pub trait NodeStore {
+    fn get(&self, hash: &Sha256Hash) -> Option<Vec<u8>>;
+    fn insert(&mut self, hash: Sha256Hash, node: Vec<u8>);
+
+    /// Like `get`, for stores whose reads can fail, for example on I/O errors
+    fn try_get(&self, hash: &Sha256Hash) -> Result<Option<Vec<u8>>, StoreError> {
+        Ok(self.get(hash))
+    }
+
+    fn contains(&self, hash: &Sha256Hash) -> bool {
+        self.get(hash).is_some()
+    }
+}
+
+impl NodeStore for HashMap<Sha256Hash, Vec<u8>> {
+    fn get(&self, hash: &Sha256Hash) -> Option<Vec<u8>> {
+        HashMap::get(self, hash).cloned()
+    }
+
+    fn insert(&mut self, hash: Sha256Hash, node: Vec<u8>) {
+        HashMap::insert(self, hash, node);
+    }
+
+    fn contains(&self, hash: &Sha256Hash) -> bool {
+        self.contains_key(hash)
+    }
+}
+
+/// A node needed to build a proof is missing from the store, or doesn't match its hash or
+/// its depth
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ProofError {
+    MissingNode(Sha256Hash),
+}
+
+/// A read from a `NodeStore` failed
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum StoreError {
+    /// the store couldn't be read, with the error of the backend
+    Backend(String),
+    /// a node of the tree is missing from the store, or doesn't match its hash
+    MissingNode(Sha256Hash),
+}
+
+/// Load the node with the given hash, truncated to `hash_len` bytes like the hash in the
+/// parent, `depth` levels down
+fn try_load<S: NodeStore>(store: &S, hash: &Sha256Hash, hash_len: usize, depth: usize) -> Result<Node, StoreError> {
+    let missing = || StoreError::MissingNode(hash.clone());
+    let bytes = store.try_get(hash)?.ok_or_else(missing)?;
+    let node = match Node::deserialize(&bytes).map_err(|_| missing())? {
+        Node::Pruned(_) => {
+            // a leaf without its value, a wrong value wouldn't match the value hash
+            let (key, value_hash, leaf_key) = read_hashed_leaf(&bytes).map_err(|_| missing())?;
+            let value = store.try_get(&value_hash)?.ok_or_else(missing)?;
+            Node::Leaf(hashed_leaf(key, value, leaf_key))
+        },
+        node => node,
+    };
+    let node = node.at_depth(depth).ok_or_else(missing)?;
+    if node.my_hash().truncated(hash_len) == *hash {
+        Ok(node)
+    } else {
+        Err(missing())
+    }
+}
+
+impl Node {
+    /// Nodes are stored by their hash as referenced by the parent, truncated to `hash_len` bytes
+    fn persist<S: NodeStore>(&self, store: &mut S, hash_len: usize) {
+        let hash = self.my_hash().truncated(hash_len);
+        // children are stored before their parent, so the whole subtree is there
+        if store.contains(&hash) {
+            return;
+        }
+        match self {
+            Node::InnerNode(inner) => {
+                for child in inner.map.values() {
+                    child.persist(store, inner.hash_len);
+                }
+            },
+            Node::Leaf(Leaf { value_hash: Some(value_hash), value, .. }) => store.insert(value_hash.clone(), value.clone()),
+            _ => (),
+        }
+        store.insert(hash, self.serialize());
+    }
+}
+
+impl<const N: usize> Tree<N> {
+    /// Write every node of the tree missing from `store`.
+    pub fn persist<S: NodeStore>(&self, store: &mut S) {
+        if let Some(ref root) = self.root {
+            root.persist(store, HASH_LEN);
+        }
+    }
+
+    /// Load a node of a version of this tree. The empty slots of the inner nodes are
+    /// serialized like children with the empty hash, they are dropped from the map.
+    fn try_load<S: NodeStore>(&self, store: &S, hash: &Sha256Hash, hash_len: usize, depth: usize) -> Result<Node, StoreError> {
+        let mut node = try_load(store, hash, hash_len, depth)?;
+        if let (Node::InnerNode(ref mut inner), Some(ref empty_hash)) = (&mut node, &self.empty_hash) {
+            inner.map.retain(|_, child| child.my_hash() != *empty_hash);
+            inner.empty_hash = Some(empty_hash.clone());
+        }
+        Ok(node)
+    }
+
+    /// Look up `key` in the version of the tree with the given root, previously persisted in
+    /// `store` by a tree built with the options of this one. `None` also if a node is missing
+    /// from the store, see `try_get_at_root`.
+    pub fn get_at_root<S: NodeStore>(&self, store: &S, root: &Sha256Hash, key: &Sha256Hash<N>) -> Option<Vec<u8>> {
+        self.try_get_at_root(store, root, key).unwrap_or(None)
+    }
+
+    /// Like `get_at_root`, telling an absent key from a store that fails or misses a node
+    /// on the path of `key`. There's no `try_get` on the tree itself: the tree in memory
+    /// holds all its nodes and `get` can't fail, only the reads from a store can.
+    pub fn try_get_at_root<S: NodeStore>(&self, store: &S, root: &Sha256Hash, key: &Sha256Hash<N>) -> Result<Option<Vec<u8>>, StoreError> {
+        if *root == EMPTY_ROOT {
+            return Ok(None);
+        }
+        let mut node = self.try_load(store, root, HASH_LEN, 0)?;
+        let digits = self.radix.digits(&key.0);
+        let mut rest = &digits[..];
+        loop {
+            node = match node {
+                Node::Leaf(leaf) => return Ok(if leaf.remaining_key == rest { Some(leaf.value) } else { None }),
+                Node::InnerNode(inner) => {
+                    let child = match rest.first().and_then(|digit| inner.map.get(digit)) {
+                        None => return Ok(None),
+                        Some(child) => child.my_hash(),
+                    };
+                    rest = &rest[1..];
+                    self.try_load(store, &child, self.hash_len, digits.len() - rest.len())?
+                },
+                Node::Pruned(_) => unreachable!("deserialized nodes are loaded from the store"),
+            };
+        }
+    }
+
+    /// Proof of `key` in the version of the tree with the given root, previously persisted in
+    /// `store` by a tree built with the options of this one, like `prove` on that version.
+    /// Fails with the hash of the first node on the path that can't be loaded, for example
+    /// because it was evicted from the store, or that is deeper than the key. Named after the
+    /// root like `try_get_at_root`, the tree in memory proves its keys with `prove`, which
+    /// can't fail. The proof is returned rather than an `Option`, it proves absence too.
+    pub fn try_prove_at_root<S: NodeStore>(&self, store: &S, root: &Sha256Hash, key: &Sha256Hash<N>) -> Result<Proof, ProofError> {
+        let load = |hash: &Sha256Hash, hash_len, depth| self.try_load(store, hash, hash_len, depth).map_err(|_| ProofError::MissingNode(hash.clone()));
+        let mut proof = Proof {
+            radix: self.radix,
+            hash_len: self.hash_len,
+            empty_hash: self.empty_hash.clone(),
+            levels: Vec::new(),
+            leaf: None,
+        };
+        if *root == EMPTY_ROOT {
+            return Ok(proof);
+        }
+        let mut hash = root.clone();
+        let mut node = load(&hash, HASH_LEN, 0)?;
+        let digits = self.radix.digits(&key.0);
+        let mut rest = &digits[..];
+        loop {
+            node = match node {
+                Node::Leaf(leaf) => {
+                    proof.leaf = Some(leaf.content());
+                    return Ok(proof);
+                },
+                Node::InnerNode(inner) => {
+                    let digit = *rest.first().ok_or(ProofError::MissingNode(hash))?;
+                    proof.levels.push(inner.siblings(digit));
+                    hash = match inner.map.get(&digit) {
+                        None => return Ok(proof),
+                        Some(child) => child.my_hash(),
+                    };
+                    rest = &rest[1..];
+                    load(&hash, self.hash_len, digits.len() - rest.len())?
+                },
+                Node::Pruned(_) => unreachable!("deserialized nodes are loaded from the store"),
+            };
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ::*;
+    use std::collections::HashMap;
+
+    #[test]
+    fn test_get_at_root() {
+        let mut store = HashMap::new();
+        let a = Sha256Hash([0u8;32]);
+        let b = Sha256Hash([1u8;32]);
+        let c = Sha256Hash([2u8;32]);
+
+        let mut tree = Tree::default();
+        tree.add(&a, vec![0x01]);
+        tree.add(&b, vec![0x02]);
+        tree.persist(&mut store);
+        let old = tree.hash();
+
+        tree.add(&a, vec![0x03]);
+        tree.add(&c, vec![0x04]);
+        tree.persist(&mut store);
+        let new = tree.hash();
+        assert_eq!(store.len(), 6);
+
+        assert_eq!(tree.get_at_root(&store, &old, &a), Some(vec![0x01]));
+        assert_eq!(tree.get_at_root(&store, &old, &b), Some(vec![0x02]));
+        assert_eq!(tree.get_at_root(&store, &old, &c), None);
+        assert_eq!(tree.get_at_root(&store, &new, &a), Some(vec![0x03]));
+        assert_eq!(tree.get_at_root(&store, &new, &b), Some(vec![0x02]));
+        assert_eq!(tree.get_at_root(&store, &new, &c), Some(vec![0x04]));
+        assert_eq!(tree.get_at_root(&store, &a, &a), None);
+    }
+
+    #[test]
+    fn test_try_prove_at_root() {
+        let mut store = HashMap::new();
+        for radix in [Radix::Byte, Radix::Nibble] {
+            let mut tree = Tree::builder().radix(radix).hash_len(16).build();
+            let absent = hash(&[0xff]);
+            for i in 0u8..20 {
+                let prove = |key: &Sha256Hash| {
+                    let proof = tree.try_prove_at_root(&store, &tree.hash(), key).unwrap();
+                    assert_eq!(proof, tree.prove(key));
+                    verify_proof(&tree.options(), &tree.hash(), key, &proof)
+                };
+                for j in 0..i {
+                    assert_eq!(prove(&hash(&[j])), Ok(Some(vec![j])));
+                }
+                assert_eq!(prove(&absent), Ok(None));
+                tree.add(hash(&[i]), vec![i]);
+                tree.persist(&mut store);
+            }
+        }
+
+        let mut tree = Tree::default();
+        for i in 0u8..20 {
+            tree.add(hash(&[i]), vec![i]);
+        }
+        tree.persist(&mut store);
+        let key = hash(&[3]);
+        let proof = tree.prove(&key);
+        let leaf = proof.leaf.clone().unwrap();
+        let evicted = Leaf::new(leaf.0, leaf.1).my_hash();
+        store.remove(&evicted);
+        assert_eq!(tree.try_prove_at_root(&store, &tree.hash(), &key), Err(ProofError::MissingNode(evicted)));
+        assert_eq!(tree.try_prove_at_root(&store, &hash(&[1]), &key), Err(ProofError::MissingNode(hash(&[1]))));
+
+        // the inner nodes of a tree with longer keys go deeper than the key
+        let mut long: Tree<2> = Tree::default();
+        long.add([0, 1], vec![1]);
+        long.add([0, 2], vec![2]);
+        long.persist(&mut store);
+        let inner = match long.root_node() {
+            Some(Node::InnerNode(root)) => root.child(0).unwrap().my_hash(),
+            other => panic!("{:?}", other),
+        };
+        let short: Tree<1> = Tree::default();
+        assert_eq!(short.try_prove_at_root(&store, &long.hash(), &Sha256Hash([0])), Err(ProofError::MissingNode(inner)));
+    }
+
+    #[test]
+    fn test_at_root_empty_hash() {
+        let mut store = HashMap::new();
+        for radix in [Radix::Byte, Radix::Nibble] {
+            let mut tree = Tree::builder().radix(radix).hash_len(16).empty_hash(hash(&[0xee])).build();
+            let absent = hash(&[0xff]);
+            for i in 0u8..20 {
+                tree.add(hash(&[i]), vec![i]);
+                tree.persist(&mut store);
+                let root = tree.hash();
+                assert_eq!(tree.try_get_at_root(&store, &root, &hash(&[0])), Ok(Some(vec![0])));
+                assert_eq!(tree.try_get_at_root(&store, &root, &absent), Ok(None));
+                let proof = tree.try_prove_at_root(&store, &root, &absent).unwrap();
+                assert_eq!(proof, tree.prove(&absent));
+                assert_eq!(verify_proof(&tree.options(), &root, &absent, &proof), Ok(None));
+            }
+        }
+    }
+
+    /// Fails the reads of the nodes in `failing`
+    struct FailingStore {
+        nodes: HashMap<Sha256Hash, Vec<u8>>,
+        failing: Vec<Sha256Hash>,
+    }
+
+    impl NodeStore for FailingStore {
+        fn get(&self, hash: &Sha256Hash) -> Option<Vec<u8>> {
+            self.try_get(hash).ok()?
+        }
+
+        fn insert(&mut self, hash: Sha256Hash, node: Vec<u8>) {
+            self.nodes.insert(hash, node);
+        }
+
+        fn try_get(&self, hash: &Sha256Hash) -> Result<Option<Vec<u8>>, StoreError> {
+            if self.failing.contains(hash) {
+                return Err(StoreError::Backend("read failed".to_string()));
+            }
+            Ok(self.nodes.get(hash).cloned())
+        }
+    }
+
+    #[test]
+    fn test_try_get_at_root() {
+        let mut store = FailingStore { nodes: HashMap::new(), failing: Vec::new() };
+        let mut tree = Tree::default();
+        assert_eq!(tree.try_get_at_root(&store, &tree.hash(), &hash(&[0])), Ok(None));
+        for i in 0u8..20 {
+            tree.add(hash(&[i]), vec![i]);
+        }
+        tree.persist(&mut store);
+        let root = tree.hash();
+        assert_eq!(tree.try_get_at_root(&store, &root, &hash(&[3])), Ok(Some(vec![3])));
+        assert_eq!(tree.try_get_at_root(&store, &root, &hash(&[0xff])), Ok(None));
+        assert_eq!(tree.try_get_at_root(&store, &hash(&[1]), &hash(&[3])), Err(StoreError::MissingNode(hash(&[1]))));
+
+        let leaf = tree.leaf_metadata(&hash(&[3])).unwrap().leaf_hash;
+        store.failing.push(leaf.clone());
+        assert_eq!(tree.try_get_at_root(&store, &root, &hash(&[3])), Err(StoreError::Backend("read failed".to_string())));
+        assert_eq!(tree.get_at_root(&store, &root, &hash(&[3])), None);
+        assert_eq!(tree.try_get_at_root(&store, &root, &hash(&[4])), Ok(Some(vec![4])));
+
+        store.failing.clear();
+        store.nodes.remove(&leaf);
+        assert_eq!(tree.try_get_at_root(&store, &root, &hash(&[3])), Err(StoreError::MissingNode(leaf)));
+    }
+
+    #[test]
+    fn test_get_at_root_value_hashing() {
+        let mut store = HashMap::new();
+        let mut tree: Tree = Tree::builder().value_hashing(ValueHashing::Digest).build();
+        for i in 0u8..10 {
+            tree.add(Sha256Hash([i;32]), vec![i; 100]);
+        }
+        tree.persist(&mut store);
+        for i in 0u8..10 {
+            assert_eq!(tree.get_at_root(&store, &tree.hash(), &Sha256Hash([i;32])), Some(vec![i; 100]));
+        }
+
+        let value_hash = hash(&[3u8; 100]);
+        store.insert(value_hash, vec![4u8; 100]);
+        assert_eq!(tree.get_at_root(&store, &tree.hash(), &Sha256Hash([3u8;32])), None);
+    }
+
+    #[test]
+    fn test_get_at_root_truncated_hashes() {
+        let mut store = HashMap::new();
+        let mut tree: Tree = Tree::builder().hash_len(8).build();
+        for i in 0u8..10 {
+            tree.add(Sha256Hash([i;32]), vec![i]);
+            tree.add(hash(&[i]), vec![i]);
+        }
+        tree.persist(&mut store);
+        for i in 0u8..10 {
+            assert_eq!(tree.get_at_root(&store, &tree.hash(), &Sha256Hash([i;32])), Some(vec![i]));
+            assert_eq!(tree.get_at_root(&store, &tree.hash(), &hash(&[i])), Some(vec![i]));
+        }
+    }
+
+    #[test]
+    fn test_get_at_root_single_leaf() {
+        let mut store = HashMap::new();
+        for radix in [Radix::Byte, Radix::Nibble] {
+            let mut tree = Tree::with_radix(radix);
+            tree.add(Sha256Hash([7u8;32]), vec![0x07]);
+            tree.persist(&mut store);
+            assert_eq!(tree.get_at_root(&store, &tree.hash(), &Sha256Hash([7u8;32])), Some(vec![0x07]));
+        }
+    }
+}
@@ -0,0 +1,104 @@
+use {Node, Tree};
+
+/// Emit the depth reached removing the key with `digits` from the tree with `root` and
+/// whether the key is there, a pruned node on the path hides it
+pub(crate) fn trace_remove(root: Option<&Node>, digits: &[u8]) {
+    let (depth, leaf) = root.map_or((0, None), |root| root.walk(digits));
+    let found = leaf.is_some_and(|leaf| leaf.remaining_key[..] == digits[depth..]);
+    trace!(op = "remove", depth, found);
+}
+
+impl<const N: usize> Tree<N> {
+    /// Emit the depth reached by an insert of `key` and whether a leaf there is split into an
+    /// inner node, with the depth of the new inner node branching between the two keys
+    pub(crate) fn trace_insert(&self, key: &[u8;N]) {
+        let digits = self.radix.digits(key);
+        let (depth, leaf) = self.walk(&digits);
+        match leaf {
+            Some(leaf) if leaf.remaining_key[..] != digits[depth..] => {
+                let common = leaf.remaining_key.iter().zip(&digits[depth..]).take_while(|(a, b)| a == b).count();
+                trace!(op = "add", depth, split = true, split_depth = depth + common);
+            },
+            _ => trace!(op = "add", depth, split = false),
+        }
+    }
+
+    /// Emit the depth reached looking up `key` for `op` and whether the key is there
+    pub(crate) fn trace_lookup(&self, op: &'static str, key: &[u8;N]) {
+        let digits = self.radix.digits(key);
+        let (depth, leaf) = self.walk(&digits);
+        let found = leaf.is_some_and(|leaf| leaf.remaining_key[..] == digits[depth..]);
+        trace!(op, depth, found);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ::*;
+    use std::fmt;
+    use std::sync::{Arc, Mutex};
+    use tracing::{Event, Metadata, Subscriber};
+    use tracing::field::{Field, Visit};
+    use tracing::span::{Attributes, Id, Record};
+
+    /// Collects the fields of every event as `name=value` strings
+    #[derive(Clone, Default)]
+    struct Recorder(Arc<Mutex<Vec<String>>>);
+
+    struct Fields(Vec<String>);
+
+    impl Visit for Fields {
+        fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+            self.0.push(format!("{}={:?}", field.name(), value));
+        }
+    }
+
+    impl Subscriber for Recorder {
+        fn enabled(&self, _: &Metadata) -> bool { true }
+        fn new_span(&self, _: &Attributes) -> Id { Id::from_u64(1) }
+        fn record(&self, _: &Id, _: &Record) {}
+        fn record_follows_from(&self, _: &Id, _: &Id) {}
+        fn event(&self, event: &Event) {
+            let mut fields = Fields(Vec::new());
+            event.record(&mut fields);
+            self.0.lock().unwrap().push(fields.0.join(" "));
+        }
+        fn enter(&self, _: &Id) {}
+        fn exit(&self, _: &Id) {}
+    }
+
+    #[test]
+    fn test_trace_add() {
+        let recorder = Recorder::default();
+        tracing::subscriber::with_default(recorder.clone(), || {
+            let mut tree = Tree::default();
+            tree.add(Sha256Hash([0u8;32]), vec![0]);
+            tree.add(Sha256Hash([1u8;32]), vec![1]);
+            let mut key = [1u8;32];
+            key[1] = 2;
+            tree.add(Sha256Hash(key), vec![2]);
+            tree.add(Sha256Hash([2u8;32]), vec![3]);
+            key[5] = 3;
+            tree.add(Sha256Hash(key), vec![4]);
+            tree.get(Sha256Hash(key));
+            tree.prove(Sha256Hash([3u8;32]));
+            tree.apply_ops(&[Op::Remove(Sha256Hash([2u8;32]))]);
+
+            let mut partial = PartialTree::from(tree);
+            partial.remove(&Sha256Hash(key)).unwrap();
+            partial.remove(&Sha256Hash([3u8;32])).unwrap();
+        });
+        assert_eq!(*recorder.0.lock().unwrap(), vec![
+            "op=\"add\" depth=0 split=false",
+            "op=\"add\" depth=0 split=true split_depth=0",
+            "op=\"add\" depth=1 split=true split_depth=1",
+            "op=\"add\" depth=1 split=false",
+            "op=\"add\" depth=2 split=true split_depth=5",
+            "op=\"get\" depth=6 found=true",
+            "op=\"prove\" depth=1 found=false",
+            "op=\"remove\" depth=1 found=true",
+            "op=\"remove\" depth=6 found=true",
+            "op=\"remove\" depth=1 found=false",
+        ]);
+    }
+}
@@ -0,0 +1,127 @@
+use {verify_proof, Hashable, Leaf, LeafKey, Proof, Sha256Hash, Tree, TreeBuilder};
+use proof::{content_leaf, fold_levels, inner_hash};
+
+/// Proves that adding a key absent from a tree takes its root from one hash to another.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UpdateProof {
+    /// absence of the key before the insert, the path is the same after it
+    before: Proof,
+    new_root: Sha256Hash,
+}
+
+impl UpdateProof {
+    /// Root of the tree after the insert, as computed by the prover
+    pub fn new_root(&self) -> &Sha256Hash {
+        &self.new_root
+    }
+}
+
+impl<const N: usize> Tree<N> {
+    /// Prove the insert of `key`, absent from the tree, with `value`. The tree is not modified.
+    pub fn prove_insert(&self, key: &Sha256Hash<N>, value: Vec<u8>) -> UpdateProof {
+        let mut after = self.clone();
+        after.add(key, value);
+        UpdateProof {
+            before: self.prove(key),
+            new_root: after.hash(),
+        }
+    }
+}
+
+/// Check that `key` is absent under `old_root` of a tree built with `options` and that adding
+/// it with `value` gives `new_root`.
+pub fn verify_insert<const N: usize>(options: &TreeBuilder<N>, proof: &UpdateProof, key: &Sha256Hash<N>, value: &[u8], old_root: &Sha256Hash, new_root: &Sha256Hash) -> bool {
+    let before = &proof.before;
+    if verify_proof(options, old_root, key, before) != Ok(None) {
+        return false;
+    }
+    let digits = options.radix.digits(&key.0);
+    let empty_hash = options.truncated_empty_hash();
+    let depth = before.levels.len();
+    let remaining_key = &digits[depth..];
+    let full_key = match options.leaf_key {
+        LeafKey::Remaining => None,
+        LeafKey::Full => Some(digits.clone()),
+    };
+    let subtree = match before.leaf {
+        None => Leaf::keyed(remaining_key.to_vec(), full_key, value.to_vec(), options.value_hashing).my_hash(),
+        Some(ref leaf) => {
+            // the leaf on the path is split below the digits the keys share
+            let old_key = &leaf.0;
+            let common = old_key.iter().zip(remaining_key).take_while(|(a, b)| a == b).count();
+            let old = content_leaf(leaf, &digits[..depth]).moved(old_key[common + 1..].to_vec());
+            let new = Leaf::keyed(remaining_key[common + 1..].to_vec(), full_key, value.to_vec(), options.value_hashing);
+            let mut hash = inner_hash(options.radix, options.hash_len, empty_hash.as_ref(), vec![(old_key[common], old.my_hash()), (remaining_key[common], new.my_hash())]);
+            for branch in remaining_key[..common].iter().rev() {
+                hash = inner_hash(options.radix, options.hash_len, empty_hash.as_ref(), vec![(*branch, hash)]);
+            }
+            hash
+        },
+    };
+    fold_levels(options.radix, options.hash_len, empty_hash.as_ref(), &before.levels, &digits, Some(subtree)).ok() == Some(Some(new_root.clone()))
+}
+
+#[cfg(test)]
+mod tests {
+    use ::*;
+
+    #[test]
+    fn test_prove_insert() {
+        for radix in [Radix::Byte, Radix::Nibble] {
+            let mut tree = Tree::with_radix(radix);
+            for i in 0u32..100 {
+                let key = hash(&i.to_be_bytes());
+                let value = vec![i as u8];
+                let proof = tree.prove_insert(&key, value.clone());
+                let old_root = tree.hash();
+                tree.add(&key, value.clone());
+                assert_eq!(*proof.new_root(), tree.hash());
+                assert!(verify_insert(&tree.options(), &proof, &key, &value, &old_root, &tree.hash()));
+                assert!(!verify_insert(&tree.options(), &proof, &key, &[0xff], &old_root, &tree.hash()));
+                assert!(!verify_insert(&tree.options(), &proof, &key, &value, &tree.hash(), &tree.hash()));
+                assert!(!verify_insert(&tree.options(), &proof, &hash(&[0]), &value, &old_root, &tree.hash()));
+            }
+            let key = hash(&0u32.to_be_bytes());
+            let proof = tree.prove_insert(&key, vec![0xff]);
+            assert!(!verify_insert(&tree.options(), &proof, &key, &[0xff], &tree.hash(), proof.new_root()));
+        }
+
+        let mut tree: Tree = Tree::builder().value_hashing(ValueHashing::Digest).build();
+        let (a, mut b) = ([1u8;32], [1u8;32]);
+        tree.add(Sha256Hash(a), vec![1]);
+        b[5] = 2;
+        let proof = tree.prove_insert(&Sha256Hash(b), vec![2]);
+        let old_root = tree.hash();
+        tree.add(Sha256Hash(b), vec![2]);
+        assert!(verify_insert(&tree.options(), &proof, &Sha256Hash(b), &[2], &old_root, &tree.hash()));
+    }
+
+    #[test]
+    fn test_forged_empty_hash() {
+        let key = hash(&0u32.to_be_bytes());
+        let mut tree = Tree::with_radix(Radix::Nibble);
+        for i in 0..200u32 {
+            tree.add(hash(&i.to_be_bytes()), i.to_be_bytes().to_vec());
+        }
+        let old_root = tree.hash();
+        let digits = Radix::Nibble.digits(&key.0);
+        let child = match tree.root_node() {
+            Some(Node::InnerNode(inner)) => inner.child(digits[0]).unwrap().my_hash(),
+            other => panic!("{:?}", other),
+        };
+        // the present key is shown absent in the full root, in the slot hashing as the empty
+        // slots, and its subtree is replaced by the leaf of the insert
+        let mut before = tree.prove(&key);
+        before.empty_hash = Some(child.clone());
+        before.levels.truncate(1);
+        before.leaf = None;
+        let mut children = before.levels[0].clone();
+        children.push((digits[0], Leaf::new(digits[1..].to_vec(), vec![0xff]).my_hash()));
+        let new_root = combine(Radix::Nibble, &children);
+        let forged = UpdateProof { before, new_root: new_root.clone() };
+
+        let prover_options = Tree::<32>::builder().radix(Radix::Nibble).empty_hash(child);
+        assert!(verify_insert(&prover_options, &forged, &key, &[0xff], &old_root, &new_root));
+        assert!(!verify_insert(&tree.options(), &forged, &key, &[0xff], &old_root, &new_root));
+    }
+}
@@ -0,0 +1,334 @@
+use std::borrow::Borrow;
+use {hash, Hashable, Leaf, LeafKey, Node, Radix, Serializable, Sha256Hash, Tree};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum VerifyError {
+    /// stored hash of the first node not matching its serialization, see `Tree::verify_hashes`
+    InvalidHash(Sha256Hash),
+    /// the hashes are consistent but the root is not the expected one
+    RootMismatch(Sha256Hash),
+}
+
+/// Stored hash of a node on the path of `Tree::verified_get` not matching its serialization
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TamperError(pub Sha256Hash);
+
+impl Node {
+    /// Check the stored hashes bottom up, pruned nodes are trusted
+    pub(crate) fn verify_hashes(&self) -> Result<(), Sha256Hash> {
+        if let Node::InnerNode(inner) = self {
+            for branch in inner.branches() {
+                inner.map[&branch].verify_hashes()?;
+            }
+        }
+        self.verify_hash()
+    }
+
+    /// Check the shape of the subtree rooted `depth` levels down in a tree with keys of
+    /// `digits` digits, the hashes are checked by `verify_hashes` once none is stale
+    fn check_shape(&self, radix: Radix, hash_len: usize, empty_hash: &Option<Sha256Hash>, leaf_key: LeafKey, depth: usize, digits: usize) -> Result<(), String> {
+        match self {
+            Node::Leaf(leaf) if depth + leaf.remaining_key.len() != digits => {
+                Err(format!("leaf {:?} with {} remaining digits at depth {}", leaf.my_hash(), leaf.remaining_key.len(), depth))
+            },
+            Node::Leaf(leaf) if leaf.leaf_key() != leaf_key => {
+                Err(format!("leaf {:?} committing to the key as {:?}", leaf.my_hash(), leaf.leaf_key()))
+            },
+            Node::Leaf(Leaf { full_key: Some(full_key), remaining_key, .. }) if full_key.len() != digits || !full_key.ends_with(remaining_key) => {
+                Err(format!("leaf {:?} with full key {:?} not ending with {:?}", self.my_hash(), full_key, remaining_key))
+            },
+            Node::Leaf(_) => Ok(()),
+            Node::InnerNode(inner) => {
+                if inner.hash.is_none() {
+                    return Err(format!("stale inner node at depth {}", depth));
+                }
+                if inner.radix != radix {
+                    return Err(format!("inner node {:?} with radix {:?}", inner.hash, inner.radix));
+                }
+                if inner.hash_len != hash_len {
+                    return Err(format!("inner node {:?} with hash length {}", inner.hash, inner.hash_len));
+                }
+                if inner.empty_hash != *empty_hash {
+                    return Err(format!("inner node {:?} with empty hash {:?}", inner.hash, inner.empty_hash));
+                }
+                if depth == digits {
+                    return Err(format!("inner node {:?} below the last digit", inner.hash));
+                }
+                if inner.map.is_empty() {
+                    return Err(format!("inner node {:?} without children", inner.hash));
+                }
+                if inner.map.len() == 1 {
+                    if let Some(Node::Leaf(_)) = inner.map.values().next().map(|child| &**child) {
+                        return Err(format!("inner node {:?} with a single leaf", inner.hash));
+                    }
+                }
+                for branch in inner.branches() {
+                    if usize::from(branch) >= inner.radix.width() {
+                        return Err(format!("inner node {:?} with branch {}", inner.hash, branch));
+                    }
+                    inner.map[&branch].check_shape(radix, hash_len, empty_hash, leaf_key, depth + 1, digits)?;
+                }
+                Ok(())
+            },
+            Node::Pruned(hash) => Err(format!("pruned node {:?}", hash)),
+        }
+    }
+
+    /// Check the stored hash of this node only, trusting the stored hashes of the children
+    fn verify_hash(&self) -> Result<(), Sha256Hash> {
+        let expected = match self {
+            Node::Leaf(leaf) => Leaf::keyed(leaf.remaining_key.clone(), leaf.full_key.clone(), leaf.value.clone(), leaf.value_hashing()).my_hash(),
+            Node::InnerNode(inner) => hash(&inner.serialize()),
+            Node::Pruned(_) => return Ok(()),
+        };
+        if expected == self.my_hash() {
+            Ok(())
+        } else {
+            Err(self.my_hash())
+        }
+    }
+}
+
+impl<const N: usize> Tree<N> {
+    /// Recompute the hash of every node from its serialization, returning the stored hash
+    /// of the first node, children before parents, not matching it.
+    pub fn verify_hashes(&self) -> Result<(), Sha256Hash> {
+        match self.root {
+            None => Ok(()),
+            Some(ref root) => root.verify_hashes(),
+        }
+    }
+
+    /// Check the invariants every operation keeps: no pruned nodes, no inner node left
+    /// empty or with a single leaf, leaves completing the key digits, the radix and hash
+    /// length of the tree in every inner node and no stale or wrong hash.
+    pub(crate) fn check_invariants(&self) -> Result<(), String> {
+        let root = match self.root {
+            None => return Ok(()),
+            Some(ref root) => root,
+        };
+        root.check_shape(self.radix, self.hash_len, &self.empty_hash, self.leaf_key, 0, self.radix.digits(&[0u8;N]).len())?;
+        self.verify_hashes().map_err(|hash| format!("wrong hash {:?}", hash))
+    }
+
+    /// Panic if `check_invariants` fails, only in debug builds. With the `paranoid` feature
+    /// it runs after every mutation.
+    pub fn debug_assert_valid(&self) {
+        debug_assert_eq!(self.check_invariants(), Ok(()));
+    }
+
+    /// Like `get`, recomputing on the way down the hash of every node on the path of `key`.
+    /// The hash of an inner node covers the stored hashes of its children, so the value
+    /// returned is the one committed to by the root hash unless a stored hash was altered.
+    pub fn verified_get<K: Borrow<[u8;N]>>(&self, key: K) -> Result<Option<Vec<u8>>, TamperError> {
+        let digits = self.radix.digits(key.borrow());
+        let mut node = match self.root {
+            None => return Ok(None),
+            Some(ref root) => root,
+        };
+        let mut depth = 0;
+        loop {
+            node.verify_hash().map_err(TamperError)?;
+            match node {
+                Node::Leaf(leaf) if leaf.remaining_key[..] == digits[depth..] => return Ok(Some(self.decode_value(&leaf.value))),
+                Node::InnerNode(inner) => match inner.map.get(&digits[depth]) {
+                    None => return Ok(None),
+                    Some(child) => node = child,
+                },
+                _ => return Ok(None),
+            }
+            depth += 1;
+        }
+    }
+
+    /// Check that the tree, for example loaded from an untrusted source, is consistent and
+    /// commits to `commitment`.
+    pub fn verify_against(&self, commitment: &Sha256Hash) -> Result<(), VerifyError> {
+        self.verify_hashes().map_err(VerifyError::InvalidHash)?;
+        let root = self.hash();
+        if root == *commitment {
+            Ok(())
+        } else {
+            Err(VerifyError::RootMismatch(root))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ::*;
+    use std::sync::Arc;
+
+    fn child(node: &mut Node, branch: u8) -> &mut Node {
+        match node {
+            Node::InnerNode(inner) => Arc::make_mut(inner.map.get_mut(&branch).unwrap()),
+            _ => panic!("not an inner node"),
+        }
+    }
+
+    #[test]
+    fn test_verify_hashes() {
+        let mut tree = Tree::default();
+        assert_eq!(tree.verify_hashes(), Ok(()));
+        for i in 0u8..4 {
+            tree.add(Sha256Hash([i;32]), vec![i]);
+            let mut key = [i;32];
+            key[1] = 0xff;
+            tree.add(Sha256Hash(key), vec![i]);
+        }
+        assert_eq!(tree.verify_hashes(), Ok(()));
+
+        let mut corrupted = tree.clone();
+        let corrupt = Sha256Hash([0xffu8;32]);
+        match child(child(corrupted.root.as_mut().unwrap(), 2), 0xff) {
+            Node::Leaf(leaf) => leaf.hash = Some(corrupt.clone()),
+            _ => panic!("not a leaf"),
+        }
+        assert_eq!(corrupted.verify_hashes(), Err(corrupt.clone()));
+        assert_eq!(tree.verify_hashes(), Ok(()));
+
+        let mut corrupted = tree.clone();
+        match child(corrupted.root.as_mut().unwrap(), 1) {
+            Node::InnerNode(inner) => inner.hash = Some(corrupt.clone()),
+            _ => panic!("not an inner node"),
+        }
+        assert_eq!(corrupted.verify_hashes(), Err(corrupt));
+    }
+
+    #[test]
+    fn test_verify_against() {
+        let mut tree = Tree::default();
+        assert_eq!(tree.verify_against(&Tree::<32>::EMPTY_ROOT), Ok(()));
+        for i in 0u8..4 {
+            tree.add(Sha256Hash([i;32]), vec![i]);
+        }
+        let commitment = tree.hash();
+        assert_eq!(tree.verify_against(&commitment), Ok(()));
+
+        let mut tampered = tree.clone();
+        let stored = match child(tampered.root.as_mut().unwrap(), 2) {
+            Node::Leaf(leaf) => {
+                leaf.value = vec![0xff];
+                leaf.hash.clone().unwrap()
+            },
+            _ => panic!("not a leaf"),
+        };
+        assert_eq!(tampered.verify_against(&commitment), Err(VerifyError::InvalidHash(stored)));
+
+        tampered.map_values(|value| value.to_vec());
+        assert_eq!(tampered.verify_against(&commitment), Err(VerifyError::RootMismatch(tampered.hash())));
+    }
+
+    #[test]
+    fn test_verified_get() {
+        let mut tree = Tree::default();
+        assert_eq!(tree.verified_get(hash(&[0])), Ok(None));
+        for i in 0u8..4 {
+            tree.add(Sha256Hash([i;32]), vec![i]);
+            let mut key = [i;32];
+            key[1] = 0xff;
+            tree.add(Sha256Hash(key), vec![i]);
+        }
+        for i in 0u8..4 {
+            assert_eq!(tree.verified_get([i;32]), Ok(Some(vec![i])));
+        }
+        assert_eq!(tree.verified_get([9u8;32]), Ok(None));
+
+        let mut tampered = tree.clone();
+        let stored = match child(child(tampered.root.as_mut().unwrap(), 2), 0xff) {
+            Node::Leaf(leaf) => {
+                leaf.value = vec![0xff];
+                leaf.hash.clone().unwrap()
+            },
+            _ => panic!("not a leaf"),
+        };
+        let mut key = [2u8;32];
+        key[1] = 0xff;
+        assert_eq!(tampered.get(key), Some(vec![0xff]));
+        assert_eq!(tampered.verified_get(key), Err(TamperError(stored)));
+        // the other paths don't go through the tampered leaf
+        assert_eq!(tampered.verified_get([2u8;32]), Ok(Some(vec![2])));
+        assert_eq!(tampered.verified_get([1u8;32]), Ok(Some(vec![1])));
+
+        // altering the stored hash of the leaf too is caught by its parent
+        match child(child(tampered.root.as_mut().unwrap(), 2), 0xff) {
+            Node::Leaf(leaf) => leaf.hash = Some(hash(&leaf.serialize())),
+            _ => panic!("not a leaf"),
+        }
+        let parent = child(tampered.root.as_mut().unwrap(), 2).my_hash();
+        assert_eq!(tampered.verified_get(key), Err(TamperError(parent.clone())));
+        assert_eq!(tampered.verified_get([2u8;32]), Err(TamperError(parent)));
+    }
+
+    #[test]
+    fn test_check_invariants() {
+        for radix in [Radix::Byte, Radix::Nibble] {
+            let mut tree = Tree::with_radix(radix);
+            assert_eq!(tree.check_invariants(), Ok(()));
+            for i in 0u8..4 {
+                tree.add(Sha256Hash([i;32]), vec![i]);
+                let mut key = [i;32];
+                key[1] = 0xff;
+                tree.add(Sha256Hash(key), vec![i]);
+            }
+            assert_eq!(tree.check_invariants(), Ok(()));
+            tree.split_off(&[2, 0xff]);
+            assert_eq!(tree.check_invariants(), Ok(()));
+            tree.debug_assert_valid();
+        }
+
+        let mut tree = Tree::default();
+        for i in 0u8..4 {
+            tree.add(Sha256Hash([i;32]), vec![i]);
+            let mut key = [i;32];
+            key[1] = 0xff;
+            tree.add(Sha256Hash(key), vec![i]);
+        }
+        let mut single = tree.clone();
+        match child(single.root.as_mut().unwrap(), 1) {
+            Node::InnerNode(inner) => {
+                inner.map.retain(|branch, _| *branch == 1);
+                inner.update();
+            },
+            _ => panic!("not an inner node"),
+        }
+        assert!(single.check_invariants().unwrap_err().contains("single leaf"));
+
+        let mut stale = tree.clone();
+        match child(stale.root.as_mut().unwrap(), 1) {
+            Node::InnerNode(inner) => inner.hash = None,
+            _ => panic!("not an inner node"),
+        }
+        assert!(stale.check_invariants().unwrap_err().contains("stale"));
+
+        let mut short = tree.clone();
+        match child(child(short.root.as_mut().unwrap(), 2), 2) {
+            Node::Leaf(leaf) => { leaf.remaining_key.pop(); },
+            _ => panic!("not a leaf"),
+        }
+        assert!(short.check_invariants().unwrap_err().contains("remaining digits"));
+
+        let mut wrong = tree.clone();
+        match child(child(wrong.root.as_mut().unwrap(), 2), 2) {
+            Node::Leaf(leaf) => leaf.value = vec![0xff],
+            _ => panic!("not a leaf"),
+        }
+        assert!(wrong.check_invariants().unwrap_err().contains("wrong hash"));
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    #[should_panic]
+    fn test_debug_assert_valid() {
+        let mut tree = Tree::default();
+        for i in 0u8..4 {
+            tree.add(Sha256Hash([i;32]), vec![i]);
+        }
+        tree.debug_assert_valid();
+        match child(tree.root.as_mut().unwrap(), 2) {
+            Node::Leaf(leaf) => leaf.value = vec![0xff],
+            _ => panic!("not a leaf"),
+        }
+        tree.debug_assert_valid();
+    }
+}
@@ -0,0 +1,171 @@
+use std::borrow::Borrow;
+use std::sync::{Arc, RwLock};
+use {Sha256Hash, Tree};
+
+/// Owns the authoritative tree and publishes a snapshot of it after every update.
+///
+/// Updates are applied to the writer copy, which shares unchanged nodes with the
+/// published snapshots, so readers don't wait for the update itself. Only the swap
+/// of the published snapshot takes a lock, held by readers and the writer just for
+/// the time of cloning or replacing an `Arc`.
+pub struct TreeWriter<const N: usize = 32> {
+    tree: Tree<N>,
+    published: Arc<RwLock<Arc<Tree<N>>>>,
+}
+
+/// Cheap handle giving the last tree published by a `TreeWriter`
+#[derive(Clone)]
+pub struct TreeReader<const N: usize = 32> {
+    published: Arc<RwLock<Arc<Tree<N>>>>,
+}
+
+impl<const N: usize> TreeWriter<N> {
+    pub fn new(tree: Tree<N>) -> Self {
+        let published = Arc::new(RwLock::new(Arc::new(tree.clone())));
+        TreeWriter {
+            tree,
+            published,
+        }
+    }
+
+    pub fn reader(&self) -> TreeReader<N> {
+        TreeReader {
+            published: self.published.clone(),
+        }
+    }
+
+    /// Apply `f` to the tree and publish the result.
+    pub fn update<F: FnOnce(&mut Tree<N>)>(&mut self, f: F) {
+        f(&mut self.tree);
+        let snapshot = Arc::new(self.tree.clone());
+        *self.published.write().unwrap() = snapshot;
+    }
+}
+
+impl<const N: usize> TreeReader<N> {
+    /// The last published tree, unaffected by later updates.
+    pub fn snapshot(&self) -> Arc<Tree<N>> {
+        self.published.read().unwrap().clone()
+    }
+}
+
+/// A tree behind a lock, shared by reference between threads. Every call holds the lock
+/// for its duration and returns owned values, so writers wait for the readers and the
+/// other way around, for longer than with `TreeWriter`.
+#[derive(Debug, Default)]
+pub struct RwLockTree<const N: usize = 32> {
+    tree: RwLock<Tree<N>>,
+}
+
+impl<const N: usize> RwLockTree<N> {
+    pub fn new(tree: Tree<N>) -> Self {
+        RwLockTree {
+            tree: RwLock::new(tree),
+        }
+    }
+
+    pub fn get<K: Borrow<[u8;N]>>(&self, key: K) -> Option<Vec<u8>> {
+        self.tree.read().unwrap().get(key)
+    }
+
+    pub fn add<K: Borrow<[u8;N]>>(&self, key: K, value: Vec<u8>) {
+        self.tree.write().unwrap().add(key, value)
+    }
+
+    pub fn hash(&self) -> Sha256Hash {
+        self.tree.read().unwrap().hash()
+    }
+
+    pub fn into_inner(self) -> Tree<N> {
+        self.tree.into_inner().unwrap()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ::*;
+    use std::collections::HashMap;
+    use std::thread;
+
+    #[test]
+    fn test_readers_see_published_roots() {
+        let keys: Vec<Sha256Hash> = (0u32..200).map(|i| hash(&i.to_be_bytes())).collect();
+        let mut tree = Tree::default();
+        let mut roots = HashMap::new();
+        roots.insert(tree.hash(), 0);
+        for (i, key) in keys.iter().enumerate() {
+            tree.add(key, vec![i as u8]);
+            roots.insert(tree.hash(), i + 1);
+        }
+
+        let mut writer = TreeWriter::new(Tree::default());
+        let readers: Vec<_> = (0..4).map(|_| {
+            let reader = writer.reader();
+            let roots = roots.clone();
+            let keys = keys.clone();
+            thread::spawn(move || {
+                let mut last = 0;
+                while last < keys.len() {
+                    let snapshot = reader.snapshot();
+                    let seen = roots[&snapshot.hash()];
+                    assert!(seen >= last);
+                    if seen > 0 {
+                        assert!(snapshot.get(&keys[seen - 1]).is_some());
+                    }
+                    if seen < keys.len() {
+                        assert!(snapshot.get(&keys[seen]).is_none());
+                    }
+                    last = seen;
+                }
+            })
+        }).collect();
+        for (i, key) in keys.iter().enumerate() {
+            writer.update(|tree| tree.add(key, vec![i as u8]));
+        }
+        for reader in readers {
+            reader.join().unwrap();
+        }
+        assert_eq!(writer.reader().snapshot().hash(), tree.hash());
+    }
+
+    #[test]
+    fn test_rw_lock_tree() {
+        let keys: Vec<Sha256Hash> = (0u32..400).map(|i| hash(&i.to_be_bytes())).collect();
+        let shared = Arc::new(RwLockTree::default());
+        let writers: Vec<_> = (0..4).map(|w| {
+            let shared = shared.clone();
+            let keys = keys.clone();
+            thread::spawn(move || {
+                for (i, key) in keys.iter().enumerate().skip(w).step_by(4) {
+                    shared.add(key, vec![i as u8]);
+                    assert_eq!(shared.get(key), Some(vec![i as u8]));
+                }
+            })
+        }).collect();
+        let readers: Vec<_> = (0..4).map(|_| {
+            let shared = shared.clone();
+            let keys = keys.clone();
+            thread::spawn(move || {
+                for _ in 0..10 {
+                    for (i, key) in keys.iter().enumerate() {
+                        if let Some(value) = shared.get(key) {
+                            assert_eq!(value, vec![i as u8]);
+                        }
+                    }
+                    shared.hash();
+                }
+            })
+        }).collect();
+        for handle in writers.into_iter().chain(readers) {
+            handle.join().unwrap();
+        }
+
+        let mut tree = Tree::default();
+        for (i, key) in keys.iter().enumerate() {
+            tree.add(key, vec![i as u8]);
+        }
+        assert_eq!(shared.hash(), tree.hash());
+        let shared = Arc::try_unwrap(shared).unwrap();
+        assert_eq!(shared.into_inner().to_entries(), tree.to_entries());
+    }
+}